@@ -0,0 +1,247 @@
+use chrono::{DateTime, TimeZone, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+
+pub struct Database {
+    connection: Connection,
+}
+
+/// A key identified by the hash of an API key. The API key itself is never sent to the server.
+pub struct KeyRecord {
+    account: Option<String>,
+    subtoken: Option<String>,
+    subtoken_added_at: Option<DateTime<Utc>>,
+    subtoken_expires_at: Option<DateTime<Utc>>,
+    public: bool,
+    disabled: bool,
+}
+
+pub struct ShareRecord {
+    key_hash: String,
+    account: String,
+    added_at: DateTime<Utc>,
+}
+
+impl KeyRecord {
+    pub fn account(&self) -> Option<&str> {
+        self.account.as_deref()
+    }
+    pub fn subtoken(&self) -> Option<&str> {
+        self.subtoken.as_deref()
+    }
+    pub fn subtoken_added_at(&self) -> Option<DateTime<Utc>> {
+        self.subtoken_added_at
+    }
+    pub fn subtoken_expires_at(&self) -> Option<DateTime<Utc>> {
+        self.subtoken_expires_at
+    }
+    pub fn public(&self) -> bool {
+        self.public
+    }
+    pub fn disabled(&self) -> bool {
+        self.disabled
+    }
+    /// Whether the key has a subtoken that can be handed out to friends.
+    pub fn has_valid_subtoken(&self, now: DateTime<Utc>) -> bool {
+        self.subtoken.is_some() && self.subtoken_expires_at.map(|expires_at| expires_at > now).unwrap_or(false)
+    }
+
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(KeyRecord {
+            account: row.get("account")?,
+            subtoken: row.get("subtoken")?,
+            subtoken_added_at: row.get::<_, Option<i64>>("subtoken_added_at")?.map(from_timestamp),
+            subtoken_expires_at: row.get::<_, Option<i64>>("subtoken_expires_at")?.map(from_timestamp),
+            public: row.get("public")?,
+            disabled: row.get("disabled")?,
+        })
+    }
+}
+
+impl ShareRecord {
+    pub fn key_hash(&self) -> &str {
+        &self.key_hash
+    }
+    pub fn account(&self) -> &str {
+        &self.account
+    }
+    pub fn added_at(&self) -> DateTime<Utc> {
+        self.added_at
+    }
+
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(ShareRecord {
+            key_hash: row.get("key_hash")?,
+            account: row.get("account")?,
+            added_at: from_timestamp(row.get("added_at")?),
+        })
+    }
+}
+
+fn from_timestamp(timestamp: i64) -> DateTime<Utc> {
+    Utc.timestamp_opt(timestamp, 0).unwrap()
+}
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS keys (
+    key_hash TEXT PRIMARY KEY NOT NULL,
+    account TEXT,
+    subtoken TEXT,
+    subtoken_added_at INTEGER,
+    subtoken_expires_at INTEGER,
+    public INTEGER NOT NULL DEFAULT 0,
+    disabled INTEGER NOT NULL DEFAULT 0
+);
+CREATE INDEX IF NOT EXISTS keys_account ON keys (account);
+CREATE TABLE IF NOT EXISTS shares (
+    key_hash TEXT NOT NULL REFERENCES keys (key_hash),
+    account TEXT NOT NULL,
+    added_at INTEGER NOT NULL,
+    PRIMARY KEY (key_hash, account)
+);
+CREATE INDEX IF NOT EXISTS shares_account ON shares (account);
+";
+
+const KEY_COLUMNS: &str = "account, subtoken, subtoken_added_at, subtoken_expires_at, public, disabled";
+
+impl Database {
+    pub fn open(filename: &str) -> rusqlite::Result<Self> {
+        Self::with_connection(Connection::open(filename)?)
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> rusqlite::Result<Self> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(connection: Connection) -> rusqlite::Result<Self> {
+        connection.execute_batch(SCHEMA)?;
+        Ok(Database { connection })
+    }
+
+    pub fn key(&self, key_hash: &str) -> rusqlite::Result<Option<KeyRecord>> {
+        self.connection.query_row(
+            &format!("SELECT {} FROM keys WHERE key_hash = ?", KEY_COLUMNS),
+            params![key_hash],
+            KeyRecord::from_row,
+        ).optional()
+    }
+
+    pub fn keys_of_account(&self, account: &str) -> rusqlite::Result<Vec<KeyRecord>> {
+        let mut statement = self.connection.prepare(&format!("SELECT {} FROM keys WHERE account = ?", KEY_COLUMNS))?;
+        let keys = statement.query_map(params![account], KeyRecord::from_row)?;
+        keys.collect()
+    }
+
+    /// Shares of a key with other accounts.
+    pub fn shares_of_key(&self, key_hash: &str) -> rusqlite::Result<Vec<ShareRecord>> {
+        let mut statement = self.connection.prepare("SELECT key_hash, account, added_at FROM shares WHERE key_hash = ? ORDER BY added_at")?;
+        let shares = statement.query_map(params![key_hash], ShareRecord::from_row)?;
+        shares.collect()
+    }
+
+    /// Shares of other keys with an account.
+    pub fn shares_with_account(&self, account: &str) -> rusqlite::Result<Vec<ShareRecord>> {
+        let mut statement = self.connection.prepare("SELECT key_hash, account, added_at FROM shares WHERE account = ? ORDER BY added_at")?;
+        let shares = statement.query_map(params![account], ShareRecord::from_row)?;
+        shares.collect()
+    }
+
+    pub fn set_subtoken(&self, key_hash: &str, account: &str, subtoken: &str, added_at: DateTime<Utc>, expires_at: DateTime<Utc>) -> rusqlite::Result<()> {
+        self.ensure_key(key_hash)?;
+        self.connection.execute(
+            "UPDATE keys SET account = ?, subtoken = ?, subtoken_added_at = ?, subtoken_expires_at = ? WHERE key_hash = ?",
+            params![account, subtoken, added_at.timestamp(), expires_at.timestamp(), key_hash],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_public(&self, key_hash: &str, public: bool, disabled: bool) -> rusqlite::Result<()> {
+        self.ensure_key(key_hash)?;
+        self.connection.execute(
+            "UPDATE keys SET public = ?, disabled = ? WHERE key_hash = ?",
+            params![public, disabled, key_hash],
+        )?;
+        Ok(())
+    }
+
+    pub fn share(&self, key_hash: &str, account: &str, added_at: DateTime<Utc>) -> rusqlite::Result<()> {
+        self.ensure_key(key_hash)?;
+        // Sharing again keeps the original time.
+        self.connection.execute(
+            "INSERT OR IGNORE INTO shares (key_hash, account, added_at) VALUES (?, ?, ?)",
+            params![key_hash, account, added_at.timestamp()],
+        )?;
+        Ok(())
+    }
+
+    pub fn unshare(&self, key_hash: &str, account: &str) -> rusqlite::Result<()> {
+        self.connection.execute(
+            "DELETE FROM shares WHERE key_hash = ? AND account = ?",
+            params![key_hash, account],
+        )?;
+        Ok(())
+    }
+
+    /// Forgets subtokens that expired, they cannot be used by friends anymore.
+    /// The account is kept so that shares still work once a new subtoken is added.
+    pub fn remove_expired_subtokens(&self, now: DateTime<Utc>) -> rusqlite::Result<usize> {
+        self.connection.execute(
+            "UPDATE keys SET subtoken = NULL, subtoken_added_at = NULL, subtoken_expires_at = NULL WHERE subtoken_expires_at <= ?",
+            params![now.timestamp()],
+        )
+    }
+
+    fn ensure_key(&self, key_hash: &str) -> rusqlite::Result<()> {
+        self.connection.execute("INSERT OR IGNORE INTO keys (key_hash) VALUES (?)", params![key_hash])?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn subtoken_is_stored() {
+        let db = Database::open_in_memory().unwrap();
+        let now = from_timestamp(Utc::now().timestamp());
+        db.set_subtoken("hash", "Test.1234", "subtoken", now, now + Duration::days(365)).unwrap();
+
+        let key = db.key("hash").unwrap().unwrap();
+        assert_eq!(Some("Test.1234"), key.account());
+        assert_eq!(Some("subtoken"), key.subtoken());
+        assert_eq!(Some(now), key.subtoken_added_at());
+        assert!(key.has_valid_subtoken(now));
+        assert_eq!(1, db.keys_of_account("Test.1234").unwrap().len());
+        assert!(db.key("unknown").unwrap().is_none());
+    }
+
+    #[test]
+    fn expired_subtokens_are_removed() {
+        let db = Database::open_in_memory().unwrap();
+        let now = Utc::now();
+        db.set_subtoken("expired", "Test.1234", "first", now - Duration::days(400), now - Duration::days(1)).unwrap();
+        db.set_subtoken("valid", "Test.1234", "second", now, now + Duration::days(1)).unwrap();
+
+        assert!(!db.key("expired").unwrap().unwrap().has_valid_subtoken(now));
+        assert_eq!(1, db.remove_expired_subtokens(now).unwrap());
+        let expired = db.key("expired").unwrap().unwrap();
+        assert_eq!(None, expired.subtoken());
+        assert_eq!(Some("Test.1234"), expired.account());
+        assert_eq!(Some("second"), db.key("valid").unwrap().unwrap().subtoken());
+    }
+
+    #[test]
+    fn shares_are_added_once_and_removed() {
+        let db = Database::open_in_memory().unwrap();
+        let now = Utc::now();
+        db.share("hash", "Friend.1234", now).unwrap();
+        db.share("hash", "Friend.1234", now + Duration::days(1)).unwrap();
+        assert_eq!(1, db.shares_of_key("hash").unwrap().len());
+        assert_eq!("hash", db.shares_with_account("Friend.1234").unwrap()[0].key_hash());
+
+        db.unshare("hash", "Friend.1234").unwrap();
+        assert!(db.shares_of_key("hash").unwrap().is_empty());
+    }
+}
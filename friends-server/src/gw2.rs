@@ -0,0 +1,162 @@
+use std::fmt;
+use std::fmt::Formatter;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use ureq::{Agent, AgentBuilder};
+
+const USER_AGENT: &str = concat!("clears-friends-server/", env!("CARGO_PKG_VERSION"));
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+// The addon requests subtokens with these permissions.
+const REQUIRED_PERMISSIONS: [&str; 2] = ["account", "progression"];
+
+pub struct VerifiedSubtoken {
+    account: String,
+    expires_at: DateTime<Utc>,
+}
+
+impl VerifiedSubtoken {
+    pub fn new(account: String, expires_at: DateTime<Utc>) -> Self {
+        VerifiedSubtoken { account, expires_at }
+    }
+    pub fn account(&self) -> &str {
+        &self.account
+    }
+    pub fn expires_at(&self) -> DateTime<Utc> {
+        self.expires_at
+    }
+}
+
+#[derive(Debug)]
+pub enum VerificationError {
+    /// The subtoken cannot be used by friends.
+    InvalidSubtoken(String),
+    /// The GW2 API could not be reached or responded with an unexpected error.
+    ApiUnavailable(String),
+}
+
+impl fmt::Display for VerificationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            VerificationError::InvalidSubtoken(reason) => write!(f, "invalid subtoken: {}", reason),
+            VerificationError::ApiUnavailable(reason) => write!(f, "GW2 API unavailable: {}", reason),
+        }
+    }
+}
+
+/// Checks subtokens received from the addon and finds the account they belong to.
+pub trait SubtokenVerifier {
+    fn verify(&self, subtoken: &str) -> Result<VerifiedSubtoken, VerificationError>;
+}
+
+pub struct Gw2SubtokenVerifier {
+    url: String,
+    agent: Agent,
+}
+
+impl Gw2SubtokenVerifier {
+    pub fn new(url: String) -> Self {
+        let agent = AgentBuilder::new()
+            .timeout(REQUEST_TIMEOUT)
+            .build();
+        Gw2SubtokenVerifier { url, agent }
+    }
+
+    fn get<T: for<'de> Deserialize<'de>>(&self, path: &str, subtoken: &str) -> Result<T, VerificationError> {
+        let response = self.agent.get(&format!("{}{}", self.url, path))
+            .set("User-Agent", USER_AGENT)
+            .set("X-Schema-Version", "2021-05-20T00:00:00.000Z")
+            .set("Authorization", &format!("Bearer {}", subtoken))
+            .call();
+
+        match response {
+            Ok(response) => response.into_json()
+                .map_err(|e| VerificationError::ApiUnavailable(format!("invalid response: {}", e))),
+            Err(ureq::Error::Status(status, _)) if status == 400 || status == 401 || status == 403 => {
+                Err(VerificationError::InvalidSubtoken(format!("rejected by the GW2 API ({})", status)))
+            }
+            Err(e) => Err(VerificationError::ApiUnavailable(e.to_string())),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenInfo {
+    #[serde(rename = "type")]
+    token_type: String,
+    permissions: Vec<String>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+struct Account {
+    name: String,
+}
+
+impl SubtokenVerifier for Gw2SubtokenVerifier {
+    fn verify(&self, subtoken: &str) -> Result<VerifiedSubtoken, VerificationError> {
+        let info: TokenInfo = self.get("v2/tokeninfo", subtoken)?;
+        let expires_at = check_token_info(&info, Utc::now())?;
+        let account: Account = self.get("v2/account", subtoken)?;
+
+        Ok(VerifiedSubtoken::new(account.name, expires_at))
+    }
+}
+
+/// Returns the expiration time of a subtoken that is usable by friends.
+fn check_token_info(info: &TokenInfo, now: DateTime<Utc>) -> Result<DateTime<Utc>, VerificationError> {
+    // Full API keys are never stored, they do not expire and would give friends too much access.
+    if info.token_type != "Subtoken" {
+        return Err(VerificationError::InvalidSubtoken(format!("expected a subtoken, got {}", info.token_type)));
+    }
+    if let Some(missing) = REQUIRED_PERMISSIONS.iter().find(|permission| !info.permissions.iter().any(|x| x == *permission)) {
+        return Err(VerificationError::InvalidSubtoken(format!("missing the {} permission", missing)));
+    }
+    match info.expires_at {
+        Some(expires_at) if expires_at > now => Ok(expires_at),
+        Some(_) => Err(VerificationError::InvalidSubtoken("already expired".to_string())),
+        None => Err(VerificationError::InvalidSubtoken("missing expiration".to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(token_type: &str, permissions: &[&str], expires_at: Option<DateTime<Utc>>) -> TokenInfo {
+        TokenInfo {
+            token_type: token_type.to_string(),
+            permissions: permissions.iter().map(|x| x.to_string()).collect(),
+            expires_at,
+        }
+    }
+
+    #[test]
+    fn only_valid_subtokens_are_accepted() {
+        let now: DateTime<Utc> = "2021-05-21T08:35:00Z".parse().unwrap();
+        let expires_at: DateTime<Utc> = "2022-05-21T08:35:00Z".parse().unwrap();
+
+        assert_eq!(expires_at, check_token_info(&info("Subtoken", &["account", "progression"], Some(expires_at)), now).unwrap());
+        assert!(check_token_info(&info("APIKey", &["account", "progression"], None), now).is_err());
+        assert!(check_token_info(&info("Subtoken", &["account"], Some(expires_at)), now).is_err());
+        assert!(check_token_info(&info("Subtoken", &["account", "progression"], Some(now)), now).is_err());
+    }
+
+    #[test]
+    fn token_info_parsed_correctly() {
+        let json = r#"{
+  "id": "ABCDE02B-8888-FEBA-1234-DE98765C7DEF",
+  "name": "subtoken",
+  "permissions": ["account", "progression"],
+  "type": "Subtoken",
+  "expires_at": "2022-05-21T08:35:00.000Z",
+  "issued_at": "2021-05-21T08:35:00.000Z",
+  "urls": ["/v2/account"]
+}"#;
+        let info: TokenInfo = serde_json::from_str(json).unwrap();
+        assert_eq!("Subtoken", info.token_type);
+        assert_eq!(Some("2022-05-21T08:35:00Z".parse().unwrap()), info.expires_at);
+    }
+}
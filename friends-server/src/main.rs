@@ -0,0 +1,117 @@
+//! A friends server for the clears addon, which lets players share their subtokens with friends.
+//!
+//! Usage: `clears-friends-server [--bind ADDRESS] [--database FILE] [--threads COUNT]`
+
+use std::io::Read;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use chrono::Utc;
+use tiny_http::{Header, Request, Response};
+
+use crate::db::Database;
+use crate::gw2::Gw2SubtokenVerifier;
+use crate::server::{ApiRequest, FriendsServer};
+
+mod db;
+mod gw2;
+mod server;
+mod state;
+
+const GW2_API_URL: &str = "https://api.guildwars2.com/";
+const EXPIRED_SUBTOKEN_CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const MAX_BODY_SIZE: u64 = 16 * 1024;
+
+struct Config {
+    bind: String,
+    database: String,
+    threads: usize,
+}
+
+fn parse_args() -> Result<Config, String> {
+    let mut config = Config {
+        bind: "127.0.0.1:8080".to_string(),
+        database: "friends.sqlite".to_string(),
+        threads: 4,
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("Missing value for {}", arg));
+        match arg.as_str() {
+            "--bind" => config.bind = value()?,
+            "--database" => config.database = value()?,
+            "--threads" => config.threads = value()?.parse().map_err(|_| "Invalid thread count".to_string())?,
+            _ => return Err(format!("Unknown argument {}", arg)),
+        }
+    }
+    Ok(config)
+}
+
+fn main() {
+    let config = match parse_args() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("Usage: clears-friends-server [--bind ADDRESS] [--database FILE] [--threads COUNT]");
+            std::process::exit(2);
+        }
+    };
+
+    let db = Database::open(&config.database).expect("Failed to open the database");
+    let friends_server = Arc::new(FriendsServer::new(db, Gw2SubtokenVerifier::new(GW2_API_URL.to_string())));
+    let http_server = Arc::new(tiny_http::Server::http(&config.bind).expect("Failed to start the HTTP server"));
+    println!("Listening on {}", config.bind);
+
+    let cleanup_server = friends_server.clone();
+    thread::spawn(move || loop {
+        match cleanup_server.database().lock().unwrap().remove_expired_subtokens(Utc::now()) {
+            Ok(0) => {}
+            Ok(count) => println!("Removed {} expired subtokens", count),
+            Err(e) => eprintln!("Failed to remove expired subtokens: {}", e),
+        }
+        thread::sleep(EXPIRED_SUBTOKEN_CLEANUP_INTERVAL);
+    });
+
+    let workers: Vec<_> = (0..config.threads.max(1))
+        .map(|_| {
+            let http_server = http_server.clone();
+            let friends_server = friends_server.clone();
+            thread::spawn(move || {
+                for request in http_server.incoming_requests() {
+                    respond(request, &friends_server);
+                }
+            })
+        })
+        .collect();
+
+    for worker in workers {
+        let _ = worker.join();
+    }
+}
+
+fn respond(mut request: Request, friends_server: &FriendsServer<Gw2SubtokenVerifier>) {
+    let header = |name: &str| request.headers().iter()
+        .find(|header| header.field.as_str().as_str().eq_ignore_ascii_case(name))
+        .map(|header| header.value.as_str().to_string());
+    let auth_keys = header("x-auth-keys");
+    let public_friends = header("x-public-friends");
+
+    let mut body = String::new();
+    if request.as_reader().take(MAX_BODY_SIZE).read_to_string(&mut body).is_err() {
+        let _ = request.respond(Response::from_string("").with_status_code(400));
+        return;
+    }
+
+    let api_request = ApiRequest::new(&request.method().to_string(), request.url(), auth_keys, public_friends, &body);
+    let api_response = friends_server.handle(&api_request);
+
+    let content_type = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap();
+    let response = Response::from_string(api_response.body())
+        .with_status_code(api_response.status())
+        .with_header(content_type);
+    if let Err(e) = request.respond(response) {
+        eprintln!("Failed to send a response: {}", e);
+    }
+}
@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Formatter;
+use std::sync::Mutex;
+
+use chrono::Utc;
+use serde_json::json;
+
+use crate::db::Database;
+use crate::gw2::{SubtokenVerifier, VerificationError};
+use crate::state;
+
+/// Limits on what clients may send, to keep requests cheap to process.
+const MAX_KEYS_PER_REQUEST: usize = 50;
+const MAX_PUBLIC_FRIENDS_PER_REQUEST: usize = 500;
+const MAX_ACCOUNT_NAME_LENGTH: usize = 64;
+
+/// A request to the friends API, with everything already extracted from the HTTP request.
+pub struct ApiRequest {
+    method: String,
+    path: String,
+    auth_keys: Option<String>,
+    public_friends: Option<String>,
+    form: HashMap<String, String>,
+}
+
+pub struct ApiResponse {
+    status: u16,
+    body: String,
+}
+
+enum RequestError {
+    BadRequest(String),
+    Unauthorized,
+    NotFound,
+    ServiceUnavailable(String),
+    Database(rusqlite::Error),
+}
+
+pub struct FriendsServer<V: SubtokenVerifier> {
+    db: Mutex<Database>,
+    verifier: V,
+}
+
+impl ApiRequest {
+    /// `auth_keys` and `public_friends` are values of the `x-auth-keys` and `x-public-friends` headers.
+    pub fn new(method: &str, path: &str, auth_keys: Option<String>, public_friends: Option<String>, body: &str) -> Self {
+        ApiRequest {
+            method: method.to_string(),
+            path: path.split('?').next().unwrap_or("").to_string(),
+            auth_keys,
+            public_friends,
+            form: form_urlencoded::parse(body.as_bytes()).into_owned().collect(),
+        }
+    }
+
+    fn form_value(&self, name: &str) -> Result<&str, RequestError> {
+        self.form.get(name)
+            .map(|x| x.as_str())
+            .ok_or_else(|| RequestError::BadRequest(format!("missing {}", name)))
+    }
+
+    fn form_bool(&self, name: &str) -> Result<bool, RequestError> {
+        match self.form_value(name)? {
+            "true" => Ok(true),
+            "false" => Ok(false),
+            _ => Err(RequestError::BadRequest(format!("invalid {}", name))),
+        }
+    }
+}
+
+impl ApiResponse {
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            RequestError::BadRequest(reason) => write!(f, "{}", reason),
+            RequestError::Unauthorized => write!(f, "the key hash is not in x-auth-keys"),
+            RequestError::NotFound => write!(f, "not found"),
+            RequestError::ServiceUnavailable(reason) => write!(f, "{}", reason),
+            // Details are logged, but not sent to clients.
+            RequestError::Database(_) => write!(f, "internal error"),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for RequestError {
+    fn from(e: rusqlite::Error) -> Self {
+        RequestError::Database(e)
+    }
+}
+
+impl From<VerificationError> for RequestError {
+    fn from(e: VerificationError) -> Self {
+        match e {
+            VerificationError::InvalidSubtoken(_) => RequestError::BadRequest(e.to_string()),
+            VerificationError::ApiUnavailable(_) => RequestError::ServiceUnavailable(e.to_string()),
+        }
+    }
+}
+
+impl<V: SubtokenVerifier> FriendsServer<V> {
+    pub fn new(db: Database, verifier: V) -> Self {
+        FriendsServer { db: Mutex::new(db), verifier }
+    }
+
+    pub fn database(&self) -> &Mutex<Database> {
+        &self.db
+    }
+
+    pub fn handle(&self, request: &ApiRequest) -> ApiResponse {
+        match self.route(request) {
+            Ok(body) => ApiResponse { status: 200, body },
+            Err(e) => {
+                let status = match e {
+                    RequestError::BadRequest(_) => 400,
+                    RequestError::Unauthorized => 401,
+                    RequestError::NotFound => 404,
+                    RequestError::ServiceUnavailable(_) => 503,
+                    RequestError::Database(ref e) => {
+                        eprintln!("Database error: {}", e);
+                        500
+                    }
+                };
+                ApiResponse { status, body: json!({ "error": e.to_string() }).to_string() }
+            }
+        }
+    }
+
+    fn route(&self, request: &ApiRequest) -> Result<String, RequestError> {
+        let key_hashes = parse_key_hashes(request.auth_keys.as_deref())?;
+        let public_friends = parse_public_friends(request.public_friends.as_deref())?;
+
+        // Reverse proxies may serve the API from a subdirectory, so only the end of the path matters.
+        let path = request.path.trim_end_matches('/');
+        let endpoint = ["state", "key/add", "key/share", "key/unshare", "key/public"].iter()
+            .find(|endpoint| path == format!("/{}", endpoint) || path.ends_with(&format!("/{}", endpoint)))
+            .ok_or(RequestError::NotFound)?;
+
+        match (request.method.as_str(), *endpoint) {
+            ("GET", "state") => {}
+            ("POST", "key/add") => {
+                let key_hash = authorized_key_hash(request, &key_hashes)?;
+                // The GW2 API is asked before locking the database, it may take a while.
+                let verified = self.verifier.verify(request.form_value("subtoken")?)?;
+                self.db.lock().unwrap().set_subtoken(
+                    key_hash,
+                    verified.account(),
+                    request.form_value("subtoken")?,
+                    Utc::now(),
+                    verified.expires_at(),
+                )?;
+            }
+            ("POST", "key/share") => {
+                let key_hash = authorized_key_hash(request, &key_hashes)?;
+                let account = parse_account_name(request.form_value("account")?)?;
+                self.db.lock().unwrap().share(key_hash, account, Utc::now())?;
+            }
+            ("POST", "key/unshare") => {
+                let key_hash = authorized_key_hash(request, &key_hashes)?;
+                let account = parse_account_name(request.form_value("account")?)?;
+                self.db.lock().unwrap().unshare(key_hash, account)?;
+            }
+            ("POST", "key/public") => {
+                let key_hash = authorized_key_hash(request, &key_hashes)?;
+                let public = request.form_bool("public")?;
+                let disabled = request.form_bool("disabled")?;
+                self.db.lock().unwrap().set_public(key_hash, public, disabled)?;
+            }
+            _ => return Err(RequestError::NotFound),
+        }
+
+        // All endpoints respond with the current state.
+        let state = state::build_state(&self.db.lock().unwrap(), &key_hashes, &public_friends, Utc::now())?;
+        Ok(serde_json::to_string(&state).expect("Failed to serialize state"))
+    }
+}
+
+/// Clients prove they own a key by sending its hash in the `x-auth-keys` header.
+fn authorized_key_hash<'a>(request: &'a ApiRequest, key_hashes: &[String]) -> Result<&'a str, RequestError> {
+    let key_hash = request.form_value("key_hash")?;
+    if key_hashes.iter().any(|x| x == key_hash) {
+        Ok(key_hash)
+    } else {
+        Err(RequestError::Unauthorized)
+    }
+}
+
+fn parse_key_hashes(header: Option<&str>) -> Result<Vec<String>, RequestError> {
+    let hashes: Vec<String> = header.unwrap_or("").split(',')
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_string())
+        .collect();
+
+    if hashes.len() > MAX_KEYS_PER_REQUEST {
+        return Err(RequestError::BadRequest("too many keys".to_string()));
+    }
+    // Hashes are hex-encoded SHA-256 hashes of API keys.
+    if let Some(hash) = hashes.iter().find(|x| x.len() != 64 || !x.chars().all(|c| c.is_ascii_hexdigit())) {
+        return Err(RequestError::BadRequest(format!("invalid key hash {}", hash)));
+    }
+    Ok(hashes)
+}
+
+fn parse_public_friends(header: Option<&str>) -> Result<Vec<String>, RequestError> {
+    let accounts = header.unwrap_or("").split(',')
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .map(|x| parse_account_name(x).map(|x| x.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if accounts.len() > MAX_PUBLIC_FRIENDS_PER_REQUEST {
+        return Err(RequestError::BadRequest("too many public friends".to_string()));
+    }
+    Ok(accounts)
+}
+
+fn parse_account_name(account: &str) -> Result<&str, RequestError> {
+    if account.is_empty() || account.len() > MAX_ACCOUNT_NAME_LENGTH || account.contains(',') {
+        Err(RequestError::BadRequest(format!("invalid account name {}", account)))
+    } else {
+        Ok(account)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use serde_json::Value;
+    use crate::gw2::VerifiedSubtoken;
+
+    const OWN_HASH: &str = "01ba4719c80b6fe911b091a7c05124b64eeece964e09c058ef8f9805daca546b";
+    const FRIEND_HASH: &str = "27e6da1e6e2a277cbaf23df8213159a9862f6b4d0f6b82d72652a672e01d76f4";
+
+    /// Subtokens are accepted if they are named after the account.
+    struct FakeVerifier;
+
+    impl SubtokenVerifier for FakeVerifier {
+        fn verify(&self, subtoken: &str) -> Result<VerifiedSubtoken, VerificationError> {
+            match subtoken.strip_prefix("subtoken-") {
+                Some(account) => Ok(VerifiedSubtoken::new(account.to_string(), Utc::now() + Duration::days(365))),
+                None => Err(VerificationError::InvalidSubtoken("unknown".to_string())),
+            }
+        }
+    }
+
+    fn post(server: &FriendsServer<FakeVerifier>, path: &str, hash: &str, body: &str) -> ApiResponse {
+        server.handle(&ApiRequest::new("POST", path, Some(hash.to_string()), None, body))
+    }
+
+    fn json(response: &ApiResponse) -> Value {
+        serde_json::from_str(response.body()).unwrap()
+    }
+
+    #[test]
+    fn shared_subtoken_is_visible_to_friend() {
+        let server = FriendsServer::new(Database::open_in_memory().unwrap(), FakeVerifier);
+        let own_add = post(&server, "/key/add", OWN_HASH, &format!("key_hash={}&subtoken=subtoken-Own.1234", OWN_HASH));
+        assert_eq!(200, own_add.status());
+        assert_eq!("Own.1234", json(&own_add)["keys"][0]["account"]);
+
+        post(&server, "/key/add", FRIEND_HASH, &format!("key_hash={}&subtoken=subtoken-Friend.5678", FRIEND_HASH));
+        let share = post(&server, "/key/share", FRIEND_HASH, &format!("key_hash={}&account=Own.1234", FRIEND_HASH));
+        assert_eq!("Own.1234", json(&share)["keys"][0]["shared_to"][0]["account"]);
+
+        let state = server.handle(&ApiRequest::new("GET", "/state", Some(OWN_HASH.to_string()), None, ""));
+        let friend = &json(&state)["friends"][0];
+        assert_eq!("Friend.5678", friend["account"]);
+        assert_eq!("subtoken-Friend.5678", friend["subtoken"]["subtoken"]);
+        assert_eq!("Own.1234", friend["shared_with"][0]);
+    }
+
+    #[test]
+    fn changing_keys_requires_their_hash() {
+        let server = FriendsServer::new(Database::open_in_memory().unwrap(), FakeVerifier);
+        let response = post(&server, "/key/public", OWN_HASH, &format!("key_hash={}&public=true&disabled=false", FRIEND_HASH));
+        assert_eq!(401, response.status());
+    }
+
+    #[test]
+    fn invalid_requests_are_rejected() {
+        let server = FriendsServer::new(Database::open_in_memory().unwrap(), FakeVerifier);
+        assert_eq!(400, post(&server, "/key/add", OWN_HASH, &format!("key_hash={}&subtoken=nope", OWN_HASH)).status());
+        assert_eq!(400, post(&server, "/key/public", OWN_HASH, &format!("key_hash={}&public=yes&disabled=false", OWN_HASH)).status());
+        assert_eq!(400, server.handle(&ApiRequest::new("GET", "/state", Some("not-a-hash".to_string()), None, "")).status());
+        assert_eq!(404, server.handle(&ApiRequest::new("GET", "/unknown", None, None, "")).status());
+        assert_eq!(404, server.handle(&ApiRequest::new("GET", "/key/add", None, None, "")).status());
+    }
+
+    #[test]
+    fn api_can_be_served_from_subdirectory() {
+        let server = FriendsServer::new(Database::open_in_memory().unwrap(), FakeVerifier);
+        let response = server.handle(&ApiRequest::new("GET", "/clears/state", Some(OWN_HASH.to_string()), None, ""));
+        assert_eq!(200, response.status());
+        assert_eq!(OWN_HASH, json(&response)["keys"][0]["key_hash"]);
+    }
+}
@@ -0,0 +1,232 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::db::{Database, KeyRecord};
+
+// These have to match the structures the addon deserializes in `friends.rs`.
+
+#[derive(Serialize)]
+pub struct State {
+    keys: Vec<KeyState>,
+    friends: Vec<FriendState>,
+}
+
+#[derive(Serialize)]
+pub struct ShareState {
+    account: String,
+    added_at: DateTime<Utc>,
+    account_available: bool,
+}
+
+#[derive(Serialize)]
+pub struct KeyState {
+    key_hash: String,
+    shared_to: Vec<ShareState>,
+    subtoken_added_at: Option<DateTime<Utc>>,
+    subtoken_expires_at: Option<DateTime<Utc>>,
+    account: Option<String>,
+    public: bool,
+    disabled: bool,
+}
+
+#[derive(Serialize)]
+pub struct FriendState {
+    account: String,
+    subtoken: Option<SubtokenState>,
+    shared_with: Vec<String>,
+    known: bool,
+    public: bool,
+}
+
+#[derive(Serialize)]
+pub struct SubtokenState {
+    subtoken: String,
+    expires_at: DateTime<Utc>,
+}
+
+/// Builds the state as seen by the owner of the keys with the provided hashes.
+///
+/// Friends are accounts that shared a key with one of the owner's accounts,
+/// or requested public friends that made one of their keys public.
+pub fn build_state(db: &Database, key_hashes: &[String], public_friends: &[String], now: DateTime<Utc>) -> rusqlite::Result<State> {
+    let mut keys = Vec::new();
+    let mut own_accounts: Vec<String> = Vec::new();
+    for key_hash in key_hashes {
+        let key = db.key(key_hash)?;
+        let shared_to = db.shares_of_key(key_hash)?.into_iter()
+            .map(|share| Ok(ShareState {
+                account_available: account_available(db, share.account(), now)?,
+                account: share.account().to_string(),
+                added_at: share.added_at(),
+            }))
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        if let Some(account) = key.as_ref().and_then(|key| key.account()) {
+            if !own_accounts.iter().any(|x| x == account) {
+                own_accounts.push(account.to_string());
+            }
+        }
+
+        keys.push(KeyState {
+            key_hash: key_hash.clone(),
+            shared_to,
+            subtoken_added_at: key.as_ref().and_then(|key| key.subtoken_added_at()),
+            subtoken_expires_at: key.as_ref().and_then(|key| key.subtoken_expires_at()),
+            account: key.as_ref().and_then(|key| key.account().map(|x| x.to_string())),
+            public: key.as_ref().map(|key| key.public()).unwrap_or(false),
+            disabled: key.as_ref().map(|key| key.disabled()).unwrap_or(false),
+        });
+    }
+
+    let mut friends: Vec<FriendState> = Vec::new();
+    for own_account in &own_accounts {
+        for share in db.shares_with_account(own_account)? {
+            let key = match db.key(share.key_hash())? {
+                Some(key) => key,
+                None => continue,
+            };
+            let account = match key.account() {
+                Some(account) if usable_by_friends(&key, now) && !own_accounts.iter().any(|x| x == account) => account,
+                _ => continue,
+            };
+
+            let friend = friend_entry(&mut friends, account);
+            if !friend.shared_with.contains(own_account) {
+                friend.shared_with.push(own_account.clone());
+            }
+            offer_subtoken(friend, &key);
+        }
+    }
+
+    for account in public_friends {
+        if own_accounts.contains(account) {
+            continue;
+        }
+
+        let account_keys = db.keys_of_account(account)?;
+        let public_keys: Vec<&KeyRecord> = account_keys.iter()
+            .filter(|key| key.public() && usable_by_friends(key, now))
+            .collect();
+
+        let known = account_keys.iter().any(|key| key.has_valid_subtoken(now));
+        let friend = friend_entry(&mut friends, account);
+        friend.known = known;
+        for key in public_keys {
+            friend.public = true;
+            offer_subtoken(friend, key);
+        }
+    }
+
+    Ok(State { keys, friends })
+}
+
+fn usable_by_friends(key: &KeyRecord, now: DateTime<Utc>) -> bool {
+    !key.disabled() && key.has_valid_subtoken(now)
+}
+
+/// Whether there is a key for the account that friends can use.
+fn account_available(db: &Database, account: &str, now: DateTime<Utc>) -> rusqlite::Result<bool> {
+    Ok(db.keys_of_account(account)?.iter().any(|key| key.has_valid_subtoken(now)))
+}
+
+fn friend_entry<'a>(friends: &'a mut Vec<FriendState>, account: &str) -> &'a mut FriendState {
+    let index = match friends.iter().position(|x| x.account == account) {
+        Some(index) => index,
+        None => {
+            friends.push(FriendState {
+                account: account.to_string(),
+                subtoken: None,
+                shared_with: Vec::new(),
+                known: true,
+                public: false,
+            });
+            friends.len() - 1
+        }
+    };
+    &mut friends[index]
+}
+
+/// Friends may have multiple keys, the subtoken that is valid the longest is used.
+fn offer_subtoken(friend: &mut FriendState, key: &KeyRecord) {
+    if let (Some(subtoken), Some(expires_at)) = (key.subtoken(), key.subtoken_expires_at()) {
+        let better = friend.subtoken.as_ref().map(|current| current.expires_at < expires_at).unwrap_or(true);
+        if better {
+            friend.subtoken = Some(SubtokenState { subtoken: subtoken.to_string(), expires_at });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use serde_json::Value;
+
+    fn setup() -> (Database, DateTime<Utc>) {
+        let db = Database::open_in_memory().unwrap();
+        let now = Utc::now();
+        db.set_subtoken("own", "Own.1234", "own-subtoken", now, now + Duration::days(365)).unwrap();
+        db.set_subtoken("friend", "Friend.1234", "friend-subtoken", now, now + Duration::days(365)).unwrap();
+        (db, now)
+    }
+
+    #[test]
+    fn shared_key_is_a_friend() {
+        let (db, now) = setup();
+        db.share("friend", "Own.1234", now).unwrap();
+
+        let state = build_state(&db, &["own".to_string()], &[], now).unwrap();
+        assert_eq!(1, state.friends.len());
+        assert_eq!("Friend.1234", state.friends[0].account);
+        assert_eq!(vec!["Own.1234".to_string()], state.friends[0].shared_with);
+        assert_eq!("friend-subtoken", state.friends[0].subtoken.as_ref().unwrap().subtoken);
+        assert_eq!(Some("Own.1234".to_string()), state.keys[0].account);
+    }
+
+    #[test]
+    fn disabled_and_expired_keys_are_not_shared() {
+        let (db, now) = setup();
+        db.share("friend", "Own.1234", now).unwrap();
+        db.set_public("friend", false, true).unwrap();
+        assert!(build_state(&db, &["own".to_string()], &[], now).unwrap().friends.is_empty());
+
+        db.set_public("friend", false, false).unwrap();
+        let later = now + Duration::days(366);
+        assert!(build_state(&db, &["own".to_string()], &[], later).unwrap().friends.is_empty());
+    }
+
+    #[test]
+    fn public_friends_require_public_keys() {
+        let (db, now) = setup();
+        let public_friends = vec!["Friend.1234".to_string(), "Unknown.5678".to_string()];
+
+        let state = build_state(&db, &["own".to_string()], &public_friends, now).unwrap();
+        assert!(state.friends[0].subtoken.is_none());
+        assert!(state.friends[0].known);
+        assert!(!state.friends[1].known);
+
+        db.set_public("friend", true, false).unwrap();
+        let state = build_state(&db, &["own".to_string()], &public_friends, now).unwrap();
+        assert!(state.friends[0].public);
+        assert!(state.friends[0].subtoken.is_some());
+    }
+
+    #[test]
+    fn json_has_expected_shape() {
+        let (db, now) = setup();
+        db.share("own", "Friend.1234", now).unwrap();
+        let state = build_state(&db, &["own".to_string(), "new".to_string()], &[], now).unwrap();
+        let json: Value = serde_json::to_value(&state).unwrap();
+
+        let key = &json["keys"][0];
+        assert_eq!("own", key["key_hash"]);
+        assert_eq!("Friend.1234", key["shared_to"][0]["account"]);
+        assert_eq!(true, key["shared_to"][0]["account_available"]);
+        assert!(key["shared_to"][0]["added_at"].is_string());
+        assert!(key["subtoken_expires_at"].is_string());
+        assert_eq!(false, key["public"]);
+        assert!(json["keys"][1]["account"].is_null());
+        assert!(json["keys"][1]["subtoken_added_at"].is_null());
+        assert!(json["friends"].as_array().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,174 @@
+use serde::Deserialize;
+
+use crate::clears::{EncounterType, RaidEncounter, RaidWing};
+
+pub const STRIKE_MISSIONS_GROUP_ID: &str = "strike_missions";
+// Challenge motes are split in two groups to keep tables from getting too wide.
+pub const CHALLENGE_MOTES_W3_W5_GROUP_ID: &str = "challenge_motes_w3_w5";
+pub const CHALLENGE_MOTES_W6_W7_GROUP_ID: &str = "challenge_motes_w6_w7";
+
+/// An encounter that is not available in `/v2/account/raids`, and is instead
+/// tracked through achievement progress.
+struct AchievementEncounter {
+    encounter_id: &'static str,
+    achievement_id: u32,
+    /// Some achievements track multiple encounters, each of them being a separate bit.
+    bit: Option<u32>,
+}
+
+// Achievement ids are from /v2/achievements. The weekly strike mission achievement
+// is reset with the raid reset, and has one bit for each strike mission.
+const STRIKE_MISSIONS: [AchievementEncounter; 7] = [
+    AchievementEncounter { encounter_id: "shiverpeak_pass", achievement_id: 5245, bit: Some(0) },
+    AchievementEncounter { encounter_id: "voice_and_claw", achievement_id: 5245, bit: Some(1) },
+    AchievementEncounter { encounter_id: "fraenir_of_jormag", achievement_id: 5245, bit: Some(2) },
+    AchievementEncounter { encounter_id: "boneskinner", achievement_id: 5245, bit: Some(3) },
+    AchievementEncounter { encounter_id: "whisper_of_jormag", achievement_id: 5245, bit: Some(4) },
+    AchievementEncounter { encounter_id: "cold_war", achievement_id: 5245, bit: Some(5) },
+    AchievementEncounter { encounter_id: "forging_steel", achievement_id: 5245, bit: Some(6) },
+];
+
+// Challenge mote achievements are permanent, they show whether the account has ever
+// killed the boss with the challenge mote enabled.
+const CHALLENGE_MOTES_W3_W5: [AchievementEncounter; 7] = [
+    AchievementEncounter { encounter_id: "keep_construct_cm", achievement_id: 1794, bit: None },
+    AchievementEncounter { encounter_id: "cairn_cm", achievement_id: 2081, bit: None },
+    AchievementEncounter { encounter_id: "mursaat_overseer_cm", achievement_id: 2072, bit: None },
+    AchievementEncounter { encounter_id: "samarog_cm", achievement_id: 2085, bit: None },
+    AchievementEncounter { encounter_id: "deimos_cm", achievement_id: 2110, bit: None },
+    AchievementEncounter { encounter_id: "soulless_horror_cm", achievement_id: 2335, bit: None },
+    AchievementEncounter { encounter_id: "voice_in_the_void_cm", achievement_id: 2376, bit: None },
+];
+
+const CHALLENGE_MOTES_W6_W7: [AchievementEncounter; 6] = [
+    AchievementEncounter { encounter_id: "conjured_amalgamate_cm", achievement_id: 2636, bit: None },
+    AchievementEncounter { encounter_id: "twin_largos_cm", achievement_id: 2596, bit: None },
+    AchievementEncounter { encounter_id: "qadim_cm", achievement_id: 2637, bit: None },
+    AchievementEncounter { encounter_id: "adina_cm", achievement_id: 3019, bit: None },
+    AchievementEncounter { encounter_id: "sabir_cm", achievement_id: 3334, bit: None },
+    AchievementEncounter { encounter_id: "qadim_the_peerless_cm", achievement_id: 3287, bit: None },
+];
+
+#[derive(Deserialize)]
+pub struct AchievementProgress {
+    id: u32,
+    #[serde(default)]
+    done: bool,
+    bits: Option<Vec<u32>>,
+}
+
+impl AchievementProgress {
+    pub fn new(id: u32, done: bool, bits: Option<Vec<u32>>) -> Self {
+        AchievementProgress { id, done, bits }
+    }
+}
+
+fn all_encounters() -> impl Iterator<Item=&'static AchievementEncounter> {
+    STRIKE_MISSIONS.iter()
+        .chain(CHALLENGE_MOTES_W3_W5.iter())
+        .chain(CHALLENGE_MOTES_W6_W7.iter())
+}
+
+/// Ids of all achievements that have to be requested to find finished encounters.
+pub fn tracked_achievement_ids() -> Vec<u32> {
+    let mut ids: Vec<u32> = all_encounters()
+        .map(|x| x.achievement_id)
+        .collect();
+    ids.sort_unstable();
+    ids.dedup();
+    ids
+}
+
+/// Groups of weekly encounters shown after raid wings, these use the same structure as wings.
+pub fn encounter_groups() -> Vec<RaidWing> {
+    vec![
+        RaidWing::new(
+            STRIKE_MISSIONS_GROUP_ID.to_string(),
+            STRIKE_MISSIONS.iter()
+                .map(|x| RaidEncounter::new(x.encounter_id.to_string(), EncounterType::StrikeMission))
+                .collect(),
+        ),
+    ]
+}
+
+/// Groups of challenge motes, these are not reset weekly and are shown separately from raid wings.
+pub fn challenge_mote_groups() -> Vec<RaidWing> {
+    vec![
+        RaidWing::new(
+            CHALLENGE_MOTES_W3_W5_GROUP_ID.to_string(),
+            CHALLENGE_MOTES_W3_W5.iter()
+                .map(|x| RaidEncounter::new(x.encounter_id.to_string(), EncounterType::ChallengeMote))
+                .collect(),
+        ),
+        RaidWing::new(
+            CHALLENGE_MOTES_W6_W7_GROUP_ID.to_string(),
+            CHALLENGE_MOTES_W6_W7.iter()
+                .map(|x| RaidEncounter::new(x.encounter_id.to_string(), EncounterType::ChallengeMote))
+                .collect(),
+        ),
+    ]
+}
+
+/// Whether the group is added to raid wings from achievements instead of being a raid wing.
+pub fn is_achievement_group(wing: &RaidWing) -> bool {
+    wing.id() == STRIKE_MISSIONS_GROUP_ID
+}
+
+/// Ids of strike missions finished this week according to achievement progress.
+pub fn finished_strike_mission_ids(progress: &[AchievementProgress]) -> Vec<String> {
+    finished_ids(STRIKE_MISSIONS.iter(), progress)
+}
+
+/// Ids of challenge motes that the account has ever finished according to achievement progress.
+pub fn finished_challenge_mote_ids(progress: &[AchievementProgress]) -> Vec<String> {
+    finished_ids(CHALLENGE_MOTES_W3_W5.iter().chain(CHALLENGE_MOTES_W6_W7.iter()), progress)
+}
+
+fn finished_ids<'a>(encounters: impl Iterator<Item=&'a AchievementEncounter>, progress: &[AchievementProgress]) -> Vec<String> {
+    encounters
+        .filter(|encounter| {
+            progress.iter()
+                .filter(|achievement| achievement.id == encounter.achievement_id)
+                .any(|achievement| match encounter.bit {
+                    Some(bit) => achievement.done || achievement.bits.as_ref().map(|bits| bits.contains(&bit)).unwrap_or(false),
+                    None => achievement.done,
+                })
+        })
+        .map(|encounter| encounter.encounter_id.to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bits_mark_individual_encounters_finished() {
+        let progress = vec![AchievementProgress::new(5245, false, Some(vec![1, 4]))];
+        let finished = finished_strike_mission_ids(&progress);
+        assert_eq!(vec!["voice_and_claw".to_string(), "whisper_of_jormag".to_string()], finished);
+    }
+
+    #[test]
+    fn done_achievement_finishes_all_of_its_encounters() {
+        let progress = vec![AchievementProgress::new(5245, true, None)];
+        assert_eq!(STRIKE_MISSIONS.len(), finished_strike_mission_ids(&progress).len());
+        assert!(finished_challenge_mote_ids(&progress).is_empty());
+    }
+
+    #[test]
+    fn challenge_motes_require_done() {
+        let progress = vec![
+            AchievementProgress::new(1794, false, Some(vec![0])),
+            AchievementProgress::new(2081, true, None),
+        ];
+        assert_eq!(vec!["cairn_cm".to_string()], finished_challenge_mote_ids(&progress));
+        assert!(finished_strike_mission_ids(&progress).is_empty());
+    }
+
+    #[test]
+    fn tracked_ids_are_unique() {
+        let ids = tracked_achievement_ids();
+        assert_eq!(1 + CHALLENGE_MOTES_W3_W5.len() + CHALLENGE_MOTES_W6_W7.len(), ids.len());
+    }
+}
@@ -1,20 +1,79 @@
+use std::fmt;
+use std::fmt::Formatter;
+use std::sync::Arc;
+use std::time::Duration;
+
+use itertools::Itertools;
 use serde::{Deserialize, Serialize};
-use ureq::Error;
+use ureq::{Agent, AgentBuilder, Error, ErrorKind, Request, Response};
 
-use crate::clears::{EncounterType, FinishedEncountersStore, RaidEncounter, RaidWing, RaidWings};
+use crate::clears::{EncounterType, FinishedEncountersStore, RaidEncounter, RaidWing, RaidWings, WorldBosses};
 use chrono::{DateTime, Utc, TimeZone};
-use log::error;
+use crate::achievements::AchievementProgress;
+use crate::httpcache::{CachedResponse, HttpCache};
+use log::warn;
+use crate::ratelimit::RateLimiter;
 use crate::settings::{AccountData, TokenInfo, TokenType};
+use crate::shutdown::Stopped;
 
 const USER_AGENT: &str = concat!("arcdps-clears v", env!("CARGO_PKG_VERSION"));
 const LIVE_GW2_API_URL: &str = "https://api.guildwars2.com/";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const BODY_EXCERPT_MAX_CHARS: usize = 200;
+const RAIDS_PATH: &str = "v2/raids?ids=all";
+const DUNGEONS_PATH: &str = "v2/dungeons?ids=all";
+const WORLD_BOSSES_PATH: &str = "v2/worldbosses";
+
+/// Details of an unsuccessful response from the API.
+#[derive(Debug, Clone)]
+pub struct ErrorResponse {
+    pub status: u16,
+    /// The error text from the API if it was provided, otherwise an excerpt of the response body.
+    pub text: String,
+}
 
 #[derive(Debug)]
 pub enum ApiError {
-    UnknownError,
-    InvalidKey,
+    /// The connection failed before we received a response.
+    Transport(String),
+    /// The server did not respond in time.
+    Timeout,
+    /// The API key is not valid, or has been deleted.
+    InvalidKey(ErrorResponse),
+    /// The API key is valid, but lacks a permission (or subtoken url) required by the endpoint.
+    InsufficientPermissions(ErrorResponse),
+    NotFound(ErrorResponse),
+    TooManyRequests { retry_after: Option<Duration> },
+    /// The API is turned off, this typically happens during maintenance after game updates.
+    ApiDisabled(ErrorResponse),
+    ServerError(ErrorResponse),
+    UnexpectedStatus(ErrorResponse),
+    /// The response was received, but is missing something we need.
+    InvalidResponse(String),
     JsonDeserializationFailed(serde_json::Error),
-    TooManyRequests,
+    /// The request was not sent because the plugin is shutting down.
+    Stopped,
+}
+
+impl ApiError {
+    /// Whether the request may succeed if we try again later.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, ApiError::TooManyRequests { .. } | ApiError::ServerError(_) | ApiError::ApiDisabled(_) | ApiError::Timeout)
+    }
+
+    /// How long the server asked us to wait before retrying, if it did.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ApiError::TooManyRequests { retry_after } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+impl From<Stopped> for ApiError {
+    fn from(_: Stopped) -> Self {
+        ApiError::Stopped
+    }
 }
 
 impl From<serde_json::Error> for ApiError {
@@ -23,6 +82,85 @@ impl From<serde_json::Error> for ApiError {
     }
 }
 
+impl From<Error> for ApiError {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Status(status, response) => {
+                let retry_after = response.header("retry-after").map(|x| x.to_string());
+                let body = response.into_string().unwrap_or_default();
+                classify_status(status, retry_after.as_deref(), &body)
+            }
+            Error::Transport(transport) => {
+                let timed_out = transport.kind() == ErrorKind::Io && std::error::Error::source(&transport)
+                    .and_then(|source| source.downcast_ref::<std::io::Error>())
+                    .map(|source| matches!(source.kind(), std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock))
+                    .unwrap_or(false);
+
+                if timed_out {
+                    ApiError::Timeout
+                } else {
+                    ApiError::Transport(transport.to_string())
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::Transport(message) => write!(f, "connection failed: {}", message),
+            ApiError::Timeout => write!(f, "request timed out"),
+            ApiError::InvalidKey(response) => write!(f, "invalid key ({}: {})", response.status, response.text),
+            ApiError::InsufficientPermissions(response) => write!(f, "insufficient permissions ({}: {})", response.status, response.text),
+            ApiError::NotFound(response) => write!(f, "not found ({}: {})", response.status, response.text),
+            ApiError::TooManyRequests { retry_after: Some(retry_after) } => write!(f, "too many requests, rate limited (retry after {} s)", retry_after.as_secs()),
+            ApiError::TooManyRequests { retry_after: None } => write!(f, "too many requests, rate limited"),
+            ApiError::ApiDisabled(response) => write!(f, "API is disabled ({}: {})", response.status, response.text),
+            ApiError::ServerError(response) => write!(f, "server error ({}: {})", response.status, response.text),
+            ApiError::UnexpectedStatus(response) => write!(f, "unexpected status ({}: {})", response.status, response.text),
+            ApiError::InvalidResponse(message) => write!(f, "invalid response: {}", message),
+            ApiError::JsonDeserializationFailed(e) => write!(f, "json deserialization failed: {}", e),
+            ApiError::Stopped => write!(f, "shutting down"),
+        }
+    }
+}
+
+fn classify_status(status: u16, retry_after: Option<&str>, body: &str) -> ApiError {
+    // Errors from the API have a {"text": "..."} body, but we also want something
+    // useful if the response comes from a proxy or the server is badly broken.
+    #[derive(Deserialize)]
+    struct ErrorBody {
+        text: String,
+    }
+
+    let text = match serde_json::from_str::<ErrorBody>(body) {
+        Ok(error) => error.text,
+        Err(_) => body.trim().chars().take(BODY_EXCERPT_MAX_CHARS).collect(),
+    };
+    let lowercase_text = text.to_lowercase();
+    let response = ErrorResponse { status, text };
+
+    match status {
+        401 => ApiError::InvalidKey(response),
+        // The API has used 400 for invalid keys in the past.
+        400 if lowercase_text.contains("invalid key") || lowercase_text.contains("invalid access token") => ApiError::InvalidKey(response),
+        403 => ApiError::InsufficientPermissions(response),
+        404 => ApiError::NotFound(response),
+        429 => ApiError::TooManyRequests {
+            retry_after: retry_after.and_then(|x| x.trim().parse().ok()).map(Duration::from_secs)
+        },
+        503 if lowercase_text.contains("disabled") || lowercase_text.contains("not active") => ApiError::ApiDisabled(response),
+        500..=599 => ApiError::ServerError(response),
+        _ => ApiError::UnexpectedStatus(response),
+    }
+}
+
+fn read_body(response: Response) -> Result<String, ApiError> {
+    response.into_string()
+        .map_err(|e| ApiError::InvalidResponse(format!("failed to read body: {}", e)))
+}
+
 fn parse_raids(json: &str) -> Result<RaidWings, serde_json::Error> {
     // We do not care about individual raids, we extract wings from them and only work with wings.
     // For this reason, we only define this struct locally.
@@ -37,6 +175,37 @@ fn parse_raids(json: &str) -> Result<RaidWings, serde_json::Error> {
     ))
 }
 
+fn parse_dungeons(json: &str) -> Result<RaidWings, serde_json::Error> {
+    // Dungeons are represented in the same way as raid wings, with paths as their encounters.
+    #[derive(Deserialize)]
+    struct Dungeon {
+        id: String,
+        paths: Vec<DungeonPath>,
+    }
+    #[derive(Deserialize)]
+    struct DungeonPath {
+        id: String,
+    }
+    let dungeons: Vec<Dungeon> = serde_json::from_str(json)?;
+    Ok(RaidWings::new(
+        dungeons.into_iter()
+            .map(|dungeon| RaidWing::new(
+                dungeon.id,
+                dungeon.paths.into_iter()
+                    .map(|path| RaidEncounter::new(path.id, EncounterType::DungeonPath))
+                    .collect(),
+            ))
+            .collect(),
+    ))
+}
+
+fn parse_world_bosses(json: &str) -> Result<WorldBosses, serde_json::Error> {
+    let ids: Vec<String> = serde_json::from_str(json)?;
+    Ok(WorldBosses::new(
+        ids.into_iter().map(|id| RaidEncounter::new(id, EncounterType::WorldBoss)).collect(),
+    ))
+}
+
 fn parse_clears(json: &str) -> Result<FinishedEncountersStore, serde_json::Error> {
     let cleared_ids: Vec<String> = serde_json::from_str(json)?;
     Ok(FinishedEncountersStore::new(cleared_ids))
@@ -88,77 +257,154 @@ fn parse_token_info(json: &str) -> Result<TokenInfo, serde_json::Error> {
 pub trait Gw2Api {
     fn get_raids(&self) -> Result<RaidWings, ApiError>;
     fn get_finished_encounters(&self, api_key: &str) -> Result<FinishedEncountersStore, ApiError>;
+    /// Progress of the specified achievements, achievements without any progress are omitted.
+    fn get_achievement_progress(&self, api_key: &str, ids: &[u32]) -> Result<Vec<AchievementProgress>, ApiError>;
+    fn get_dungeons(&self) -> Result<RaidWings, ApiError>;
+    /// Dungeon paths finished since the last daily reset.
+    fn get_dungeon_paths(&self, api_key: &str) -> Result<FinishedEncountersStore, ApiError>;
+    fn get_world_bosses(&self) -> Result<WorldBosses, ApiError>;
+    /// World bosses killed since the last daily reset.
+    fn get_finished_world_bosses(&self, api_key: &str) -> Result<FinishedEncountersStore, ApiError>;
     fn get_account_data(&self, api_key: &str) -> Result<AccountData, ApiError>;
     fn get_token_info(&self, api_key: &str) -> Result<TokenInfo, ApiError>;
     fn create_subtoken(&self, api_key: &str, permissions: &[&str], urls: &[&str], expiration: DateTime<Utc>) -> Result<String, ApiError>;
     fn get_account_last_modified(&self, api_key: &str) -> Result<DateTime<Utc>, ApiError>;
 }
 
+/// Loads the raid structure saved by a previous run, if there is one.
+pub fn load_cached_raids(filename: &str) -> Option<RaidWings> {
+    let cached = CachedResponse::load_from_file(filename).ok()?;
+    parse_raids(cached.body()).ok()
+}
+
 pub struct LiveApi {
     url: String,
+    agent: Agent,
+    rate_limiter: Arc<RateLimiter>,
+    cache: HttpCache,
+    raids_cache_filename: Option<String>,
 }
 
 impl LiveApi {
-    pub fn new(url: String) -> Self {
-        LiveApi { url }
+    pub fn new(url: String, rate_limiter: Arc<RateLimiter>) -> Self {
+        let agent = AgentBuilder::new()
+            .timeout(REQUEST_TIMEOUT)
+            .build();
+        LiveApi { url, agent, rate_limiter, cache: HttpCache::new(), raids_cache_filename: None }
+    }
+
+    /// Keeps the raid structure in a file so it is available before the API responds on the next start.
+    pub fn with_raids_cache_file(mut self, filename: &str) -> Self {
+        if let Ok(cached) = CachedResponse::load_from_file(filename) {
+            self.cache.insert(HttpCache::cache_key(RAIDS_PATH, None), cached);
+        }
+        self.raids_cache_filename = Some(filename.to_string());
+        self
+    }
+
+    pub fn official(rate_limiter: Arc<RateLimiter>) -> Self {
+        Self::new(LIVE_GW2_API_URL.to_string(), rate_limiter)
+    }
+
+    /// Prepares a GET request for an API path, waiting for the rate limiter if needed.
+    fn get(&self, path: &str) -> Result<Request, ApiError> {
+        self.rate_limiter.acquire()?;
+        Ok(self.agent.get(&format!("{}{}", self.url, path))
+            .set("User-Agent", USER_AGENT))
     }
 
-    pub fn official() -> Self {
-        Self::new(LIVE_GW2_API_URL.to_string())
+    /// Sends a request, reusing the cached response if the server reports it has not changed.
+    fn call_cached(&self, path: &str, api_key: Option<&str>, request: Request) -> Result<CachedResponse, ApiError> {
+        let cache_key = HttpCache::cache_key(path, api_key);
+        let cached = self.cache.get(&cache_key);
+
+        let request = match &cached {
+            Some(cached) => cached.add_validators(request),
+            None => request,
+        };
+        let response = request.call()?;
+
+        if response.status() == 304 {
+            return cached.ok_or_else(|| ApiError::InvalidResponse("not modified, but there is no cached response".to_string()));
+        }
+
+        let etag = response.header("etag").map(|x| x.to_string());
+        let last_modified = response.header("last-modified").map(|x| x.to_string());
+        let fresh = CachedResponse::new(etag, last_modified, read_body(response)?);
+        if fresh.has_validators() {
+            self.cache.insert(cache_key, fresh.clone());
+        }
+        Ok(fresh)
     }
 }
 
 impl Gw2Api for LiveApi {
     fn get_raids(&self) -> Result<RaidWings, ApiError> {
-        match ureq::get(&format!("{}v2/raids?ids=all", self.url))
-            .set("User-Agent", USER_AGENT)
-            .call()
-        {
-            Ok(response) => {
-                if let Ok(text) = response.into_string() {
-                    Ok(parse_raids(&text)?)
-                } else {
-                    Err(ApiError::UnknownError)
-                }
+        let response = self.call_cached(RAIDS_PATH, None, self.get(RAIDS_PATH)?)?;
+        let raids = parse_raids(response.body())?;
+
+        if let Some(filename) = &self.raids_cache_filename {
+            if let Err(e) = response.save_to_file(filename) {
+                warn!("Failed to save the raid cache: {}", e);
             }
-            Err(_) => Err(ApiError::UnknownError),
         }
+
+        Ok(raids)
     }
 
     fn get_finished_encounters(&self, api_key: &str) -> Result<FinishedEncountersStore, ApiError> {
-        match ureq::get(&format!("{}v2/account/raids", self.url))
-            .set("User-Agent", USER_AGENT)
-            .set("Authorization", &format!("Bearer {}", api_key))
-            .call()
-        {
-            Ok(response) => {
-                if let Ok(text) = response.into_string() {
-                    Ok(parse_clears(&text)?)
-                } else {
-                    // TODO: Implement
-                    Err(ApiError::UnknownError)
-                }
-            }
-            Err(err) => {
-                match err {
-                    // TODO: Make sure this is used everywhere
-                    Error::Status(code, _response) => {
-                        if code == 401 {
-                            Err(ApiError::InvalidKey)
-                        } else if code == 429 {
-                            Err(ApiError::TooManyRequests)
-                        } else {
-                            // TODO: Add logging
-                            Err(ApiError::UnknownError)
-                        }
-                    }
-                    // TODO: Logging
-                    _ => Err(ApiError::UnknownError),
-                }
-            }
+        let path = "v2/account/raids";
+        let request = self.get(path)?
+            .set("Authorization", &format!("Bearer {}", api_key));
+        let response = self.call_cached(path, Some(api_key), request)?;
+
+        Ok(parse_clears(response.body())?)
+    }
+
+    fn get_achievement_progress(&self, api_key: &str, ids: &[u32]) -> Result<Vec<AchievementProgress>, ApiError> {
+        let path = format!("v2/account/achievements?ids={}", ids.iter().join(","));
+        let request = self.get(&path)?
+            .set("Authorization", &format!("Bearer {}", api_key));
+
+        match self.call_cached(&path, Some(api_key), request) {
+            Ok(response) => Ok(serde_json::from_str(response.body())?),
+            // The API responds with 404 if there is no progress on any of the achievements.
+            Err(ApiError::NotFound(_)) => Ok(Vec::new()),
+            Err(e) => Err(e),
         }
     }
 
+    fn get_dungeons(&self) -> Result<RaidWings, ApiError> {
+        let response = self.call_cached(DUNGEONS_PATH, None, self.get(DUNGEONS_PATH)?)?;
+
+        Ok(parse_dungeons(response.body())?)
+    }
+
+    fn get_dungeon_paths(&self, api_key: &str) -> Result<FinishedEncountersStore, ApiError> {
+        let path = "v2/account/dungeons";
+        let request = self.get(path)?
+            .set("Authorization", &format!("Bearer {}", api_key));
+        let response = self.call_cached(path, Some(api_key), request)?;
+
+        // The format is the same as for raids, a list of finished ids.
+        Ok(parse_clears(response.body())?)
+    }
+
+    fn get_world_bosses(&self) -> Result<WorldBosses, ApiError> {
+        let response = self.call_cached(WORLD_BOSSES_PATH, None, self.get(WORLD_BOSSES_PATH)?)?;
+
+        Ok(parse_world_bosses(response.body())?)
+    }
+
+    fn get_finished_world_bosses(&self, api_key: &str) -> Result<FinishedEncountersStore, ApiError> {
+        let path = "v2/account/worldbosses";
+        let request = self.get(path)?
+            .set("Authorization", &format!("Bearer {}", api_key));
+        let response = self.call_cached(path, Some(api_key), request)?;
+
+        Ok(parse_clears(response.body())?)
+    }
+
     fn get_account_last_modified(&self, api_key: &str) -> Result<DateTime<Utc>, ApiError> {
         // Why masteries?
         // This endpoint provides access to the last-modified header,
@@ -167,63 +413,40 @@ impl Gw2Api for LiveApi {
         // received from the friends API.
         // And all other API keys *should* provide access to this endpoint as well,
         // except for custom restricted subtokens.
-        match ureq::get(&format!("{}v2/account/masteries", self.url))
-            .set("User-Agent", USER_AGENT)
+        let response = self.get("v2/account/masteries")?
             .set("X-Schema-Version", "2021-05-20T00:00:00.000Z")
             .set("Authorization", &format!("Bearer {}", api_key))
-            .call()
-        {
-            Ok(response) => {
-                if let Some(last_modified) = response.header("last-modified") {
-                    if let Ok(time) = DateTime::parse_from_rfc2822(last_modified) {
-                        Ok(time.into())
-                    } else {
-                        error!("Failed to parse last modified header time");
-                        Err(ApiError::UnknownError)
-                    }
-                } else {
-                    error!("Missing last-modified header in response");
-                    Err(ApiError::UnknownError)
-                }
+            .call()?;
+
+        if let Some(last_modified) = response.header("last-modified") {
+            if let Ok(time) = DateTime::parse_from_rfc2822(last_modified) {
+                Ok(time.into())
+            } else {
+                Err(ApiError::InvalidResponse(format!("failed to parse last-modified header: {}", last_modified)))
             }
-            Err(_) => Err(ApiError::UnknownError),
+        } else {
+            Err(ApiError::InvalidResponse("missing last-modified header".to_string()))
         }
     }
 
     fn get_account_data(&self, api_key: &str) -> Result<AccountData, ApiError> {
-        match ureq::get(&format!("{}v2/account", self.url))
-            .set("User-Agent", USER_AGENT)
+        let path = "v2/account";
+        let request = self.get(path)?
             .set("X-Schema-Version", "2021-05-20T00:00:00.000Z")
-            .set("Authorization", &format!("Bearer {}", api_key))
-            .call()
-        {
-            Ok(response) => {
-                if let Ok(text) = response.into_string() {
-                    Ok(parse_account_data(&text)?)
-                } else {
-                    Err(ApiError::UnknownError)
-                }
-            }
-            Err(_) => Err(ApiError::UnknownError),
-        }
+            .set("Authorization", &format!("Bearer {}", api_key));
+        let response = self.call_cached(path, Some(api_key), request)?;
+
+        Ok(parse_account_data(response.body())?)
     }
 
     fn get_token_info(&self, api_key: &str) -> Result<TokenInfo, ApiError> {
-        match ureq::get(&format!("{}v2/tokeninfo", self.url))
-            .set("User-Agent", USER_AGENT)
+        let path = "v2/tokeninfo";
+        let request = self.get(path)?
             .set("X-Schema-Version", "2021-05-20T00:00:00.000Z")
-            .set("Authorization", &format!("Bearer {}", api_key))
-            .call()
-        {
-            Ok(response) => {
-                if let Ok(text) = response.into_string() {
-                    Ok(parse_token_info(&text)?)
-                } else {
-                    Err(ApiError::UnknownError)
-                }
-            }
-            Err(_) => Err(ApiError::UnknownError),
-        }
+            .set("Authorization", &format!("Bearer {}", api_key));
+        let response = self.call_cached(path, Some(api_key), request)?;
+
+        Ok(parse_token_info(response.body())?)
     }
 
     fn create_subtoken(&self, api_key: &str, permissions: &[&str], urls: &[&str], expiration: DateTime<Utc>) -> Result<String, ApiError> {
@@ -232,31 +455,21 @@ impl Gw2Api for LiveApi {
             subtoken: String
         }
 
-        match ureq::get(&format!("{}v2/createsubtoken", self.url))
-            .set("User-Agent", USER_AGENT)
+        let response = self.get("v2/createsubtoken")?
             .set("X-Schema-Version", "2021-05-20T00:00:00.000Z")
             .set("Authorization", &format!("Bearer {}", api_key))
             .query("expire", &expiration.to_rfc3339())
             .query("permissions", &permissions.join(","))
             .query("urls", &urls.join(","))
-            .call()
-        {
-            Ok(response) => {
-                if let Ok(json) = response.into_string() {
-                    Ok(serde_json::from_str::<SubtokenResponse>(&json)?.subtoken)
-                } else {
-                    Err(ApiError::UnknownError)
-                }
-            }
-            Err(_) => Err(ApiError::UnknownError),
-        }
+            .call()?;
+
+        Ok(serde_json::from_str::<SubtokenResponse>(&read_body(response)?)?.subtoken)
     }
 }
 
 pub struct ApiMock {}
 
 impl ApiMock {
-    #[allow(dead_code)]
     pub fn new() -> Self {
         ApiMock {}
     }
@@ -293,6 +506,47 @@ impl Gw2Api for ApiMock {
         ]))
     }
 
+    fn get_achievement_progress(&self, _: &str, _: &[u32]) -> Result<Vec<AchievementProgress>, ApiError> {
+        Ok(vec![
+            AchievementProgress::new(5245, false, Some(vec![0, 2])),
+            AchievementProgress::new(2081, true, None),
+        ])
+    }
+
+    fn get_dungeons(&self) -> Result<RaidWings, ApiError> {
+        Ok(RaidWings::new(vec![
+            RaidWing::new(
+                "ascalonian_catacombs".to_string(),
+                vec![
+                    RaidEncounter::new("ac_story".to_string(), EncounterType::DungeonPath),
+                    RaidEncounter::new("hodgins".to_string(), EncounterType::DungeonPath),
+                    RaidEncounter::new("detha".to_string(), EncounterType::DungeonPath),
+                    RaidEncounter::new("tzark".to_string(), EncounterType::DungeonPath),
+                ],
+            ),
+        ]))
+    }
+
+    fn get_dungeon_paths(&self, _: &str) -> Result<FinishedEncountersStore, ApiError> {
+        Ok(FinishedEncountersStore::new(vec![
+            "hodgins".to_string(),
+        ]))
+    }
+
+    fn get_world_bosses(&self) -> Result<WorldBosses, ApiError> {
+        Ok(WorldBosses::new(vec![
+            RaidEncounter::new("admiral_taidha_covington".to_string(), EncounterType::WorldBoss),
+            RaidEncounter::new("claw_of_jormag".to_string(), EncounterType::WorldBoss),
+            RaidEncounter::new("tequatl_the_sunless".to_string(), EncounterType::WorldBoss),
+        ]))
+    }
+
+    fn get_finished_world_bosses(&self, _: &str) -> Result<FinishedEncountersStore, ApiError> {
+        Ok(FinishedEncountersStore::new(vec![
+            "claw_of_jormag".to_string(),
+        ]))
+    }
+
     fn get_account_data(&self, _: &str) -> Result<AccountData, ApiError> {
         Ok(AccountData::new(
             "91B33521-1234-5678-9ABCD-ADB1D78A5C72".to_string(),
@@ -310,8 +564,8 @@ impl Gw2Api for ApiMock {
         ))
     }
 
-    fn create_subtoken(&self, _api_key: &str, _permissions: &[&str], _urls: &[&str], _expiration: DateTime<Utc>) -> Result<String, ApiError> {
-        unimplemented!()
+    fn create_subtoken(&self, api_key: &str, _permissions: &[&str], _urls: &[&str], _expiration: DateTime<Utc>) -> Result<String, ApiError> {
+        Ok(format!("mock-subtoken-{}", api_key))
     }
 
     fn get_account_last_modified(&self, _api_key: &str) -> Result<DateTime<Utc>, ApiError> {
@@ -321,10 +575,15 @@ impl Gw2Api for ApiMock {
 
 #[cfg(test)]
 mod tests {
-    use crate::api::{parse_account_data, parse_clears, parse_raids, parse_token_info};
+    use std::time::Duration;
+    use crate::api::{ApiError, classify_status, parse_account_data, parse_clears, parse_dungeons, parse_raids, parse_token_info, parse_world_bosses};
     use crate::clears::{EncounterType, RaidEncounter};
     use chrono::{Utc, TimeZone, DateTime};
     use crate::settings::TokenType;
+    use crate::api::{Gw2Api, LiveApi};
+    use crate::ratelimit::RateLimiter;
+    use crate::testserver::{FakeAccount, TestServer};
+    use std::sync::Arc;
 
     #[test]
     fn raids_parsed_correctly() {
@@ -512,6 +771,72 @@ mod tests {
         assert_eq!(parsed.wings()[3].encounters().len(), 4);
     }
 
+    #[test]
+    fn dungeons_parsed_correctly() {
+        let api_data_json = r#"[
+  {
+    "id": "ascalonian_catacombs",
+    "paths": [
+      {
+        "id": "ac_story",
+        "type": "Story"
+      },
+      {
+        "id": "hodgins",
+        "type": "Explorable"
+      },
+      {
+        "id": "detha",
+        "type": "Explorable"
+      },
+      {
+        "id": "tzark",
+        "type": "Explorable"
+      }
+    ]
+  },
+  {
+    "id": "caudecus_manor",
+    "paths": [
+      {
+        "id": "cm_story",
+        "type": "Story"
+      },
+      {
+        "id": "asura",
+        "type": "Explorable"
+      }
+    ]
+  }
+]
+"#;
+        let parsed = parse_dungeons(&api_data_json).expect("Failed to deserialize api data json.");
+        assert_eq!(parsed.wings().len(), 2);
+        assert_eq!(parsed.wings()[0].id(), "ascalonian_catacombs");
+        assert_eq!(parsed.wings()[0].encounters().len(), 4);
+        assert_eq!(parsed.wings()[0].encounters()[1].id(), "hodgins");
+        assert_eq!(
+            *parsed.wings()[0].encounters()[1].encounter_type(),
+            EncounterType::DungeonPath
+        );
+        assert_eq!(parsed.wings()[1].encounters()[1].id(), "asura");
+    }
+
+    #[test]
+    fn world_bosses_parsed_correctly() {
+        let api_data_json = r#"[
+  "admiral_taidha_covington",
+  "claw_of_jormag",
+  "drakkar",
+  "tequatl_the_sunless"
+]
+"#;
+        let parsed = parse_world_bosses(&api_data_json).expect("Failed to deserialize api data json.");
+        assert_eq!(parsed.bosses().len(), 4);
+        assert_eq!(parsed.bosses()[1].id(), "claw_of_jormag");
+        assert_eq!(*parsed.bosses()[1].encounter_type(), EncounterType::WorldBoss);
+    }
+
     #[test]
     fn clears_parsed_correctly() {
         let api_response_json = r#"[
@@ -631,4 +956,111 @@ mod tests {
             _ => false
         })
     }
+
+    #[test]
+    fn unauthorized_is_invalid_key() {
+        let error = classify_status(401, None, r#"{"text": "Invalid access token"}"#);
+        assert!(match error {
+            ApiError::InvalidKey(response) => response.status == 401 && response.text == "Invalid access token",
+            _ => false
+        })
+    }
+
+    #[test]
+    fn bad_request_with_invalid_key_text_is_invalid_key() {
+        let error = classify_status(400, None, r#"{"text": "invalid key"}"#);
+        assert!(matches!(error, ApiError::InvalidKey(_)));
+    }
+
+    #[test]
+    fn forbidden_is_insufficient_permissions() {
+        let error = classify_status(403, None, r#"{"text": "requires scope progression"}"#);
+        assert!(match error {
+            ApiError::InsufficientPermissions(response) => response.text == "requires scope progression",
+            _ => false
+        })
+    }
+
+    #[test]
+    fn not_found_classified_correctly() {
+        let error = classify_status(404, None, r#"{"text": "no such endpoint"}"#);
+        assert!(matches!(error, ApiError::NotFound(_)));
+    }
+
+    #[test]
+    fn too_many_requests_reads_retry_after() {
+        let error = classify_status(429, Some("30"), r#"{"text": "too many requests"}"#);
+        assert!(match error {
+            ApiError::TooManyRequests { retry_after } => retry_after == Some(Duration::from_secs(30)),
+            _ => false
+        })
+    }
+
+    #[test]
+    fn too_many_requests_without_valid_retry_after() {
+        let error = classify_status(429, Some("Wed, 21 Oct 2015 07:28:00 GMT"), "");
+        assert!(matches!(error, ApiError::TooManyRequests { retry_after: None }));
+    }
+
+    #[test]
+    fn disabled_api_classified_correctly() {
+        let error = classify_status(503, None, r#"{"text": "API not active"}"#);
+        assert!(matches!(error, ApiError::ApiDisabled(_)));
+    }
+
+    #[test]
+    fn server_errors_classified_correctly() {
+        assert!(matches!(classify_status(500, None, ""), ApiError::ServerError(_)));
+        assert!(matches!(classify_status(502, None, "<html>Bad Gateway</html>"), ApiError::ServerError(_)));
+        assert!(matches!(classify_status(503, None, "Service Unavailable"), ApiError::ServerError(_)));
+    }
+
+    #[test]
+    fn non_json_body_is_truncated() {
+        let body = "x".repeat(1000);
+        let error = classify_status(418, None, &body);
+        assert!(match error {
+            ApiError::UnexpectedStatus(response) => response.status == 418 && response.text.len() == 200,
+            _ => false
+        })
+    }
+
+    #[test]
+    fn live_api_reads_account_data_from_server() {
+        let server = TestServer::start();
+        server.state().add_account("key", FakeAccount::new("Test.1234"));
+        let api = LiveApi::new(server.gw2_url(), Arc::new(RateLimiter::new(100, 100.0)));
+
+        let account = api.get_account_data("key").unwrap();
+        assert_eq!("Test.1234", account.name());
+        assert!(matches!(api.get_token_info("key").unwrap().token_type(), TokenType::ApiKey));
+        assert!(matches!(api.get_account_data("unknown-key"), Err(ApiError::InvalidKey(_))));
+    }
+
+    #[test]
+    fn live_api_reuses_clears_when_not_modified() {
+        let server = TestServer::start();
+        let mut account = FakeAccount::new("Test.1234");
+        account.finished_raids = vec!["vale_guardian".to_string()];
+        server.state().add_account("key", account);
+        let api = LiveApi::new(server.gw2_url(), Arc::new(RateLimiter::new(100, 100.0)));
+
+        let vale_guardian = RaidEncounter::new("vale_guardian".to_string(), EncounterType::Boss);
+        assert!(api.get_finished_encounters("key").unwrap().is_finished(&vale_guardian));
+        // The server responds with 304 Not Modified, the body comes from the cache.
+        assert!(api.get_finished_encounters("key").unwrap().is_finished(&vale_guardian));
+        assert_eq!(2, server.state().request_count("GET /v2/account/raids"));
+    }
+
+    #[test]
+    fn live_api_subtoken_is_usable() {
+        let server = TestServer::start();
+        server.state().add_account("key", FakeAccount::new("Test.1234"));
+        let api = LiveApi::new(server.gw2_url(), Arc::new(RateLimiter::new(100, 100.0)));
+
+        let subtoken = api.create_subtoken("key", &["account"], &["/v2/account/masteries"], Utc::now() + chrono::Duration::days(1)).unwrap();
+        assert!(matches!(api.get_token_info(&subtoken).unwrap().token_type(), TokenType::Subtoken { .. }));
+        assert!(api.get_account_last_modified(&subtoken).is_ok());
+        assert!(api.get_account_data(&subtoken).is_err());
+    }
 }
@@ -0,0 +1,119 @@
+/// arcdps state changes used to detect kills.
+pub const STATECHANGE_CHANGE_DEAD: u8 = 4;
+pub const STATECHANGE_LOG_START: u8 = 9;
+pub const STATECHANGE_LOG_END: u8 = 10;
+pub const STATECHANGE_REWARD: u8 = 19;
+
+/// The elite value arcdps uses for NPCs, the profession is the species id for those.
+pub const NPC_ELITE: u32 = 0xFFFFFFFF;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum KillCondition {
+    /// One of the species dies.
+    Death,
+    /// A reward chest is received while the encounter is logged,
+    /// used for encounters where the boss does not die.
+    Reward,
+}
+
+pub struct Boss {
+    encounter_id: &'static str,
+    species_ids: &'static [u32],
+    condition: KillCondition,
+}
+
+const fn boss(encounter_id: &'static str, species_ids: &'static [u32], condition: KillCondition) -> Boss {
+    Boss { encounter_id, species_ids, condition }
+}
+
+/// Raid bosses with the encounter ids used by the official API.
+static BOSSES: [Boss; 23] = [
+    boss("vale_guardian", &[15438], KillCondition::Death),
+    boss("gorseval", &[15429], KillCondition::Death),
+    boss("sabetha", &[15375], KillCondition::Death),
+    boss("slothasor", &[16123], KillCondition::Death),
+    boss("bandit_trio", &[16088, 16137, 16125], KillCondition::Reward),
+    boss("matthias", &[16115], KillCondition::Death),
+    boss("escort", &[16253], KillCondition::Reward),
+    boss("keep_construct", &[16235], KillCondition::Death),
+    boss("xera", &[16246], KillCondition::Reward),
+    boss("cairn", &[17194], KillCondition::Death),
+    boss("mursaat_overseer", &[17172], KillCondition::Death),
+    boss("samarog", &[17188], KillCondition::Death),
+    boss("deimos", &[17154], KillCondition::Reward),
+    boss("soulless_horror", &[19767], KillCondition::Death),
+    boss("river_of_souls", &[19828], KillCondition::Reward),
+    boss("statues_of_grenth", &[19691, 19536, 19651, 19844], KillCondition::Reward),
+    boss("voice_in_the_void", &[19450], KillCondition::Death),
+    boss("conjured_amalgamate", &[43974], KillCondition::Reward),
+    boss("twin_largos", &[21105, 21089], KillCondition::Reward),
+    boss("qadim", &[20934], KillCondition::Death),
+    boss("adina", &[22006], KillCondition::Death),
+    boss("sabir", &[21964], KillCondition::Death),
+    boss("qadim_the_peerless", &[22000], KillCondition::Death),
+];
+
+pub fn boss_by_species(species_id: u32) -> Option<&'static Boss> {
+    BOSSES.iter().find(|boss| boss.species_ids.contains(&species_id))
+}
+
+/// Finds raid boss kills in arcdps combat events.
+pub struct KillDetector {
+    /// The boss of the encounter that is currently logged.
+    current: Option<&'static Boss>,
+}
+
+impl KillDetector {
+    pub fn new() -> Self {
+        KillDetector { current: None }
+    }
+
+    /// Returns the id of an encounter that was finished by this event.
+    ///
+    /// For log starts, `src_agent` is the species id of the logged boss.
+    /// `src_species` is the species id of the source agent if it is an NPC.
+    pub fn process_event(&mut self, statechange: u8, src_agent: usize, src_species: Option<u32>) -> Option<&'static str> {
+        match statechange {
+            STATECHANGE_LOG_START => {
+                self.current = boss_by_species(src_agent as u32);
+                None
+            }
+            STATECHANGE_LOG_END => {
+                self.current = None;
+                None
+            }
+            // Any boss chest means the encounter is done, even if the boss also dies.
+            STATECHANGE_REWARD => self.current.map(|boss| boss.encounter_id),
+            STATECHANGE_CHANGE_DEAD => src_species
+                .and_then(boss_by_species)
+                .filter(|boss| boss.condition == KillCondition::Death)
+                .map(|boss| boss.encounter_id),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deaths_finish_only_death_encounters() {
+        let mut detector = KillDetector::new();
+        assert_eq!(Some("vale_guardian"), detector.process_event(STATECHANGE_CHANGE_DEAD, 1, Some(15438)));
+        assert_eq!(None, detector.process_event(STATECHANGE_CHANGE_DEAD, 1, Some(17154)));
+        assert_eq!(None, detector.process_event(STATECHANGE_CHANGE_DEAD, 1, None));
+    }
+
+    #[test]
+    fn rewards_finish_the_logged_encounter() {
+        let mut detector = KillDetector::new();
+        assert_eq!(None, detector.process_event(STATECHANGE_REWARD, 0, None));
+
+        detector.process_event(STATECHANGE_LOG_START, 17154, None);
+        assert_eq!(Some("deimos"), detector.process_event(STATECHANGE_REWARD, 0, None));
+
+        detector.process_event(STATECHANGE_LOG_END, 17154, None);
+        assert_eq!(None, detector.process_event(STATECHANGE_REWARD, 0, None));
+    }
+}
@@ -3,11 +3,22 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use chrono::{DateTime, Duration, TimeZone, Utc};
 use uuid::Uuid;
+use crate::achievements;
+use crate::api::ApiError;
 use crate::settings::ApiKey;
 
 pub struct ClearData {
     raids: Option<RaidWings>,
     state: HashMap<Uuid, RaidClearState>,
+    /// Dungeons use the raid structure, with paths as encounters.
+    dungeons: Option<RaidWings>,
+    dungeon_state: HashMap<Uuid, RaidClearState>,
+    world_bosses: Option<WorldBosses>,
+    world_boss_state: HashMap<Uuid, RaidClearState>,
+    /// Challenge motes use the raid structure, their achievements are never reset.
+    challenge_motes: RaidWings,
+    challenge_mote_state: HashMap<Uuid, FinishedEncountersStore>,
+    key_health: HashMap<Uuid, KeyHealth>,
 }
 
 impl ClearData {
@@ -15,6 +26,13 @@ impl ClearData {
         ClearData {
             raids: None,
             state: HashMap::new(),
+            dungeons: None,
+            dungeon_state: HashMap::new(),
+            world_bosses: None,
+            world_boss_state: HashMap::new(),
+            challenge_motes: RaidWings::new(achievements::challenge_mote_groups()),
+            challenge_mote_state: HashMap::new(),
+            key_health: HashMap::new(),
         }
     }
 }
@@ -26,29 +44,202 @@ impl ClearData {
     pub fn state(&self, key: &ApiKey) -> Option<&RaidClearState> {
         self.state.get(key.id())
     }
+    /// Strike missions are added after the raid wings.
     pub fn set_raids(&mut self, raids: Option<RaidWings>) {
-        self.raids = raids;
+        self.raids = raids.map(|raids| {
+            let mut wings = raids.wings;
+            wings.extend(achievements::encounter_groups());
+            RaidWings::new(wings)
+        });
     }
     pub fn set_state(&mut self, uuid: Uuid, state: Option<RaidClearState>) {
-        if let Some(state) = state {
+        if let Some(mut state) = state {
+            if let Some(previous) = self.state.get(&uuid) {
+                state.carry_over_from(previous);
+            }
             self.state.insert(uuid, state);
         } else {
             self.state.remove(&uuid);
         }
     }
+    /// Marks an encounter as finished before the API returns it.
+    pub fn mark_unconfirmed_clear(&mut self, uuid: Uuid, encounter_id: &str, time: DateTime<Utc>) {
+        if let Some(state) = self.state.get_mut(&uuid) {
+            state.finished_encounters.add_unconfirmed(encounter_id, time);
+        }
+    }
     pub fn finished_encounters(&self, key: &ApiKey) -> Option<&FinishedEncountersStore> {
-        static EMPTY_CLEARS: FinishedEncountersStore = FinishedEncountersStore::empty();
-
         let state = self.state(key)?;
-        let last_reset = last_raid_reset(Utc::now());
-        if last_reset >= state.last_api_update_time {
-            Some(&EMPTY_CLEARS)
+        Some(state.finished_since(last_raid_reset(Utc::now())))
+    }
+    pub fn dungeons(&self) -> &Option<RaidWings> {
+        &self.dungeons
+    }
+    pub fn set_dungeons(&mut self, dungeons: Option<RaidWings>) {
+        self.dungeons = dungeons;
+    }
+    pub fn set_dungeon_state(&mut self, uuid: Uuid, state: Option<RaidClearState>) {
+        if let Some(state) = state {
+            self.dungeon_state.insert(uuid, state);
         } else {
-            Some(&state.finished_encounters)
+            self.dungeon_state.remove(&uuid);
+        }
+    }
+    pub fn finished_dungeon_paths(&self, key: &ApiKey) -> Option<&FinishedEncountersStore> {
+        let state = self.dungeon_state.get(key.id())?;
+        Some(state.finished_since(last_daily_reset(Utc::now())))
+    }
+    pub fn world_bosses(&self) -> &Option<WorldBosses> {
+        &self.world_bosses
+    }
+    pub fn set_world_bosses(&mut self, world_bosses: Option<WorldBosses>) {
+        self.world_bosses = world_bosses;
+    }
+    pub fn set_world_boss_state(&mut self, uuid: Uuid, state: Option<RaidClearState>) {
+        if let Some(state) = state {
+            self.world_boss_state.insert(uuid, state);
+        } else {
+            self.world_boss_state.remove(&uuid);
+        }
+    }
+    pub fn finished_world_bosses(&self, key: &ApiKey) -> Option<&FinishedEncountersStore> {
+        let state = self.world_boss_state.get(key.id())?;
+        Some(state.finished_since(last_daily_reset(Utc::now())))
+    }
+    pub fn challenge_motes(&self) -> &RaidWings {
+        &self.challenge_motes
+    }
+    pub fn set_finished_challenge_motes(&mut self, uuid: Uuid, ids: Vec<String>) {
+        self.challenge_mote_state.insert(uuid, FinishedEncountersStore::new(ids));
+    }
+    /// Challenge motes the account has ever finished, these are not part of weekly clears.
+    pub fn finished_challenge_motes(&self, key: &ApiKey) -> Option<&FinishedEncountersStore> {
+        self.challenge_mote_state.get(key.id())
+    }
+    pub fn key_health(&self, key: &ApiKey) -> Option<&KeyHealth> {
+        self.key_health.get(key.id())
+    }
+    pub fn record_key_success(&mut self, uuid: Uuid, request: KeyRequest) {
+        self.key_health.entry(uuid).or_insert_with(KeyHealth::new).record_success(request);
+    }
+    pub fn record_key_failure(&mut self, uuid: Uuid, request: KeyRequest, error: &ApiError) {
+        self.key_health.entry(uuid).or_insert_with(KeyHealth::new).record_failure(request, error, Utc::now());
+    }
+}
+
+/// Kinds of requests made with an API key, their failures are tracked separately.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
+pub enum KeyRequest {
+    Clears,
+    DungeonPaths,
+    WorldBosses,
+    AccountData,
+    TokenInfo,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum ApiFailureKind {
+    InvalidKey,
+    InsufficientPermissions,
+    RateLimited,
+    ApiUnavailable,
+    ConnectionFailed,
+    Other,
+}
+
+impl ApiFailureKind {
+    /// Failures that require action from the user are higher, used to pick the failure to show.
+    fn severity(self) -> u8 {
+        match self {
+            ApiFailureKind::InvalidKey => 5,
+            ApiFailureKind::InsufficientPermissions => 4,
+            ApiFailureKind::Other => 3,
+            ApiFailureKind::ApiUnavailable => 2,
+            ApiFailureKind::ConnectionFailed => 1,
+            ApiFailureKind::RateLimited => 0,
+        }
+    }
+}
+
+impl From<&ApiError> for ApiFailureKind {
+    fn from(error: &ApiError) -> Self {
+        match error {
+            ApiError::InvalidKey(_) => ApiFailureKind::InvalidKey,
+            ApiError::InsufficientPermissions(_) => ApiFailureKind::InsufficientPermissions,
+            ApiError::TooManyRequests { .. } => ApiFailureKind::RateLimited,
+            ApiError::ApiDisabled(_) | ApiError::ServerError(_) => ApiFailureKind::ApiUnavailable,
+            ApiError::Transport(_) | ApiError::Timeout => ApiFailureKind::ConnectionFailed,
+            _ => ApiFailureKind::Other,
         }
     }
 }
 
+pub struct ApiFailure {
+    kind: ApiFailureKind,
+    message: String,
+    time: DateTime<Utc>,
+}
+
+impl ApiFailure {
+    pub fn kind(&self) -> ApiFailureKind {
+        self.kind
+    }
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+    pub fn time(&self) -> DateTime<Utc> {
+        self.time
+    }
+}
+
+/// Tracks whether requests made with an API key have been failing recently.
+///
+/// Each kind of request is tracked separately, so that a successful request
+/// of one kind does not hide failures of another one.
+pub struct KeyHealth {
+    requests: HashMap<KeyRequest, RequestHealth>,
+}
+
+struct RequestHealth {
+    last_failure: Option<ApiFailure>,
+    consecutive_failures: u32,
+}
+
+impl KeyHealth {
+    pub fn new() -> Self {
+        KeyHealth { requests: HashMap::new() }
+    }
+    fn request(&mut self, request: KeyRequest) -> &mut RequestHealth {
+        self.requests.entry(request).or_insert(RequestHealth { last_failure: None, consecutive_failures: 0 })
+    }
+    pub fn record_success(&mut self, request: KeyRequest) {
+        self.request(request).consecutive_failures = 0;
+    }
+    pub fn record_failure(&mut self, request: KeyRequest, error: &ApiError, time: DateTime<Utc>) {
+        let health = self.request(request);
+        health.last_failure = Some(ApiFailure {
+            kind: error.into(),
+            message: error.to_string(),
+            time,
+        });
+        health.consecutive_failures += 1;
+    }
+    /// The request kind with the worst failure that has not been followed by a success.
+    fn current(&self) -> Option<&RequestHealth> {
+        self.requests.values()
+            .filter(|health| health.consecutive_failures > 0)
+            .max_by_key(|health| health.last_failure.as_ref().map(|failure| (failure.kind.severity(), failure.time)))
+    }
+    /// Failures in a row of the request kind with the current failure.
+    pub fn consecutive_failures(&self) -> u32 {
+        self.current().map(|health| health.consecutive_failures).unwrap_or(0)
+    }
+    /// The worst failure of any request kind that has not succeeded since.
+    pub fn current_failure(&self) -> Option<&ApiFailure> {
+        self.current().and_then(|health| health.last_failure.as_ref())
+    }
+}
+
 pub struct RaidWings {
     wings: Vec<RaidWing>,
 }
@@ -60,6 +251,23 @@ impl RaidWings {
     pub fn wings(&self) -> &Vec<RaidWing> {
         &self.wings
     }
+
+    /// All encounters with their wing index and the number of accounts that have not finished them.
+    ///
+    /// When sorted, encounters needed by the same number of accounts stay in raid order.
+    pub fn encounters_needed_by(&self, accounts: &[&FinishedEncountersStore], sort_by_needed: bool) -> Vec<(usize, &RaidEncounter, usize)> {
+        let mut encounters: Vec<(usize, &RaidEncounter, usize)> = self.wings.iter().enumerate()
+            .flat_map(|(wing_index, wing)| wing.encounters.iter().map(move |encounter| (wing_index, encounter)))
+            .map(|(wing_index, encounter)| {
+                let needed_by = accounts.iter().filter(|finished| !finished.is_finished(encounter)).count();
+                (wing_index, encounter, needed_by)
+            })
+            .collect();
+        if sort_by_needed {
+            encounters.sort_by_key(|(_, _, needed_by)| std::cmp::Reverse(*needed_by));
+        }
+        encounters
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -107,19 +315,68 @@ pub enum EncounterType {
     Unknown,
     Checkpoint,
     Boss,
+    DungeonPath,
+    WorldBoss,
+    StrikeMission,
+    ChallengeMote,
+}
+
+/// All world bosses with a daily reward chest.
+pub struct WorldBosses {
+    bosses: Vec<RaidEncounter>,
+}
+
+impl WorldBosses {
+    pub fn new(bosses: Vec<RaidEncounter>) -> Self {
+        WorldBosses { bosses }
+    }
+    pub fn bosses(&self) -> &Vec<RaidEncounter> {
+        &self.bosses
+    }
 }
 
 pub struct FinishedEncountersStore {
     finished_encounter_ids: Vec<String>,
+    /// Clears detected from arcdps events that the API does not return yet.
+    unconfirmed: Vec<UnconfirmedClear>,
+    /// When encounters were cleared, known only for clears that happened between two updates.
+    clear_times: Vec<ClearTime>,
+}
+
+/// An encounter was cleared at some point between the earliest and latest time.
+#[derive(Clone)]
+pub struct ClearTime {
+    encounter_id: String,
+    earliest: DateTime<Utc>,
+    latest: DateTime<Utc>,
+}
+
+impl ClearTime {
+    pub fn earliest(&self) -> DateTime<Utc> {
+        self.earliest
+    }
+    pub fn latest(&self) -> DateTime<Utc> {
+        self.latest
+    }
+}
+
+pub struct UnconfirmedClear {
+    encounter_id: String,
+    time: DateTime<Utc>,
 }
 
 impl FinishedEncountersStore {
     pub fn new(finished_encounter_ids: Vec<String>) -> Self {
-        FinishedEncountersStore { finished_encounter_ids }
+        FinishedEncountersStore { finished_encounter_ids, unconfirmed: Vec::new(), clear_times: Vec::new() }
     }
 
     pub const fn empty() -> Self {
-        FinishedEncountersStore { finished_encounter_ids: Vec::new() }
+        FinishedEncountersStore { finished_encounter_ids: Vec::new(), unconfirmed: Vec::new(), clear_times: Vec::new() }
+    }
+
+    pub fn with_additional_ids(mut self, ids: Vec<String>) -> Self {
+        self.finished_encounter_ids.extend(ids);
+        self
     }
 }
 
@@ -142,12 +399,74 @@ impl RaidClearState {
     pub fn last_api_update_time(&self) -> DateTime<Utc> {
         self.last_api_update_time
     }
+    /// Keeps what is known about clears from the previous update of the same account.
+    ///
+    /// Encounters that were not finished in the previous update get the time between the updates.
+    pub fn carry_over_from(&mut self, previous: &RaidClearState) {
+        let week_start = last_raid_reset(self.last_api_update_time);
+        let previously_finished = previous.finished_since(week_start);
+
+        // The account was last modified after the clear, but the API may lag behind our checks.
+        let latest = self.last_api_update_time.min(self.last_check_time);
+        let earliest = previous.last_check_time.max(week_start).min(latest);
+
+        for id in &self.finished_encounters.finished_encounter_ids {
+            let known_time = previously_finished.clear_times.iter().find(|x| x.encounter_id == *id);
+            if let Some(time) = known_time {
+                self.finished_encounters.clear_times.push(time.clone());
+            } else if !previously_finished.finished_encounter_ids.contains(id) {
+                self.finished_encounters.clear_times.push(ClearTime { encounter_id: id.clone(), earliest, latest });
+            }
+        }
+
+        // Clears detected locally are kept until the API confirms them or the week ends.
+        let last_reset = last_raid_reset(Utc::now());
+        for clear in &previous.finished_encounters.unconfirmed {
+            if clear.time >= last_reset {
+                self.finished_encounters.add_unconfirmed(&clear.encounter_id, clear.time);
+            }
+        }
+    }
+    /// The finished encounters, or none if the account has not been updated since the last reset.
+    pub fn finished_since(&self, last_reset: DateTime<Utc>) -> &FinishedEncountersStore {
+        static EMPTY_CLEARS: FinishedEncountersStore = FinishedEncountersStore::empty();
+
+        if last_reset >= self.last_api_update_time {
+            &EMPTY_CLEARS
+        } else {
+            &self.finished_encounters
+        }
+    }
 }
 
 #[allow(dead_code)]
 impl FinishedEncountersStore {
     pub fn is_finished(&self, encounter: &RaidEncounter) -> bool {
-        self.finished_encounter_ids.iter().any(|x| *x == encounter.id)
+        self.is_confirmed(&encounter.id) || self.unconfirmed.iter().any(|x| x.encounter_id == encounter.id)
+    }
+
+    /// Finished according to arcdps, but not according to the API yet.
+    pub fn is_unconfirmed(&self, encounter: &RaidEncounter) -> bool {
+        self.is_finished(encounter) && !self.is_confirmed(&encounter.id)
+    }
+
+    pub fn add_unconfirmed(&mut self, encounter_id: &str, time: DateTime<Utc>) {
+        if self.is_confirmed(encounter_id) || self.unconfirmed.iter().any(|x| x.encounter_id == encounter_id) {
+            return;
+        }
+        self.unconfirmed.push(UnconfirmedClear { encounter_id: encounter_id.to_string(), time });
+    }
+
+    pub fn unconfirmed(&self) -> &Vec<UnconfirmedClear> {
+        &self.unconfirmed
+    }
+
+    pub fn clear_time(&self, encounter: &RaidEncounter) -> Option<&ClearTime> {
+        self.clear_times.iter().find(|x| x.encounter_id == encounter.id)
+    }
+
+    fn is_confirmed(&self, encounter_id: &str) -> bool {
+        self.finished_encounter_ids.iter().any(|x| x == encounter_id)
     }
 
     pub fn finished_encounter_ids(&self) -> &Vec<String> {
@@ -162,9 +481,14 @@ pub(crate) fn last_raid_reset(current_time: DateTime<Utc>) -> DateTime<Utc> {
     current_time - Duration::seconds((current_time - past_reset).num_seconds() % WEEK_IN_SECONDS)
 }
 
+pub(crate) fn last_daily_reset(current_time: DateTime<Utc>) -> DateTime<Utc> {
+    current_time.date().and_hms(0, 0, 0)
+}
+
 #[cfg(test)]
 mod tests {
     use chrono::{Datelike, Timelike};
+    use crate::api::ErrorResponse;
     use super::*;
 
     #[test]
@@ -244,4 +568,149 @@ mod tests {
         assert_eq!(30, reset.minute());
         assert_eq!(0, reset.second());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn unconfirmed_clears_are_kept_until_confirmed() {
+        let mut data = ClearData::new();
+        let uuid = Uuid::new_v4();
+        let now = Utc::now();
+        let deimos = RaidEncounter::new("deimos".to_string(), EncounterType::Boss);
+        let state = |ids: Vec<String>| Some(RaidClearState::new(FinishedEncountersStore::new(ids), now, now));
+
+        data.set_state(uuid, state(vec![]));
+        data.mark_unconfirmed_clear(uuid, "deimos", now);
+        assert!(data.state[&uuid].finished_encounters().is_unconfirmed(&deimos));
+
+        data.set_state(uuid, state(vec![]));
+        assert!(data.state[&uuid].finished_encounters().is_unconfirmed(&deimos));
+
+        data.set_state(uuid, state(vec!["deimos".to_string()]));
+        let finished = data.state[&uuid].finished_encounters();
+        assert!(finished.is_finished(&deimos));
+        assert!(!finished.is_unconfirmed(&deimos));
+        assert!(finished.unconfirmed().is_empty());
+    }
+
+    #[test]
+    fn clear_times_are_between_updates() {
+        let mut data = ClearData::new();
+        let uuid = Uuid::new_v4();
+        let first_check = Utc.ymd(2021, 12, 28).and_hms(20, 0, 0);
+        let second_check = Utc.ymd(2021, 12, 28).and_hms(20, 30, 0);
+        let modified = Utc.ymd(2021, 12, 28).and_hms(20, 15, 0);
+        let ids = |ids: &[&str]| FinishedEncountersStore::new(ids.iter().map(|x| x.to_string()).collect());
+        let vale_guardian = RaidEncounter::new("vale_guardian".to_string(), EncounterType::Boss);
+        let gorseval = RaidEncounter::new("gorseval".to_string(), EncounterType::Boss);
+
+        data.set_state(uuid, Some(RaidClearState::new(ids(&["vale_guardian"]), first_check, first_check)));
+        data.set_state(uuid, Some(RaidClearState::new(ids(&["vale_guardian", "gorseval"]), second_check, modified)));
+
+        let finished = data.state[&uuid].finished_encounters();
+        assert!(finished.clear_time(&vale_guardian).is_none());
+        let time = finished.clear_time(&gorseval).unwrap();
+        assert_eq!(first_check, time.earliest());
+        assert_eq!(modified, time.latest());
+
+        // Known times are kept by later updates.
+        data.set_state(uuid, Some(RaidClearState::new(ids(&["vale_guardian", "gorseval"]), second_check + Duration::minutes(5), modified)));
+        assert_eq!(modified, data.state[&uuid].finished_encounters().clear_time(&gorseval).unwrap().latest());
+    }
+
+    #[test]
+    fn encounters_are_sorted_by_most_needed() {
+        let raids = RaidWings::new(vec![
+            RaidWing::new("spirit_vale".to_string(), vec![
+                RaidEncounter::new("vale_guardian".to_string(), EncounterType::Boss),
+                RaidEncounter::new("gorseval".to_string(), EncounterType::Boss),
+            ]),
+            RaidWing::new("salvation_pass".to_string(), vec![
+                RaidEncounter::new("slothasor".to_string(), EncounterType::Boss),
+            ]),
+        ]);
+        let first = FinishedEncountersStore::new(vec!["vale_guardian".to_string()]);
+        let second = FinishedEncountersStore::new(vec!["vale_guardian".to_string(), "slothasor".to_string()]);
+        let ids = |encounters: Vec<(usize, &RaidEncounter, usize)>| encounters.iter()
+            .map(|(wing, encounter, needed_by)| (*wing, encounter.id().to_string(), *needed_by))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![(0, "vale_guardian".to_string(), 0), (0, "gorseval".to_string(), 2), (1, "slothasor".to_string(), 1)],
+            ids(raids.encounters_needed_by(&[&first, &second], false))
+        );
+        assert_eq!(
+            vec![(0, "gorseval".to_string(), 2), (1, "slothasor".to_string(), 1), (0, "vale_guardian".to_string(), 0)],
+            ids(raids.encounters_needed_by(&[&first, &second], true))
+        );
+    }
+
+    #[test]
+    fn last_daily_reset_is_midnight_utc() {
+        let time = Utc.ymd(2021, 12, 31).and_hms(15, 20, 10);
+        assert_eq!(Utc.ymd(2021, 12, 31).and_hms(0, 0, 0), last_daily_reset(time));
+    }
+
+    #[test]
+    fn last_daily_reset_on_reset() {
+        let time = Utc.ymd(2022, 1, 1).and_hms(0, 0, 0);
+        assert_eq!(time, last_daily_reset(time));
+    }
+
+    #[test]
+    fn clears_are_empty_if_not_updated_since_reset() {
+        let state = RaidClearState::new(
+            FinishedEncountersStore::new(vec!["hodgins".to_string()]),
+            Utc.ymd(2021, 12, 31).and_hms(10, 0, 0),
+            Utc.ymd(2021, 12, 30).and_hms(23, 0, 0),
+        );
+        assert!(state.finished_since(Utc.ymd(2021, 12, 31).and_hms(0, 0, 0)).finished_encounter_ids().is_empty());
+        assert_eq!(1, state.finished_since(Utc.ymd(2021, 12, 30).and_hms(0, 0, 0)).finished_encounter_ids().len());
+    }
+
+    #[test]
+    fn challenge_motes_are_not_weekly_encounters() {
+        let mut data = ClearData::new();
+        data.set_raids(Some(RaidWings::new(Vec::new())));
+        let weekly: Vec<&str> = data.raids().as_ref().unwrap().wings().iter().map(|wing| wing.id()).collect();
+        assert_eq!(vec![achievements::STRIKE_MISSIONS_GROUP_ID], weekly);
+        assert!(data.challenge_motes().wings().iter().all(|wing| !weekly.contains(&wing.id())));
+        assert!(!data.challenge_motes().wings().is_empty());
+    }
+
+    #[test]
+    fn key_health_counts_consecutive_failures() {
+        let mut health = KeyHealth::new();
+        let time = Utc.ymd(2021, 12, 27).and_hms(20, 10, 0);
+        health.record_failure(KeyRequest::Clears, &ApiError::Timeout, time);
+        health.record_failure(KeyRequest::Clears, &ApiError::TooManyRequests { retry_after: None }, time);
+        assert_eq!(2, health.consecutive_failures());
+        assert_eq!(ApiFailureKind::RateLimited, health.current_failure().unwrap().kind());
+        assert_eq!(time, health.current_failure().unwrap().time());
+    }
+
+    #[test]
+    fn key_health_success_clears_current_failure() {
+        let mut health = KeyHealth::new();
+        health.record_failure(KeyRequest::Clears, &ApiError::Timeout, Utc::now());
+        health.record_success(KeyRequest::Clears);
+        assert_eq!(0, health.consecutive_failures());
+        assert!(health.current_failure().is_none());
+    }
+
+    #[test]
+    fn key_health_success_of_other_request_keeps_failure() {
+        let mut health = KeyHealth::new();
+        let time = Utc.ymd(2021, 12, 27).and_hms(20, 10, 0);
+        health.record_failure(KeyRequest::Clears, &ApiError::InsufficientPermissions(ErrorResponse { status: 403, text: "requires scope progression".to_string() }), time);
+        health.record_failure(KeyRequest::WorldBosses, &ApiError::Timeout, time + Duration::minutes(1));
+        health.record_success(KeyRequest::TokenInfo);
+
+        // The worst failure is shown, even if it is not the most recent one.
+        assert_eq!(ApiFailureKind::InsufficientPermissions, health.current_failure().unwrap().kind());
+        assert_eq!(1, health.consecutive_failures());
+
+        health.record_success(KeyRequest::Clears);
+        assert_eq!(ApiFailureKind::ConnectionFailed, health.current_failure().unwrap().kind());
+        health.record_success(KeyRequest::WorldBosses);
+        assert!(health.current_failure().is_none());
+    }
+}
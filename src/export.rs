@@ -0,0 +1,165 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::clears::{last_raid_reset, RaidClearState, RaidWings};
+use crate::translations::{encounter_english_name, wing_english_name};
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+    Markdown,
+}
+
+/// A single encounter of an account.
+#[derive(Serialize)]
+pub struct ExportRow {
+    account: String,
+    wing: String,
+    encounter: String,
+    finished: bool,
+    /// The last time the account was updated in the API.
+    last_update: DateTime<Utc>,
+}
+
+impl ExportFormat {
+    pub fn all() -> [ExportFormat; 3] {
+        [ExportFormat::Csv, ExportFormat::Json, ExportFormat::Markdown]
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+            ExportFormat::Markdown => "md",
+        }
+    }
+
+    pub fn format(self, rows: &[ExportRow]) -> Result<String, serde_json::Error> {
+        match self {
+            ExportFormat::Csv => Ok(to_csv(rows)),
+            ExportFormat::Json => serde_json::to_string_pretty(rows),
+            ExportFormat::Markdown => Ok(to_markdown(rows)),
+        }
+    }
+}
+
+/// Rows for all encounters of each account, accounts without any data are skipped.
+pub fn export_rows(raids: &RaidWings, accounts: &[(String, Option<&RaidClearState>)], now: DateTime<Utc>) -> Vec<ExportRow> {
+    let mut rows = Vec::new();
+    for (account, state) in accounts {
+        let state = match state {
+            Some(state) => state,
+            None => continue,
+        };
+        let finished = state.finished_since(last_raid_reset(now));
+        for wing in raids.wings() {
+            for encounter in wing.encounters() {
+                rows.push(ExportRow {
+                    account: account.clone(),
+                    wing: wing_english_name(wing),
+                    encounter: encounter_english_name(encounter),
+                    finished: finished.is_finished(encounter),
+                    last_update: state.last_api_update_time(),
+                });
+            }
+        }
+    }
+    rows
+}
+
+/// Writes the export next to other addon files and returns the name of the file.
+pub fn write_export(filename_base: &str, format: ExportFormat, rows: &[ExportRow]) -> Result<String, Box<dyn Error>> {
+    let filename = format!("{}.{}", filename_base, format.extension());
+    let mut writer = BufWriter::new(File::create(&filename)?);
+    writer.write_all(format.format(rows)?.as_bytes())?;
+    writer.flush()?;
+    Ok(filename)
+}
+
+fn row_fields(row: &ExportRow) -> [String; 5] {
+    [
+        row.account.clone(),
+        row.wing.clone(),
+        row.encounter.clone(),
+        row.finished.to_string(),
+        row.last_update.to_rfc3339(),
+    ]
+}
+
+const HEADER: [&str; 5] = ["account", "wing", "encounter", "finished", "last_update"];
+
+fn to_csv(rows: &[ExportRow]) -> String {
+    fn escape(field: &str) -> String {
+        if field.contains(|c| c == ',' || c == '"' || c == '\n') {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field.to_string()
+        }
+    }
+
+    let mut csv = HEADER.join(",");
+    csv.push('\n');
+    for row in rows {
+        let fields: Vec<String> = row_fields(row).iter().map(|x| escape(x)).collect();
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+fn to_markdown(rows: &[ExportRow]) -> String {
+    fn escape(field: &str) -> String {
+        field.replace('|', "\\|")
+    }
+
+    let mut markdown = format!("| {} |\n", HEADER.join(" | "));
+    markdown.push_str(&format!("|{}\n", " --- |".repeat(HEADER.len())));
+    for row in rows {
+        let fields: Vec<String> = row_fields(row).iter().map(|x| escape(x)).collect();
+        markdown.push_str(&format!("| {} |\n", fields.join(" | ")));
+    }
+    markdown
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use super::*;
+    use crate::clears::{EncounterType, FinishedEncountersStore, RaidEncounter, RaidWing};
+
+    fn rows() -> Vec<ExportRow> {
+        let raids = RaidWings::new(vec![RaidWing::new("spirit_vale".to_string(), vec![
+            RaidEncounter::new("vale_guardian".to_string(), EncounterType::Boss),
+            RaidEncounter::new("gorseval".to_string(), EncounterType::Boss),
+        ])]);
+        let now = Utc.ymd(2021, 12, 28).and_hms(12, 0, 0);
+        let state = RaidClearState::new(FinishedEncountersStore::new(vec!["gorseval".to_string()]), now, now);
+        export_rows(&raids, &[("Test, \"Quoted\".1234".to_string(), Some(&state)), ("Friend.1234".to_string(), None)], now)
+    }
+
+    #[test]
+    fn csv_is_escaped() {
+        let csv = ExportFormat::Csv.format(&rows()).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(3, lines.len());
+        assert_eq!("account,wing,encounter,finished,last_update", lines[0]);
+        assert_eq!("\"Test, \"\"Quoted\"\".1234\",Spirit Vale,Gorseval,true,2021-12-28T12:00:00+00:00", lines[2]);
+    }
+
+    #[test]
+    fn json_and_markdown_have_all_rows() {
+        let json: serde_json::Value = serde_json::from_str(&ExportFormat::Json.format(&rows()).unwrap()).unwrap();
+        assert_eq!(2, json.as_array().unwrap().len());
+        assert_eq!(false, json[0]["finished"]);
+        assert_eq!("Vale Guardian", json[0]["encounter"]);
+
+        let markdown = ExportFormat::Markdown.format(&rows()).unwrap();
+        assert_eq!(4, markdown.lines().count());
+        assert!(markdown.starts_with("| account | wing | encounter | finished | last_update |\n| --- | --- |"));
+    }
+}
@@ -1,17 +1,23 @@
 use std::collections::HashMap;
-use std::fmt::Write;
+use std::fmt;
+use std::fmt::{Formatter, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use chrono::{DateTime, Utc};
 use itertools::Itertools;
 use serde::Deserialize;
 use sha2::{Digest, Sha256};
-use ureq::Request;
+use ureq::{Agent, AgentBuilder, Request};
 use crate::clears;
 
 use crate::clears::{FinishedEncountersStore, RaidClearState};
+use crate::ratelimit::RateLimiter;
 use crate::settings::{ApiKey, TokenType};
+use crate::shutdown::Stopped;
 
 const USER_AGENT: &str = concat!("arcdps-clears/", env!("CARGO_PKG_VERSION"));
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
 
 pub const SUBTOKEN_URLS: [&str; 8] = [
     "/v2/tokeninfo",
@@ -148,11 +154,17 @@ pub struct FriendData {
     api_state: Option<State>,
     subtokens_by_account: HashMap<String, String>,
     clears_by_account: HashMap<String, RaidClearState>,
+    world_bosses_by_account: HashMap<String, RaidClearState>,
 }
 
 impl FriendData {
     pub fn new() -> Self {
-        FriendData { api_state: None, clears_by_account: HashMap::new(), subtokens_by_account: HashMap::new() }
+        FriendData {
+            api_state: None,
+            clears_by_account: HashMap::new(),
+            world_bosses_by_account: HashMap::new(),
+            subtokens_by_account: HashMap::new(),
+        }
     }
     pub fn api_state(&self) -> Option<&State> {
         self.api_state.as_ref()
@@ -178,19 +190,22 @@ impl FriendData {
         self.api_state = api_state;
     }
     pub fn finished_encounters(&self, account: &str) -> Option<&FinishedEncountersStore> {
-        static EMPTY_CLEARS: FinishedEncountersStore = FinishedEncountersStore::empty();
-
         let state = self.clears_by_account.get(account)?;
-        let last_reset = clears::last_raid_reset(Utc::now());
-        if last_reset >= state.last_api_update_time() {
-            Some(&EMPTY_CLEARS)
-        } else {
-            Some(state.finished_encounters())
-        }
+        Some(state.finished_since(clears::last_raid_reset(Utc::now())))
     }
-    pub fn set_clears(&mut self, account: String, clear_data: RaidClearState) {
+    pub fn set_clears(&mut self, account: String, mut clear_data: RaidClearState) {
+        if let Some(previous) = self.clears_by_account.get(&account) {
+            clear_data.carry_over_from(previous);
+        }
         self.clears_by_account.insert(account, clear_data);
     }
+    pub fn finished_world_bosses(&self, account: &str) -> Option<&FinishedEncountersStore> {
+        let state = self.world_bosses_by_account.get(account)?;
+        Some(state.finished_since(clears::last_daily_reset(Utc::now())))
+    }
+    pub fn set_world_bosses(&mut self, account: String, state: RaidClearState) {
+        self.world_bosses_by_account.insert(account, state);
+    }
     pub fn set_subtoken(&mut self, account: String, subtoken: String) {
         self.subtokens_by_account.insert(account, subtoken);
     }
@@ -203,10 +218,44 @@ fn parse_state(json: &str) -> Result<State, serde_json::Error> {
     serde_json::from_str(json)
 }
 
+#[derive(Debug)]
 pub enum FriendsApiError {
     JsonDeserializationFailed(serde_json::Error),
     UreqError(ureq::Error),
     UnknownError,
+    /// The request was not sent because the plugin is shutting down.
+    Stopped,
+}
+
+impl FriendsApiError {
+    /// Whether the request may succeed if we try again later.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            FriendsApiError::UreqError(ureq::Error::Status(status, _)) => *status == 429 || (500..=599).contains(status),
+            _ => false,
+        }
+    }
+
+    /// How long the server asked us to wait before retrying, if it did.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            FriendsApiError::UreqError(ureq::Error::Status(_, response)) => response.header("retry-after")
+                .and_then(|x| x.trim().parse().ok())
+                .map(Duration::from_secs),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for FriendsApiError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            FriendsApiError::JsonDeserializationFailed(e) => write!(f, "json deserialization failed: {}", e),
+            FriendsApiError::UreqError(e) => write!(f, "{}", e),
+            FriendsApiError::UnknownError => write!(f, "unknown error"),
+            FriendsApiError::Stopped => write!(f, "shutting down"),
+        }
+    }
 }
 
 impl From<serde_json::Error> for FriendsApiError {
@@ -215,6 +264,12 @@ impl From<serde_json::Error> for FriendsApiError {
     }
 }
 
+impl From<Stopped> for FriendsApiError {
+    fn from(_: Stopped) -> Self {
+        FriendsApiError::Stopped
+    }
+}
+
 impl From<ureq::Error> for FriendsApiError {
     fn from(e: ureq::Error) -> Self {
         FriendsApiError::UreqError(e)
@@ -227,17 +282,34 @@ pub struct FriendRequestMetadata {
     pub public_friends: Vec<String>,
 }
 
+pub trait FriendsApi {
+    fn get_state(&self, metadata: FriendRequestMetadata) -> Result<State, FriendsApiError>;
+    fn add_subtoken(&self, metadata: FriendRequestMetadata, api_key: &str, subtoken: String) -> Result<State, FriendsApiError>;
+    fn share(&self, metadata: FriendRequestMetadata, api_key: &str, friend_account: String) -> Result<State, FriendsApiError>;
+    fn unshare(&self, metadata: FriendRequestMetadata, api_key: &str, friend_account: String) -> Result<State, FriendsApiError>;
+    fn set_public(&self, metadata: FriendRequestMetadata, api_key: &str, public: bool, disabled: bool) -> Result<State, FriendsApiError>;
+}
+
 pub struct FriendsApiClient {
     url: String,
+    agent: Agent,
+    rate_limiter: Arc<RateLimiter>,
 }
 
 impl FriendsApiClient {
-    pub fn new(url: String) -> Self {
-        FriendsApiClient { url }
+    pub fn new(url: String, rate_limiter: Arc<RateLimiter>) -> Self {
+        // Without a timeout, an unresponsive server would keep the worker from stopping.
+        let agent = AgentBuilder::new()
+            .timeout(REQUEST_TIMEOUT)
+            .build();
+        FriendsApiClient { url, agent, rate_limiter }
     }
+}
 
-    pub fn get_state(&self, metadata: FriendRequestMetadata) -> Result<State, FriendsApiError> {
-        let response = ureq::get(&format!("{}state", self.url))
+impl FriendsApi for FriendsApiClient {
+    fn get_state(&self, metadata: FriendRequestMetadata) -> Result<State, FriendsApiError> {
+        self.rate_limiter.acquire()?;
+        let response = self.agent.get(&format!("{}state", self.url))
             .apply_metadata(metadata)
             .call()?;
 
@@ -248,8 +320,9 @@ impl FriendsApiClient {
         }
     }
 
-    pub fn add_subtoken(&self, metadata: FriendRequestMetadata, api_key: &str, subtoken: String) -> Result<State, FriendsApiError> {
-        let response = ureq::post(&format!("{}key/add", self.url))
+    fn add_subtoken(&self, metadata: FriendRequestMetadata, api_key: &str, subtoken: String) -> Result<State, FriendsApiError> {
+        self.rate_limiter.acquire()?;
+        let response = self.agent.post(&format!("{}key/add", self.url))
             .apply_metadata(metadata)
             .send_form(&[
                 ("key_hash", &key_hash(api_key)),
@@ -263,8 +336,9 @@ impl FriendsApiClient {
         }
     }
 
-    pub fn share(&self, metadata: FriendRequestMetadata, api_key: &str, friend_account: String) -> Result<State, FriendsApiError> {
-        let response = ureq::post(&format!("{}key/share", self.url))
+    fn share(&self, metadata: FriendRequestMetadata, api_key: &str, friend_account: String) -> Result<State, FriendsApiError> {
+        self.rate_limiter.acquire()?;
+        let response = self.agent.post(&format!("{}key/share", self.url))
             .apply_metadata(metadata)
             .send_form(&[
                 ("key_hash", &key_hash(api_key)),
@@ -278,8 +352,9 @@ impl FriendsApiClient {
         }
     }
 
-    pub fn unshare(&self, metadata: FriendRequestMetadata, api_key: &str, friend_account: String) -> Result<State, FriendsApiError> {
-        let response = ureq::post(&format!("{}key/unshare", self.url))
+    fn unshare(&self, metadata: FriendRequestMetadata, api_key: &str, friend_account: String) -> Result<State, FriendsApiError> {
+        self.rate_limiter.acquire()?;
+        let response = self.agent.post(&format!("{}key/unshare", self.url))
             .apply_metadata(metadata)
             .send_form(&[
                 ("key_hash", &key_hash(api_key)),
@@ -293,8 +368,9 @@ impl FriendsApiClient {
         }
     }
 
-    pub fn set_public(&self, metadata: FriendRequestMetadata, api_key: &str, public: bool, disabled: bool) -> Result<State, FriendsApiError> {
-        let response = ureq::post(&format!("{}key/public", self.url))
+    fn set_public(&self, metadata: FriendRequestMetadata, api_key: &str, public: bool, disabled: bool) -> Result<State, FriendsApiError> {
+        self.rate_limiter.acquire()?;
+        let response = self.agent.post(&format!("{}key/public", self.url))
             .apply_metadata(metadata)
             .send_form(&[
                 ("key_hash", &key_hash(api_key)),
@@ -310,6 +386,179 @@ impl FriendsApiClient {
     }
 }
 
+/// A friends server kept in memory, used to work on friends features without a server.
+///
+/// Keys receive an account name once their subtoken is added, shares and public status
+/// are kept for the lifetime of the mock.
+pub struct FriendsApiMock {
+    keys: Mutex<HashMap<String, MockKey>>,
+}
+
+struct MockKey {
+    account: Option<String>,
+    subtoken: Option<String>,
+    subtoken_added_at: Option<DateTime<Utc>>,
+    shared_to: Vec<ShareState>,
+    /// Friends added to the mock directly share their data with everyone.
+    shared_with_everyone: bool,
+    public: bool,
+    disabled: bool,
+}
+
+impl MockKey {
+    fn new() -> Self {
+        MockKey {
+            account: None,
+            subtoken: None,
+            subtoken_added_at: None,
+            shared_to: Vec::new(),
+            shared_with_everyone: false,
+            public: false,
+            disabled: false,
+        }
+    }
+}
+
+impl FriendsApiMock {
+    pub fn new() -> Self {
+        FriendsApiMock { keys: Mutex::new(HashMap::new()) }
+    }
+
+    /// Adds a friend that shares their data with all accounts.
+    pub fn with_friend(self, account: &str, public: bool) -> Self {
+        let mut key = MockKey::new();
+        key.account = Some(account.to_string());
+        key.subtoken = Some(format!("mock-subtoken-{}", account));
+        key.subtoken_added_at = Some(Utc::now());
+        key.shared_with_everyone = true;
+        key.public = public;
+        self.keys.lock().unwrap().insert(key_hash(account), key);
+        self
+    }
+
+    fn subtoken_expires_at(key: &MockKey) -> Option<DateTime<Utc>> {
+        key.subtoken_added_at.map(|added_at| added_at + chrono::Duration::days(365))
+    }
+
+    /// Changes a key that is included in the metadata and returns the new state.
+    fn update_key<F: FnOnce(&mut MockKey)>(&self, metadata: FriendRequestMetadata, api_key: &str, update: F) -> Result<State, FriendsApiError> {
+        if !metadata.api_keys.iter().any(|x| x == api_key) {
+            return Err(FriendsApiError::UnknownError);
+        }
+        update(self.keys.lock().unwrap().entry(key_hash(api_key)).or_insert_with(MockKey::new));
+        self.get_state(metadata)
+    }
+}
+
+impl FriendsApi for FriendsApiMock {
+    fn get_state(&self, metadata: FriendRequestMetadata) -> Result<State, FriendsApiError> {
+        let keys = self.keys.lock().unwrap();
+        let own_hashes: Vec<String> = metadata.api_keys.iter().map(|x| key_hash(x)).collect();
+        let own_accounts: Vec<String> = own_hashes.iter()
+            .filter_map(|hash| keys.get(hash).and_then(|key| key.account.clone()))
+            .collect();
+
+        let key_states = own_hashes.iter()
+            .map(|hash| match keys.get(hash) {
+                Some(key) => KeyState {
+                    key_hash: hash.clone(),
+                    shared_to: key.shared_to.iter()
+                        .map(|x| ShareState { account: x.account.clone(), added_at: x.added_at, account_available: x.account_available })
+                        .collect(),
+                    subtoken_added_at: key.subtoken_added_at,
+                    subtoken_expires_at: Self::subtoken_expires_at(key),
+                    account: key.account.clone(),
+                    public: key.public,
+                    disabled: key.disabled,
+                },
+                None => KeyState {
+                    key_hash: hash.clone(),
+                    shared_to: Vec::new(),
+                    subtoken_added_at: None,
+                    subtoken_expires_at: None,
+                    account: None,
+                    public: false,
+                    disabled: false,
+                },
+            })
+            .collect();
+
+        let mut friends: Vec<FriendState> = Vec::new();
+        for (hash, key) in keys.iter() {
+            let (account, subtoken) = match (&key.account, &key.subtoken) {
+                (Some(account), Some(subtoken)) => (account, subtoken),
+                _ => continue,
+            };
+            if key.disabled || own_hashes.contains(hash) || own_accounts.contains(account) {
+                continue;
+            }
+
+            let shared_with: Vec<String> = own_accounts.iter()
+                .filter(|own| key.shared_with_everyone || key.shared_to.iter().any(|x| x.account == **own))
+                .cloned()
+                .collect();
+            let public = key.public && metadata.public_friends.contains(account);
+            if shared_with.is_empty() && !public {
+                continue;
+            }
+
+            friends.push(FriendState {
+                account: account.clone(),
+                subtoken: Some(SubtokenState {
+                    subtoken: subtoken.clone(),
+                    expires_at: Self::subtoken_expires_at(key).unwrap_or_else(Utc::now),
+                }),
+                shared_with,
+                known: true,
+                public,
+            });
+        }
+
+        for account in &metadata.public_friends {
+            if !friends.iter().any(|x| x.account == *account) {
+                friends.push(FriendState {
+                    account: account.clone(),
+                    subtoken: None,
+                    shared_with: Vec::new(),
+                    known: false,
+                    public: false,
+                });
+            }
+        }
+
+        Ok(State { keys: key_states, friends })
+    }
+
+    fn add_subtoken(&self, metadata: FriendRequestMetadata, api_key: &str, subtoken: String) -> Result<State, FriendsApiError> {
+        // There is no GW2 API to ask, so the account name is made up from the key.
+        let account = format!("Mock.{}", &key_hash(api_key)[..4]);
+        self.update_key(metadata, api_key, |key| {
+            key.account = Some(account);
+            key.subtoken = Some(subtoken);
+            key.subtoken_added_at = Some(Utc::now());
+        })
+    }
+
+    fn share(&self, metadata: FriendRequestMetadata, api_key: &str, friend_account: String) -> Result<State, FriendsApiError> {
+        self.update_key(metadata, api_key, |key| {
+            if !key.shared_to.iter().any(|x| x.account == friend_account) {
+                key.shared_to.push(ShareState { account: friend_account, added_at: Utc::now(), account_available: true });
+            }
+        })
+    }
+
+    fn unshare(&self, metadata: FriendRequestMetadata, api_key: &str, friend_account: String) -> Result<State, FriendsApiError> {
+        self.update_key(metadata, api_key, |key| key.shared_to.retain(|x| x.account != friend_account))
+    }
+
+    fn set_public(&self, metadata: FriendRequestMetadata, api_key: &str, public: bool, disabled: bool) -> Result<State, FriendsApiError> {
+        self.update_key(metadata, api_key, |key| {
+            key.public = public;
+            key.disabled = disabled;
+        })
+    }
+}
+
 trait RequestExt {
     fn apply_metadata(self, metadata: FriendRequestMetadata) -> Self;
 }
@@ -584,4 +833,72 @@ mod tests {
         let hash = key_hash(&api_key);
         assert_eq!(hash, "27e6da1e6e2a277cbaf23df8213159a9862f6b4d0f6b82d72652a672e01d76f4");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn client_shares_key_through_server() {
+        use crate::api::{Gw2Api, LiveApi};
+        use crate::testserver::{FakeAccount, TestServer};
+
+        let server = TestServer::start();
+        server.state().add_account("own-key", FakeAccount::new("Own.1234"));
+        server.state().add_account("friend-key", FakeAccount::new("Friend.5678"));
+        let rate_limiter = Arc::new(RateLimiter::new(100, 100.0));
+        let api = LiveApi::new(server.gw2_url(), rate_limiter.clone());
+        let client = FriendsApiClient::new(server.friends_url(), rate_limiter);
+        let metadata = |key: &str| FriendRequestMetadata { api_keys: vec![key.to_string()], public_friends: Vec::new() };
+
+        // The server only knows which account a key belongs to after it receives its subtoken.
+        for key in &["own-key", "friend-key"] {
+            let subtoken = api.create_subtoken(key, &SUBTOKEN_PERMISSIONS, &SUBTOKEN_URLS, Utc::now() + chrono::Duration::days(1)).unwrap();
+            client.add_subtoken(metadata(key), key, subtoken).unwrap();
+            assert!(server.state().friends_has_subtoken(key));
+        }
+        let state = client.get_state(metadata("friend-key")).unwrap();
+        assert_eq!(&Some("Friend.5678".to_string()), state.keys()[0].account());
+
+        client.share(metadata("friend-key"), "friend-key", "Own.1234".to_string()).unwrap();
+        let state = client.get_state(metadata("own-key")).unwrap();
+        assert_eq!(1, state.friends().len());
+        assert_eq!("Friend.5678", state.friends()[0].account());
+        assert!(state.friends()[0].subtoken().is_some());
+
+        client.unshare(metadata("friend-key"), "friend-key", "Own.1234".to_string()).unwrap();
+        assert!(client.get_state(metadata("own-key")).unwrap().friends().is_empty());
+    }
+
+    #[test]
+    fn mock_keeps_shares_consistent() {
+        let api = FriendsApiMock::new().with_friend("Friend.1234", false);
+        let own = FriendRequestMetadata { api_keys: vec!["own-key".to_string()], public_friends: Vec::new() };
+        let other = FriendRequestMetadata { api_keys: vec!["other-key".to_string()], public_friends: Vec::new() };
+
+        api.add_subtoken(own.clone(), "own-key", "own-subtoken".to_string()).unwrap();
+        let other_state = api.add_subtoken(other.clone(), "other-key", "other-subtoken".to_string()).unwrap();
+        let other_account = other_state.keys()[0].account().clone().unwrap();
+        assert_eq!(vec!["Friend.1234"], other_state.friends().iter().map(|x| x.account()).collect::<Vec<_>>());
+
+        let own_state = api.share(own.clone(), "own-key", other_account.clone()).unwrap();
+        assert_eq!(other_account, own_state.keys()[0].shared_to()[0].account());
+        assert_eq!(2, api.get_state(other.clone()).unwrap().friends().len());
+
+        api.unshare(own.clone(), "own-key", other_account).unwrap();
+        assert_eq!(1, api.get_state(other.clone()).unwrap().friends().len());
+        assert!(api.share(own, "other-key", "Friend.1234".to_string()).is_err());
+    }
+
+    #[test]
+    fn mock_public_friends_are_only_visible_when_requested() {
+        let api = FriendsApiMock::new().with_friend("Public.1234", true);
+        let mut metadata = FriendRequestMetadata { api_keys: vec!["key".to_string()], public_friends: Vec::new() };
+        api.set_public(metadata.clone(), "key", true, false).unwrap();
+
+        // The friend shares with all accounts, but our key has no account without a subtoken.
+        assert!(api.get_state(metadata.clone()).unwrap().friends().is_empty());
+
+        metadata.public_friends = vec!["Public.1234".to_string(), "Unknown.5678".to_string()];
+        let state = api.get_state(metadata).unwrap();
+        assert!(state.friends()[0].public());
+        assert!(!state.friends()[1].known());
+        assert!(state.keys()[0].public());
+    }
+}
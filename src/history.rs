@@ -0,0 +1,184 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::clears::last_raid_reset;
+
+/// Weeks older than this are removed from the history.
+const MAX_WEEKS: usize = 104;
+
+/// Finished encounters of accounts for past raid weeks.
+#[derive(Serialize, Deserialize)]
+pub struct ClearHistory {
+    /// Sorted from the newest week.
+    weeks: Vec<WeekHistory>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct WeekHistory {
+    /// The raid reset that started this week.
+    start: DateTime<Utc>,
+    /// Finished encounter ids by account name.
+    accounts: BTreeMap<String, Vec<String>>,
+}
+
+impl WeekHistory {
+    pub fn start(&self) -> DateTime<Utc> {
+        self.start
+    }
+    pub fn finished_encounter_ids(&self, account_name: &str) -> Option<&Vec<String>> {
+        self.accounts.get(account_name)
+    }
+    pub fn is_finished(&self, account_name: &str, encounter_id: &str) -> bool {
+        self.accounts.get(account_name)
+            .map(|ids| ids.iter().any(|x| x == encounter_id))
+            .unwrap_or(false)
+    }
+}
+
+impl ClearHistory {
+    pub fn new() -> Self {
+        ClearHistory { weeks: Vec::new() }
+    }
+
+    pub fn weeks(&self) -> &Vec<WeekHistory> {
+        &self.weeks
+    }
+
+    /// All accounts that have any recorded week, sorted by name.
+    pub fn accounts(&self) -> Vec<&str> {
+        let mut accounts: Vec<&str> = self.weeks.iter()
+            .flat_map(|week| week.accounts.keys().map(|x| x.as_str()))
+            .collect();
+        accounts.sort_unstable();
+        accounts.dedup();
+        accounts
+    }
+
+    /// Records encounters finished by an account in the raid week of `time`.
+    ///
+    /// Clears only accumulate during a week, so previously recorded encounters are kept.
+    pub fn record(&mut self, account_name: &str, time: DateTime<Utc>, finished_encounter_ids: &[String]) {
+        let start = last_raid_reset(time);
+        let index = match self.weeks.iter().position(|week| week.start <= start) {
+            Some(index) if self.weeks[index].start == start => index,
+            Some(index) => {
+                self.weeks.insert(index, WeekHistory { start, accounts: BTreeMap::new() });
+                index
+            }
+            None => {
+                self.weeks.push(WeekHistory { start, accounts: BTreeMap::new() });
+                self.weeks.len() - 1
+            }
+        };
+
+        let ids = self.weeks[index].accounts.entry(account_name.to_string()).or_insert_with(Vec::new);
+        for id in finished_encounter_ids {
+            if !ids.contains(id) {
+                ids.push(id.clone());
+            }
+        }
+
+        self.weeks.truncate(MAX_WEEKS);
+    }
+
+    /// Adds all records from another history, used when the file is loaded after new records were made.
+    pub fn merge(&mut self, other: ClearHistory) {
+        for week in other.weeks {
+            for (account_name, ids) in week.accounts {
+                self.record(&account_name, week.start, &ids);
+            }
+        }
+    }
+
+    /// The number of consecutive weeks in which the account finished all of the encounters.
+    ///
+    /// The current week only counts once it is done, an unfinished current week does not break the streak.
+    pub fn streak(&self, account_name: &str, encounter_ids: &[&str], now: DateTime<Utc>) -> u32 {
+        let all_finished = |week: &WeekHistory| encounter_ids.iter().all(|id| week.is_finished(account_name, id));
+
+        let current_week = last_raid_reset(now);
+        let mut streak = 0;
+        if self.weeks.iter().any(|week| week.start == current_week && all_finished(week)) {
+            streak += 1;
+        }
+
+        let mut expected_start = current_week - Duration::weeks(1);
+        for week in self.weeks.iter().filter(|week| week.start < current_week) {
+            if week.start != expected_start || !all_finished(week) {
+                break;
+            }
+            streak += 1;
+            expected_start = expected_start - Duration::weeks(1);
+        }
+        streak
+    }
+
+    pub fn load_from_file(filename: &str) -> Result<Self, Box<dyn Error>> {
+        let mut file = File::open(filename)?;
+        let mut history_json = String::new();
+        file.read_to_string(&mut history_json)?;
+        Ok(serde_json::from_str(&history_json)?)
+    }
+
+    pub fn save_to_file(&self, filename: &str) -> Result<(), Box<dyn Error>> {
+        // We first serialize the history into a temporary file and then move the file
+        let tmp_filename = format!("{}.tmp", filename);
+        let tmp_file = File::create(&tmp_filename)?;
+        let mut writer = BufWriter::new(tmp_file);
+        serde_json::to_writer(&mut writer, &self)?;
+        writer.flush()?;
+
+        std::fs::rename(tmp_filename, filename)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use super::*;
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn records_are_merged_per_week() {
+        let mut history = ClearHistory::new();
+        let monday = Utc.ymd(2021, 12, 27).and_hms(12, 0, 0);
+        history.record("Test.1234", monday, &ids(&["vale_guardian"]));
+        history.record("Test.1234", monday + Duration::days(2), &ids(&["gorseval", "vale_guardian"]));
+        history.record("Test.1234", monday - Duration::weeks(2), &ids(&["sabetha"]));
+        history.record("Friend.5678", monday - Duration::weeks(1), &ids(&[]));
+
+        assert_eq!(3, history.weeks().len());
+        assert_eq!(Utc.ymd(2021, 12, 27).and_hms(7, 30, 0), history.weeks()[0].start());
+        assert_eq!(2, history.weeks()[0].finished_encounter_ids("Test.1234").unwrap().len());
+        assert!(history.weeks()[1].finished_encounter_ids("Test.1234").is_none());
+        assert!(history.weeks()[2].is_finished("Test.1234", "sabetha"));
+        assert_eq!(vec!["Friend.5678", "Test.1234"], history.accounts());
+    }
+
+    #[test]
+    fn streak_counts_consecutive_full_weeks() {
+        let mut history = ClearHistory::new();
+        let now = Utc.ymd(2021, 12, 29).and_hms(12, 0, 0);
+        let bosses = ["vale_guardian", "gorseval"];
+        history.record("Test.1234", now, &ids(&["vale_guardian"]));
+        history.record("Test.1234", now - Duration::weeks(1), &ids(&bosses));
+        history.record("Test.1234", now - Duration::weeks(2), &ids(&bosses));
+        history.record("Test.1234", now - Duration::weeks(4), &ids(&bosses));
+
+        // The unfinished current week does not break the streak, the missing week does.
+        assert_eq!(2, history.streak("Test.1234", &bosses, now));
+
+        history.record("Test.1234", now, &ids(&bosses));
+        assert_eq!(3, history.streak("Test.1234", &bosses, now));
+        assert_eq!(0, history.streak("Unknown.1234", &bosses, now));
+    }
+}
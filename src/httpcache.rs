@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::Write as FmtWrite;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use ureq::Request;
+
+/// A previously received response that can be reused if the server
+/// tells us it has not changed (`304 Not Modified`).
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct CachedResponse {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    body: String,
+}
+
+impl CachedResponse {
+    pub fn new(etag: Option<String>, last_modified: Option<String>, body: String) -> Self {
+        CachedResponse { etag, last_modified, body }
+    }
+
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+
+    /// Whether a conditional request can be made for this response.
+    pub fn has_validators(&self) -> bool {
+        self.etag.is_some() || self.last_modified.is_some()
+    }
+
+    /// Makes the request conditional, the server responds with `304 Not Modified`
+    /// if this response is still up to date.
+    pub fn add_validators(&self, mut request: Request) -> Request {
+        if let Some(etag) = &self.etag {
+            request = request.set("If-None-Match", etag);
+        }
+        if let Some(last_modified) = &self.last_modified {
+            request = request.set("If-Modified-Since", last_modified);
+        }
+        request
+    }
+
+    pub fn load_from_file(filename: &str) -> Result<Self, Box<dyn Error>> {
+        let mut file = File::open(filename)?;
+        let mut json = String::new();
+        file.read_to_string(&mut json)?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    pub fn save_to_file(&self, filename: &str) -> Result<(), Box<dyn Error>> {
+        // We first serialize into a temporary file and then move the file
+        let tmp_filename = format!("{}.tmp", filename);
+        let tmp_file = File::create(&tmp_filename)?;
+        let mut writer = BufWriter::new(tmp_file);
+        serde_json::to_writer(&mut writer, &self)?;
+        writer.flush()?;
+
+        std::fs::rename(tmp_filename, filename)?;
+        Ok(())
+    }
+}
+
+/// Responses kept in memory, keyed by the request path and the API key used.
+pub struct HttpCache {
+    entries: Mutex<HashMap<String, CachedResponse>>,
+}
+
+impl HttpCache {
+    pub fn new() -> Self {
+        HttpCache { entries: Mutex::new(HashMap::new()) }
+    }
+
+    /// Responses differ for each API key, so the key is a part of the cache key.
+    /// We only keep a hash of the API key to avoid having more copies of it around.
+    pub fn cache_key(path: &str, api_key: Option<&str>) -> String {
+        let mut cache_key = path.to_string();
+        if let Some(api_key) = api_key {
+            write!(cache_key, "#{:x}", Sha256::digest(api_key.as_bytes())).unwrap();
+        }
+        cache_key
+    }
+
+    pub fn get(&self, cache_key: &str) -> Option<CachedResponse> {
+        self.entries.lock().unwrap().get(cache_key).cloned()
+    }
+
+    pub fn insert(&self, cache_key: String, response: CachedResponse) {
+        self.entries.lock().unwrap().insert(cache_key, response);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_key_differs_per_api_key() {
+        let first = HttpCache::cache_key("v2/account/raids", Some("first-key"));
+        let second = HttpCache::cache_key("v2/account/raids", Some("second-key"));
+        assert_ne!(first, second);
+        assert!(!first.contains("first-key"));
+        assert_eq!("v2/raids?ids=all", HttpCache::cache_key("v2/raids?ids=all", None));
+    }
+
+    #[test]
+    fn cache_returns_inserted_response() {
+        let cache = HttpCache::new();
+        let key = HttpCache::cache_key("v2/account", Some("key"));
+        let response = CachedResponse::new(Some("\"abc\"".to_string()), None, "{}".to_string());
+        assert!(cache.get(&key).is_none());
+        cache.insert(key.clone(), response.clone());
+        assert_eq!(Some(response), cache.get(&key));
+    }
+
+    #[test]
+    fn response_without_headers_has_no_validators() {
+        assert!(!CachedResponse::new(None, None, "[]".to_string()).has_validators());
+        assert!(CachedResponse::new(None, Some("Fri, 21 May 2021 08:35:00 GMT".to_string()), "[]".to_string()).has_validators());
+    }
+}
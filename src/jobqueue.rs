@@ -0,0 +1,289 @@
+use std::collections::{HashSet, VecDeque};
+use std::hash::Hash;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum JobPriority {
+    /// Periodic refreshes done in the background.
+    Background,
+    /// Jobs requested by the user, these are processed before background jobs.
+    User,
+}
+
+#[derive(Debug)]
+pub struct QueueClosed;
+
+/// A job queue that does not contain the same job twice.
+///
+/// Pushing a job that is already pending does nothing, unless it has a higher priority,
+/// in which case the pending job is moved ahead to the higher priority.
+///
+/// Jobs taken from the queue are considered unfinished until the consumer calls
+/// [`JobQueue::finish`], which allows other jobs to wait for them with [`JobQueue::push_after`].
+pub struct JobQueue<T> {
+    state: Mutex<QueueState<T>>,
+    available: Condvar,
+}
+
+struct QueueState<T> {
+    user: VecDeque<T>,
+    background: VecDeque<T>,
+    pending: HashSet<T>,
+    /// Jobs that were taken, but not finished yet. May contain duplicates.
+    unfinished: Vec<T>,
+    waiting: Vec<WaitingJob<T>>,
+    closed: bool,
+}
+
+/// A job that is queued only once no job matching the prerequisite is pending or unfinished.
+struct WaitingJob<T> {
+    job: T,
+    priority: JobPriority,
+    prerequisite: fn(&T) -> bool,
+}
+
+impl<T: Clone + Eq + Hash> JobQueue<T> {
+    pub fn new() -> Self {
+        JobQueue {
+            state: Mutex::new(QueueState {
+                user: VecDeque::new(),
+                background: VecDeque::new(),
+                pending: HashSet::new(),
+                unfinished: Vec::new(),
+                waiting: Vec::new(),
+                closed: false,
+            }),
+            available: Condvar::new(),
+        }
+    }
+
+    pub fn push(&self, job: T, priority: JobPriority) -> Result<(), QueueClosed> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return Err(QueueClosed);
+        }
+
+        state.enqueue(job, priority);
+        self.available.notify_one();
+        Ok(())
+    }
+
+    /// Pushes a job once there are no pending or unfinished jobs matching the prerequisite.
+    ///
+    /// Jobs that are pushed while this job is waiting are also waited for.
+    pub fn push_after(&self, job: T, priority: JobPriority, prerequisite: fn(&T) -> bool) -> Result<(), QueueClosed> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return Err(QueueClosed);
+        }
+
+        if state.waiting.iter().any(|x| x.job == job) {
+            return Ok(());
+        }
+        state.waiting.push(WaitingJob { job, priority, prerequisite });
+        if state.release_waiting() {
+            self.available.notify_all();
+        }
+        Ok(())
+    }
+
+    /// Marks a job taken from the queue as finished, releasing jobs that were waiting for it.
+    ///
+    /// Jobs that are retried later should only be finished after the last attempt.
+    pub fn finish(&self, job: &T) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(index) = state.unfinished.iter().position(|x| x == job) {
+            state.unfinished.swap_remove(index);
+        }
+        if state.release_waiting() {
+            self.available.notify_all();
+        }
+    }
+
+    /// Takes the next job, blocking until one is available.
+    /// Returns `None` if the queue was closed.
+    pub fn pop(&self) -> Option<T> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.closed {
+                return None;
+            }
+            if let Some(job) = state.take_next() {
+                return Some(job);
+            }
+            state = self.available.wait(state).unwrap();
+        }
+    }
+
+    /// Takes the next job, waiting at most `timeout` for one to become available.
+    /// Returns `Ok(None)` if the timeout elapsed.
+    pub fn pop_timeout(&self, timeout: Duration) -> Result<Option<T>, QueueClosed> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.closed {
+                return Err(QueueClosed);
+            }
+            if let Some(job) = state.take_next() {
+                return Ok(Some(job));
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            state = self.available.wait_timeout(state, deadline - now).unwrap().0;
+        }
+    }
+
+    /// Closes the queue, waking up all waiting consumers. Pending jobs are dropped.
+    pub fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        state.user.clear();
+        state.background.clear();
+        state.pending.clear();
+        state.waiting.clear();
+        self.available.notify_all();
+    }
+}
+
+impl<T: Clone + Eq + Hash> QueueState<T> {
+    fn take_next(&mut self) -> Option<T> {
+        let job = self.user.pop_front().or_else(|| self.background.pop_front())?;
+        self.pending.remove(&job);
+        self.unfinished.push(job.clone());
+        Some(job)
+    }
+
+    fn enqueue(&mut self, job: T, priority: JobPriority) {
+        if self.pending.contains(&job) {
+            if priority == JobPriority::User {
+                if let Some(index) = self.background.iter().position(|x| *x == job) {
+                    self.background.remove(index);
+                    self.user.push_back(job);
+                }
+            }
+            return;
+        }
+
+        self.pending.insert(job.clone());
+        match priority {
+            JobPriority::User => self.user.push_back(job),
+            JobPriority::Background => self.background.push_back(job),
+        }
+    }
+
+    /// Queues waiting jobs that have no prerequisites left. Returns `true` if any were queued.
+    fn release_waiting(&mut self) -> bool {
+        let mut released = false;
+        let mut index = 0;
+        while index < self.waiting.len() {
+            let prerequisite = self.waiting[index].prerequisite;
+            let blocked = self.user.iter()
+                .chain(self.background.iter())
+                .chain(self.unfinished.iter())
+                .any(|x| prerequisite(x));
+            if blocked {
+                index += 1;
+            } else {
+                let waiting = self.waiting.remove(index);
+                self.enqueue(waiting.job, waiting.priority);
+                released = true;
+            }
+        }
+        released
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duplicate_jobs_are_not_queued() {
+        let queue = JobQueue::new();
+        queue.push(1, JobPriority::Background).unwrap();
+        queue.push(2, JobPriority::Background).unwrap();
+        queue.push(1, JobPriority::Background).unwrap();
+
+        assert_eq!(Some(1), queue.pop());
+        assert_eq!(Some(2), queue.pop());
+        assert_eq!(Ok(None), queue.pop_timeout(Duration::from_millis(0)).map_err(|_| ()));
+    }
+
+    #[test]
+    fn job_can_be_queued_again_after_being_taken() {
+        let queue = JobQueue::new();
+        queue.push(1, JobPriority::Background).unwrap();
+        assert_eq!(Some(1), queue.pop());
+        queue.push(1, JobPriority::Background).unwrap();
+        assert_eq!(Some(1), queue.pop());
+    }
+
+    #[test]
+    fn user_jobs_are_taken_first() {
+        let queue = JobQueue::new();
+        queue.push(1, JobPriority::Background).unwrap();
+        queue.push(2, JobPriority::User).unwrap();
+        queue.push(3, JobPriority::Background).unwrap();
+        queue.push(4, JobPriority::User).unwrap();
+
+        assert_eq!(Some(2), queue.pop());
+        assert_eq!(Some(4), queue.pop());
+        assert_eq!(Some(1), queue.pop());
+        assert_eq!(Some(3), queue.pop());
+    }
+
+    #[test]
+    fn pending_background_job_is_moved_ahead_by_user() {
+        let queue = JobQueue::new();
+        queue.push(1, JobPriority::Background).unwrap();
+        queue.push(2, JobPriority::Background).unwrap();
+        queue.push(2, JobPriority::User).unwrap();
+
+        assert_eq!(Some(2), queue.pop());
+        assert_eq!(Some(1), queue.pop());
+    }
+
+    #[test]
+    fn closed_queue_rejects_jobs() {
+        let queue = JobQueue::new();
+        queue.push(1, JobPriority::Background).unwrap();
+        queue.close();
+
+        assert!(queue.push(2, JobPriority::User).is_err());
+        assert_eq!(None, queue.pop());
+        assert!(queue.pop_timeout(Duration::from_secs(1)).is_err());
+    }
+
+    #[test]
+    fn job_waits_for_unfinished_prerequisites() {
+        let queue = JobQueue::new();
+        queue.push(1, JobPriority::Background).unwrap();
+        queue.push(2, JobPriority::Background).unwrap();
+        queue.push_after(10, JobPriority::User, |x| *x < 10).unwrap();
+
+        assert_eq!(Some(1), queue.pop());
+        queue.finish(&1);
+        assert_eq!(Some(2), queue.pop());
+        assert_eq!(Ok(None), queue.pop_timeout(Duration::from_millis(0)).map_err(|_| ()));
+
+        // Jobs pushed while processing a prerequisite are waited for as well.
+        queue.push(3, JobPriority::Background).unwrap();
+        queue.finish(&2);
+        assert_eq!(Some(3), queue.pop());
+        queue.finish(&3);
+        assert_eq!(Some(10), queue.pop());
+    }
+
+    #[test]
+    fn job_without_prerequisites_is_queued_immediately() {
+        let queue = JobQueue::new();
+        queue.push(20, JobPriority::Background).unwrap();
+        queue.push_after(10, JobPriority::User, |x| *x < 10).unwrap();
+
+        assert_eq!(Some(10), queue.pop());
+        assert_eq!(Some(20), queue.pop());
+    }
+}
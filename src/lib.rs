@@ -1,16 +1,23 @@
-use arcdps::arcdps_export;
+use arcdps::{arcdps_export, Agent, CombatEvent};
 use arcdps::imgui;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 use lazy_static::lazy_static;
 use crate::clears::ClearData;
 use crate::settings::Settings;
 use crate::translations::{Translation};
-use crate::api::LiveApi;
-use crate::workers::BackgroundWorkers;
+use crate::api::{ApiMock, LiveApi};
+use crate::workers::{ApiJob, BackgroundWorkers};
 use crate::ui::UiState;
 use std::ops::Deref;
-use log::error;
-use crate::friends::{FriendData, FriendsApiClient};
+use log::{error, warn};
+use crate::friends::{FriendData, FriendsApiClient, FriendsApiMock};
+use crate::ratelimit::RateLimiter;
+use crate::shutdown::ShutdownSignal;
+use crate::squad::Squad;
+use crate::bosses::KillDetector;
+use crate::history::ClearHistory;
+use chrono::Utc;
+use uuid::Uuid;
 
 
 mod api;
@@ -23,9 +30,29 @@ mod updates;
 mod input;
 mod friends;
 mod urls;
+mod ratelimit;
+mod shutdown;
+mod jobqueue;
+mod httpcache;
+mod achievements;
+mod squad;
+mod bosses;
+mod history;
+mod export;
+mod summary;
+#[cfg(test)]
+mod testserver;
 
 const SETTINGS_FILENAME: &str = "addons/arcdps/settings_clears.json";
 const TRANSLATION_FILENAME: &str = "addons/arcdps/arcdps_lang_clears.json";
+const RAIDS_CACHE_FILENAME: &str = "addons/arcdps/cache_clears_raids.json";
+const HISTORY_FILENAME: &str = "addons/arcdps/history_clears.json";
+/// Exports get an extension based on the format.
+const EXPORT_FILENAME_BASE: &str = "addons/arcdps/export_clears";
+/// Settings shared with other players, without API keys.
+const SHARED_SETTINGS_FILENAME: &str = "addons/arcdps/shared_settings_clears.json";
+/// If this environment variable is set, mock APIs are used instead of the real ones.
+const MOCK_API_ENV_VAR: &str = "CLEARS_MOCK_API";
 
 arcdps_export! {
     name: "Clears",
@@ -33,6 +60,7 @@ arcdps_export! {
     options_windows: options,
     options_end: options_end,
     imgui: imgui,
+    combat: combat,
     init: init,
     release: release,
     wnd_filter: wnd_filter,
@@ -45,6 +73,7 @@ lazy_static! {
     static ref DATA: Mutex<Data> = Mutex::new(Data::new());
     static ref UI_STATE: Mutex<UiState> = Mutex::new(UiState::new());
     static ref SETTINGS: Mutex<Option<Settings>> = Mutex::new(None);
+    static ref KILL_DETECTOR: Mutex<KillDetector> = Mutex::new(KillDetector::new());
     // We fall back to the default translation before there's an attempt to load a translation.
     static ref TRANSLATION: Mutex<Translation> = Mutex::new(Translation::load_from_string(translations::get_default_translation_contents()).expect("Failed to load default translation!"));
 }
@@ -52,11 +81,13 @@ lazy_static! {
 pub struct Data {
     clears: ClearData,
     friends: FriendData,
+    squad: Squad,
+    history: ClearHistory,
 }
 
 impl Data {
     pub fn new() -> Self {
-        Data { clears: ClearData::new(), friends: FriendData::new() }
+        Data { clears: ClearData::new(), friends: FriendData::new(), squad: Squad::new(), history: ClearHistory::new() }
     }
 }
 
@@ -67,6 +98,22 @@ fn init() {
             *TRANSLATION.lock().unwrap() = translation;
         }
     });
+    std::thread::spawn(move || {
+        // Raids from the previous run let us show the table before the API responds.
+        if let Some(raids) = api::load_cached_raids(RAIDS_CACHE_FILENAME) {
+            let mut data = DATA.lock().unwrap();
+            if data.clears.raids().is_none() {
+                data.clears.set_raids(Some(raids));
+            }
+        }
+    });
+    std::thread::spawn(move || {
+        match ClearHistory::load_from_file(HISTORY_FILENAME) {
+            // Workers may have recorded clears already.
+            Ok(history) => DATA.lock().unwrap().history.merge(history),
+            Err(e) => warn!("Failed to read clear history: {}", e),
+        }
+    });
     settings::load_bg(&SETTINGS, SETTINGS_FILENAME, Some(|| {
         if SETTINGS.lock().unwrap().as_ref().expect("Settings should be loaded by now.").check_updates {
             std::thread::spawn(move || {
@@ -82,16 +129,45 @@ fn init() {
         let friends_api_url = SETTINGS.lock().unwrap().as_ref()
             .expect("Settings should be loaded by now.").friends.friends_api_url.to_string();
 
-        *BACKGROUND_WORKERS.lock().unwrap() = Some(workers::start_workers(
-            &DATA,
-            &SETTINGS,
-            LiveApi::official(),
-            FriendsApiClient::new(friends_api_url),
-        ));
+        // Stopping the workers also cuts short requests waiting for the rate limiter.
+        let shutdown = Arc::new(ShutdownSignal::new());
+        let rate_limiter = Arc::new(RateLimiter::gw2_api().with_shutdown(shutdown.clone()));
+
+        let workers = if std::env::var_os(MOCK_API_ENV_VAR).is_some() {
+            // Used for mock runs, no requests are sent anywhere.
+            workers::start_workers(
+                &DATA,
+                &SETTINGS,
+                ApiMock::new(),
+                FriendsApiMock::new()
+                    .with_friend("Friend.1234", false)
+                    .with_friend("PublicFriend.5678", true),
+                shutdown,
+            )
+        } else {
+            workers::start_workers(
+                &DATA,
+                &SETTINGS,
+                LiveApi::official(rate_limiter.clone()).with_raids_cache_file(RAIDS_CACHE_FILENAME),
+                FriendsApiClient::new(friends_api_url, rate_limiter),
+                shutdown,
+            )
+        };
+        *BACKGROUND_WORKERS.lock().unwrap() = Some(workers);
     }));
 }
 
 fn release() {
+    // The workers are stopped first so that any changes they make to settings are saved.
+    let workers = BACKGROUND_WORKERS.lock().unwrap().take();
+    if let Some(workers) = workers {
+        workers.stop();
+    }
+
+    if let Err(err) = DATA.lock().unwrap().history.save_to_file(HISTORY_FILENAME) {
+        error!("Failed to save clear history: {:?}", err)
+    }
+
     if let Some(settings) = SETTINGS.lock().unwrap().deref() {
         match settings.save_to_file(SETTINGS_FILENAME) {
             Ok(_) => {}
@@ -135,6 +211,67 @@ fn imgui(imgui_ui: &imgui::Ui, not_loading_or_character_selection: bool) {
     );
 }
 
+fn combat(ev: Option<&CombatEvent>, src: Option<Agent>, dst: Option<Agent>, _skill_name: Option<&str>, _id: u64, _revision: u64) {
+    match ev {
+        Some(ev) => detect_kill(ev, src),
+        None => track_squad(src, dst),
+    }
+}
+
+fn detect_kill(ev: &CombatEvent, src: Option<Agent>) {
+    let src_species = src
+        .filter(|src| src.elite == bosses::NPC_ELITE)
+        .map(|src| src.prof);
+    let encounter_id = KILL_DETECTOR.lock().unwrap().process_event(ev.is_statechange, ev.src_agent, src_species);
+
+    if let Some(encounter_id) = encounter_id {
+        let own_account = DATA.lock().unwrap().squad.members().iter()
+            .find(|member| member.is_self())
+            .map(|member| member.account_name().to_string());
+
+        if let Some(own_account) = own_account {
+            let key_uuids: Vec<Uuid> = SETTINGS.lock().unwrap().as_ref()
+                .map(|x| x.api_keys().iter()
+                    .filter(|key| key.data().account_data().as_ref().map(|x| x.name()) == Some(own_account.as_str()))
+                    .map(|key| *key.id())
+                    .collect())
+                .unwrap_or_default();
+
+            let mut data = DATA.lock().unwrap();
+            for uuid in key_uuids {
+                data.clears.mark_unconfirmed_clear(uuid, encounter_id, Utc::now());
+            }
+        }
+    }
+}
+
+fn track_squad(src: Option<Agent>, dst: Option<Agent>) {
+    // Agent notifications come without an event, squad members have an elite of 0
+    // and the account name in the destination agent.
+    let (src, dst) = match (src, dst) {
+        (Some(src), Some(dst)) if src.elite == 0 => (src, dst),
+        _ => return,
+    };
+    let account_name = match dst.name {
+        Some(name) => name,
+        None => return,
+    };
+
+    if src.prof != 0 {
+        let added = DATA.lock().unwrap().squad.add_member(account_name, src.name.unwrap_or_default(), dst.self_ != 0);
+        if added {
+            // New members are requested from the friends server in case their clears are public.
+            if let Some(workers) = BACKGROUND_WORKERS.lock().unwrap().as_ref() {
+                if let Err(_) = workers.background_api_sender().send(ApiJob::UpdateFriendState) {
+                    warn!("Failed to send request to API worker");
+                }
+            }
+        }
+    } else {
+        DATA.lock().unwrap().squad.remove_member(account_name);
+    }
+}
+
 fn options(ui: &imgui::Ui, window_name: Option<&str>) -> bool {
     if window_name.is_none() {
         let tr = TRANSLATION.lock().unwrap();
@@ -165,7 +302,7 @@ fn options_end(ui: &imgui::Ui) {
 
 
 fn wnd_filter(key: usize, key_down: bool, _prev_key_down: bool) -> bool {
-    if let Some(settings) = SETTINGS.lock().unwrap().as_ref() {
+    if let Some(settings) = SETTINGS.lock().unwrap().as_mut() {
         if let Some(main_window_keybind) = settings.keybinds.main_window {
             if key_down && key == main_window_keybind {
                 let shown = UI_STATE.lock().unwrap().main_window.shown;
@@ -181,6 +318,13 @@ fn wnd_filter(key: usize, key_down: bool, _prev_key_down: bool) -> bool {
                 return false;
             }
         }
+
+        if let Some(next_profile_keybind) = settings.keybinds.next_profile {
+            if key_down && key == next_profile_keybind {
+                settings.switch_to_next_profile();
+                return false;
+            }
+        }
     }
 
     true
@@ -0,0 +1,171 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::shutdown::{ShutdownSignal, Stopped};
+
+const BACKOFF_BASE: Duration = Duration::from_secs(5);
+const BACKOFF_MAX: Duration = Duration::from_secs(10 * 60);
+
+/// A token bucket limiting how many requests we send.
+/// Each request takes a token, tokens are refilled over time up to the capacity.
+pub struct RateLimiter {
+    capacity: f64,
+    refill_per_second: f64,
+    bucket: Mutex<Bucket>,
+    /// Waiting for a token is cut short when this is stopped.
+    shutdown: Arc<ShutdownSignal>,
+}
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(capacity: u32, refill_per_second: f64) -> Self {
+        RateLimiter {
+            capacity: capacity as f64,
+            refill_per_second,
+            bucket: Mutex::new(Bucket { tokens: capacity as f64, last_refill: Instant::now() }),
+            shutdown: Arc::new(ShutdownSignal::new()),
+        }
+    }
+
+    pub fn with_shutdown(mut self, shutdown: Arc<ShutdownSignal>) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    pub fn gw2_api() -> Self {
+        // The GW2 API allows bursts of 300 requests and refills 5 requests per second.
+        // The limit is shared with all other tools running on the same IP,
+        // so we only use a part of it.
+        Self::new(100, 2.5)
+    }
+
+    /// Takes a token, blocking until one is available or a shutdown is requested.
+    pub fn acquire(&self) -> Result<(), Stopped> {
+        loop {
+            if self.shutdown.is_stopped() {
+                return Err(Stopped);
+            }
+            match self.try_acquire_at(Instant::now()) {
+                Ok(()) => return Ok(()),
+                Err(wait) => {
+                    self.shutdown.wait(wait);
+                }
+            }
+        }
+    }
+
+    /// Takes a token if one is available, otherwise returns how long to wait for the next one.
+    fn try_acquire_at(&self, now: Instant) -> Result<(), Duration> {
+        let mut bucket = self.bucket.lock().unwrap();
+        let elapsed = now.saturating_duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.refill_per_second).min(self.capacity);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.refill_per_second))
+        }
+    }
+}
+
+/// The delay before retrying a job that failed `attempt` times in a row (starting from 0).
+///
+/// The delay grows exponentially and is randomized to avoid retrying many jobs at once.
+/// If the server told us when to retry, we never retry sooner than that.
+pub fn backoff_delay(attempt: u32, retry_after: Option<Duration>) -> Duration {
+    let exponential = BACKOFF_BASE.checked_mul(1 << attempt.min(16)).unwrap_or(BACKOFF_MAX).min(BACKOFF_MAX);
+    let jittered = exponential.mul_f64(0.5 + jitter() / 2.0);
+
+    match retry_after {
+        Some(retry_after) => jittered.max(retry_after),
+        None => jittered,
+    }
+}
+
+/// A number in [0, 1).
+fn jitter() -> f64 {
+    // The hasher keys are randomly seeded, which is good enough for spreading out retries.
+    let random = RandomState::new().build_hasher().finish();
+    (random % 1_000_000) as f64 / 1_000_000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limiter_allows_burst_up_to_capacity() {
+        let limiter = RateLimiter::new(3, 1.0);
+        let now = Instant::now();
+        assert!(limiter.try_acquire_at(now).is_ok());
+        assert!(limiter.try_acquire_at(now).is_ok());
+        assert!(limiter.try_acquire_at(now).is_ok());
+        assert!(limiter.try_acquire_at(now).is_err());
+    }
+
+    #[test]
+    fn limiter_refills_over_time() {
+        let limiter = RateLimiter::new(1, 2.0);
+        let now = Instant::now();
+        assert!(limiter.try_acquire_at(now).is_ok());
+        let wait = limiter.try_acquire_at(now).unwrap_err();
+        assert_eq!(Duration::from_millis(500), wait);
+        assert!(limiter.try_acquire_at(now + Duration::from_millis(500)).is_ok());
+    }
+
+    #[test]
+    fn limiter_does_not_refill_above_capacity() {
+        let limiter = RateLimiter::new(2, 10.0);
+        let later = Instant::now() + Duration::from_secs(60);
+        assert!(limiter.try_acquire_at(later).is_ok());
+        assert!(limiter.try_acquire_at(later).is_ok());
+        assert!(limiter.try_acquire_at(later).is_err());
+    }
+
+    #[test]
+    fn stopped_limiter_does_not_wait() {
+        let shutdown = Arc::new(ShutdownSignal::new());
+        let limiter = RateLimiter::new(1, 0.001).with_shutdown(shutdown.clone());
+        assert!(limiter.acquire().is_ok());
+
+        let stopper = std::thread::spawn({
+            let shutdown = shutdown.clone();
+            move || {
+                std::thread::sleep(Duration::from_millis(50));
+                shutdown.stop();
+            }
+        });
+        // The next token would take over 15 minutes.
+        assert!(limiter.acquire().is_err());
+        stopper.join().unwrap();
+    }
+
+    #[test]
+    fn backoff_grows_exponentially() {
+        for attempt in 0..5 {
+            let delay = backoff_delay(attempt, None);
+            let full = BACKOFF_BASE * 2u32.pow(attempt);
+            assert!(delay >= full / 2, "attempt {}: {:?}", attempt, delay);
+            assert!(delay <= full, "attempt {}: {:?}", attempt, delay);
+        }
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        assert!(backoff_delay(30, None) <= BACKOFF_MAX);
+    }
+
+    #[test]
+    fn backoff_respects_retry_after() {
+        let retry_after = Duration::from_secs(120);
+        assert!(backoff_delay(0, Some(retry_after)) >= retry_after);
+    }
+}
@@ -11,6 +11,7 @@ pub fn my_clears_style() -> ClearsStyle {
         show_clears_table_row_names: true,
         finished_clear_color: FINISHED_COLOR,
         unfinished_clear_color: UNFINISHED_COLOR,
+        sort_matrix_by_needed: sort_matrix_by_needed(),
     }
 }
 
@@ -22,9 +23,14 @@ pub fn friends_clears_style() -> ClearsStyle {
         show_clears_table_row_names: true,
         finished_clear_color: FINISHED_COLOR,
         unfinished_clear_color: UNFINISHED_COLOR,
+        sort_matrix_by_needed: sort_matrix_by_needed(),
     }
 }
 
+pub fn sort_matrix_by_needed() -> bool {
+    true
+}
+
 pub fn check_updates() -> bool {
     true
 }
@@ -77,9 +83,26 @@ pub fn keybinds() -> Keybinds {
         // can change the conflicting keybind in our plugin or in arcdps itself if needed.
         main_window: Some(67),
         api_window: None,
+        next_profile: next_profile_keybind(),
     }
 }
 
+pub fn next_profile_keybind() -> Option<usize> {
+    None
+}
+
+pub fn profiles() -> Vec<SettingsProfile> {
+    Vec::new()
+}
+
+pub fn active_profile() -> Option<String> {
+    None
+}
+
+pub fn encrypted_keys() -> Option<EncryptedKeys> {
+    None
+}
+
 pub mod friends {
     use super::*;
 
@@ -88,6 +111,8 @@ pub mod friends {
             enabled: enabled(),
             list: friend_list(),
             friends_api_url: api_url(),
+            make_keys_public_pending: make_keys_public_pending(),
+            selected_group: selected_group(),
         }
     }
 
@@ -99,11 +124,28 @@ pub mod friends {
         "https://clears.gw2scratch.com/".to_string()
     }
 
+    pub fn make_keys_public_pending() -> bool {
+        false
+    }
+
+    pub fn selected_group() -> Option<String> {
+        None
+    }
+
     pub fn friend_list() -> FriendList {
         FriendList {
-            friends: Vec::new()
+            friends: Vec::new(),
+            groups: groups(),
         }
     }
+
+    pub fn groups() -> Vec<String> {
+        Vec::new()
+    }
+
+    pub fn friend_groups() -> Vec<String> {
+        Vec::new()
+    }
 }
 
 pub mod feature_ads {
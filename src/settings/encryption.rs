@@ -0,0 +1,207 @@
+use std::convert::TryInto;
+use std::error::Error;
+
+use argon2::Argon2;
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::aead::rand_core::RngCore;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::settings::{ApiKey, Settings};
+
+const SALT_LENGTH: usize = 16;
+
+/// API keys encrypted with a key derived from a passphrase, stored instead of the keys.
+#[derive(Serialize, Deserialize)]
+pub struct EncryptedKeys {
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// The key derived from the passphrase, it is only ever kept in memory.
+pub struct KeyCipher {
+    key: [u8; 32],
+    salt: [u8; SALT_LENGTH],
+}
+
+impl KeyCipher {
+    fn new(passphrase: &str) -> Result<Self, Box<dyn Error>> {
+        let mut salt = [0u8; SALT_LENGTH];
+        OsRng.fill_bytes(&mut salt);
+        KeyCipher::with_salt(passphrase, salt)
+    }
+
+    fn with_salt(passphrase: &str, salt: [u8; SALT_LENGTH]) -> Result<Self, Box<dyn Error>> {
+        let mut key = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|e| e.to_string())?;
+        Ok(KeyCipher { key, salt })
+    }
+
+    fn encrypt(&self, plaintext: &[u8]) -> Result<EncryptedKeys, Box<dyn Error>> {
+        // A new nonce is used for every save.
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = XChaCha20Poly1305::new(Key::from_slice(&self.key))
+            .encrypt(&nonce, plaintext)
+            .map_err(|_| "Failed to encrypt API keys")?;
+        Ok(EncryptedKeys {
+            salt: to_hex(&self.salt),
+            nonce: to_hex(&nonce),
+            ciphertext: to_hex(&ciphertext),
+        })
+    }
+}
+
+impl EncryptedKeys {
+    /// Returns the decrypted data along with the cipher, which is used for later saves.
+    fn decrypt(&self, passphrase: &str) -> Result<(KeyCipher, Vec<u8>), Box<dyn Error>> {
+        let salt: [u8; SALT_LENGTH] = from_hex(&self.salt)?.as_slice().try_into().map_err(|_| "Invalid salt")?;
+        let nonce = from_hex(&self.nonce)?;
+        if nonce.len() != 24 {
+            return Err("Invalid nonce".into());
+        }
+
+        let cipher = KeyCipher::with_salt(passphrase, salt)?;
+        let plaintext = XChaCha20Poly1305::new(Key::from_slice(&cipher.key))
+            .decrypt(XNonce::from_slice(&nonce), from_hex(&self.ciphertext)?.as_slice())
+            .map_err(|_| "Wrong passphrase")?;
+        Ok((cipher, plaintext))
+    }
+}
+
+impl Settings {
+    /// Keys are encrypted and have not been unlocked with the passphrase yet.
+    /// There are no keys from the settings file until they are unlocked.
+    pub fn keys_locked(&self) -> bool {
+        self.encrypted_keys.is_some()
+    }
+
+    pub fn key_encryption_enabled(&self) -> bool {
+        self.key_cipher.is_some() || self.encrypted_keys.is_some()
+    }
+
+    pub fn unlock_keys(&mut self, passphrase: &str) -> Result<(), Box<dyn Error>> {
+        let encrypted = match &self.encrypted_keys {
+            Some(encrypted) => encrypted,
+            None => return Ok(()),
+        };
+        let (cipher, plaintext) = encrypted.decrypt(passphrase)?;
+        let mut keys: Vec<ApiKey> = serde_json::from_slice(&plaintext)?;
+
+        // Keys added while locked are kept after the decrypted ones.
+        keys.append(&mut self.api_keys);
+        self.api_keys = keys;
+        self.encrypted_keys = None;
+        self.key_cipher = Some(cipher);
+        Ok(())
+    }
+
+    /// Keys are encrypted with the passphrase from the next save on.
+    pub fn enable_key_encryption(&mut self, passphrase: &str) -> Result<(), Box<dyn Error>> {
+        if self.keys_locked() {
+            return Err("API keys have to be unlocked first".into());
+        }
+        if passphrase.is_empty() {
+            return Err("The passphrase is empty".into());
+        }
+        self.key_cipher = Some(KeyCipher::new(passphrase)?);
+        Ok(())
+    }
+
+    /// Keys are saved in plain text again, this requires them to be unlocked.
+    pub fn disable_key_encryption(&mut self) {
+        if !self.keys_locked() {
+            self.key_cipher = None;
+        }
+    }
+
+    /// Removes encrypted keys that cannot be unlocked, used if the passphrase is lost.
+    /// All other settings are kept, keys have to be added again.
+    pub fn forget_encrypted_keys(&mut self) {
+        self.encrypted_keys = None;
+    }
+
+    /// Settings as they are written to the settings file, with keys encrypted if enabled.
+    pub(super) fn to_file_value(&self) -> Result<Value, Box<dyn Error>> {
+        let mut value = serde_json::to_value(self)?;
+        if let Some(cipher) = &self.key_cipher {
+            let encrypted = cipher.encrypt(&serde_json::to_vec(&self.api_keys)?)?;
+            value["encrypted_keys"] = serde_json::to_value(encrypted)?;
+            value["api_keys"] = Value::Array(Vec::new());
+        }
+        Ok(value)
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    if hex.len() % 2 != 0 {
+        return Err("Invalid hex string length".into());
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| hex.get(i..i + 2).ok_or("Invalid hex string").and_then(|x| u8::from_str_radix(x, 16).map_err(|_| "Invalid hex string")))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|e| e.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_encrypted_in_file() {
+        let mut settings = Settings::default();
+        settings.api_keys.push(ApiKey::new("SECRET-KEY"));
+        settings.enable_key_encryption("passphrase").unwrap();
+
+        let json = serde_json::to_string(&settings.to_file_value().unwrap()).unwrap();
+        assert!(!json.contains("SECRET-KEY"));
+
+        let mut loaded = Settings::from_json(&json).unwrap();
+        assert!(loaded.keys_locked());
+        assert!(loaded.api_keys().is_empty());
+        assert!(loaded.unlock_keys("wrong").is_err());
+        assert!(loaded.keys_locked());
+
+        loaded.unlock_keys("passphrase").unwrap();
+        assert!(!loaded.keys_locked());
+        assert!(loaded.key_encryption_enabled());
+        assert_eq!("SECRET-KEY", loaded.api_keys()[0].key());
+        assert_eq!(settings.api_keys()[0].id(), loaded.api_keys()[0].id());
+    }
+
+    #[test]
+    fn locked_keys_are_kept_when_saving() {
+        let mut settings = Settings::default();
+        settings.api_keys.push(ApiKey::new("SECRET-KEY"));
+        settings.enable_key_encryption("passphrase").unwrap();
+        let json = serde_json::to_string(&settings.to_file_value().unwrap()).unwrap();
+
+        // Saving again without unlocking must not lose the keys.
+        let locked = Settings::from_json(&json).unwrap();
+        let json = serde_json::to_string(&locked.to_file_value().unwrap()).unwrap();
+        let mut loaded = Settings::from_json(&json).unwrap();
+        loaded.unlock_keys("passphrase").unwrap();
+        assert_eq!(1, loaded.api_keys().len());
+
+        loaded.disable_key_encryption();
+        let json = serde_json::to_string(&loaded.to_file_value().unwrap()).unwrap();
+        assert!(json.contains("SECRET-KEY"));
+        assert!(!Settings::from_json(&json).unwrap().key_encryption_enabled());
+    }
+
+    #[test]
+    fn hex_round_trip() {
+        assert_eq!("00ff10", to_hex(&[0, 255, 16]));
+        assert_eq!(vec![0, 255, 16], from_hex("00ff10").unwrap());
+        assert!(from_hex("0g").is_err());
+        assert!(from_hex("abc").is_err());
+    }
+}
@@ -1,11 +1,66 @@
+use std::error::Error;
+
 use chrono::{DateTime, Utc};
-use log::info;
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use uuid::Uuid;
 
 use crate::Settings;
 use crate::settings::{AccountData, AccountHeaderStyle, ApiKey, ApiKeyData, ClearsTableStyle, FeatureAdverts, Keybinds, TokenInfo, TokenType};
 
+/// The version of the settings format, settings without a version are from clears 1.0.0 or earlier.
+pub const CURRENT_VERSION: u32 = 1;
+
+/// A change of the settings format, upgrading settings from the previous version to `version`.
+struct MigrationStep {
+    version: u32,
+    migrate: fn(&mut Map<String, Value>),
+}
+
+/// All migration steps, ordered by version.
+const STEPS: [MigrationStep; 1] = [
+    MigrationStep { version: 1, migrate: migrate_to_1 },
+];
+
+/// Upgrades settings to the current version.
+/// Returns the version settings were migrated from, or `None` if they were already up to date.
+pub fn migrate(settings: &mut Value) -> Result<Option<u32>, Box<dyn Error>> {
+    let settings = settings.as_object_mut().ok_or("Settings are not a JSON object")?;
+    let version = settings.get("schema_version").and_then(|x| x.as_u64()).unwrap_or(0) as u32;
+    if version > CURRENT_VERSION {
+        // Settings from a newer version of the addon, we load what we understand.
+        warn!("Settings version {} is newer than supported version {}", version, CURRENT_VERSION);
+        return Ok(None);
+    }
+    if version == CURRENT_VERSION {
+        return Ok(None);
+    }
+
+    let last_run_version = settings.get("last_run_version")
+        .and_then(|x| x.as_str())
+        .unwrap_or("unknown")
+        .to_string();
+    for step in STEPS.iter().filter(|step| step.version > version) {
+        (step.migrate)(settings);
+        settings.insert("schema_version".to_string(), Value::from(step.version));
+        info!("Migrated settings last used with clears {} to version {}", last_run_version, step.version);
+    }
+    Ok(Some(version))
+}
+
+/// Fields added after clears 1.0.0.
+fn migrate_to_1(settings: &mut Map<String, Value>) {
+    for style in ["my_clears_style", "friends_clears_style"].iter() {
+        if let Some(style) = settings.get_mut(*style).and_then(|x| x.as_object_mut()) {
+            style.entry("sort_matrix_by_needed").or_insert(Value::Bool(true));
+        }
+    }
+    if let Some(keybinds) = settings.get_mut("keybinds").and_then(|x| x.as_object_mut()) {
+        keybinds.entry("next_profile").or_insert(Value::Null);
+    }
+}
+
 pub fn load_old_settings(json: &str) -> Option<Settings> {
     if let Some(settings) = load_0_1_0(json) {
         info!("Migrated clears 0.1 settings");
@@ -184,6 +239,7 @@ pub fn load_0_3(json: &str) -> Option<Settings> {
         settings.keybinds = Keybinds {
             main_window: old_settings.main_window_keybind,
             api_window: old_settings.api_window_keybind,
+            next_profile: None,
         };
 
         settings.close_window_with_escape = old_settings.close_window_with_escape;
@@ -200,3 +256,42 @@ pub fn load_0_3(json: &str) -> Option<Settings> {
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    #[test]
+    fn migrate_to_1_adds_new_fields() {
+        let mut settings = json!({
+            "my_clears_style": { "table_style": "WingRows" },
+            "friends_clears_style": { "table_style": "SingleRow", "sort_matrix_by_needed": false },
+            "keybinds": { "main_window": 67, "api_window": null },
+        });
+        migrate_to_1(settings.as_object_mut().unwrap());
+        assert_eq!(json!(true), settings["my_clears_style"]["sort_matrix_by_needed"]);
+        assert_eq!(json!(false), settings["friends_clears_style"]["sort_matrix_by_needed"]);
+        assert_eq!(Value::Null, settings["keybinds"]["next_profile"]);
+    }
+
+    #[test]
+    fn unversioned_settings_are_migrated_to_current_version() {
+        let mut settings = json!({ "last_run_version": "1.0.0", "keybinds": { "main_window": 67, "api_window": null } });
+        assert_eq!(Some(0), migrate(&mut settings).unwrap());
+        assert_eq!(json!(CURRENT_VERSION), settings["schema_version"]);
+        assert_eq!(Value::Null, settings["keybinds"]["next_profile"]);
+
+        // Current and newer versions are left alone.
+        assert_eq!(None, migrate(&mut settings).unwrap());
+        let mut newer = json!({ "schema_version": CURRENT_VERSION + 1 });
+        assert_eq!(None, migrate(&mut newer).unwrap());
+        assert!(migrate(&mut json!([])).is_err());
+    }
+
+    #[test]
+    fn steps_are_ordered() {
+        assert!(STEPS.windows(2).all(|steps| steps[0].version < steps[1].version));
+        assert_eq!(CURRENT_VERSION, STEPS.last().unwrap().version);
+    }
+}
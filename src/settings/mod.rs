@@ -1,5 +1,7 @@
 mod defaults;
+mod encryption;
 mod migrations;
+mod sharing;
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
@@ -8,11 +10,16 @@ use std::fs::File;
 use std::hash::{Hash, Hasher};
 use std::io::{BufWriter, Read, Write};
 use std::sync::Mutex;
-use log::error;
+use log::{error, info};
 use uuid::Uuid;
 
+use encryption::{EncryptedKeys, KeyCipher};
+pub use sharing::ImportPreview;
+
 #[derive(Serialize, Deserialize)]
 pub struct Settings {
+    /// Version of the settings format, see `migrations`.
+    pub schema_version: u32,
     #[serde(default = "defaults::last_run_version")]
     pub last_run_version: String,
     #[serde(default = "defaults::api_keys")]
@@ -40,7 +47,18 @@ pub struct Settings {
     #[serde(default = "defaults::friends::settings")]
     pub friends: FriendSettings,
     #[serde(default = "defaults::clears_check_interval_minutes")]
-    pub clears_check_interval_minutes: u32
+    pub clears_check_interval_minutes: u32,
+    #[serde(default = "defaults::profiles")]
+    pub profiles: Vec<SettingsProfile>,
+    /// The profile currently applied, changes are saved into it when switching to another one.
+    #[serde(default = "defaults::active_profile")]
+    pub active_profile: Option<String>,
+    /// Set if keys in the file are encrypted and were not unlocked yet.
+    #[serde(default = "defaults::encrypted_keys")]
+    encrypted_keys: Option<EncryptedKeys>,
+    /// Set if keys are encrypted when saved.
+    #[serde(skip)]
+    key_cipher: Option<KeyCipher>,
     // Are you adding a new style option? Make sure to add it to `reset_style()`!
 }
 
@@ -52,6 +70,12 @@ pub struct FriendSettings {
     pub friends_api_url: String,
     #[serde(default = "defaults::friends::friend_list")]
     pub list: FriendList,
+    /// Keys should be made public once their subtokens are uploaded.
+    #[serde(default = "defaults::friends::make_keys_public_pending")]
+    pub make_keys_public_pending: bool,
+    /// The friend group shown in the friends tab, all friends are shown if there is none.
+    #[serde(default = "defaults::friends::selected_group")]
+    pub selected_group: Option<String>,
 }
 
 impl FriendSettings {
@@ -63,10 +87,11 @@ impl FriendSettings {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, PartialEq)]
 pub struct Keybinds {
     pub main_window: Option<usize>,
     pub api_window: Option<usize>,
+    pub next_profile: Option<usize>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -75,7 +100,7 @@ pub struct FeatureAdverts {
     pub friends_shown: bool,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub struct ClearsStyle {
     pub table_style: ClearsTableStyle,
     pub account_header_style: AccountHeaderStyle,
@@ -83,6 +108,8 @@ pub struct ClearsStyle {
     pub show_clears_table_row_names: bool,
     pub finished_clear_color: [f32; 4],
     pub unfinished_clear_color: [f32; 4],
+    /// Encounters needed by the most accounts are shown first in the matrix style.
+    pub sort_matrix_by_needed: bool,
 }
 
 #[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Copy)]
@@ -97,6 +124,8 @@ pub enum ClearsTableStyle {
     WingColumns,
     WingRows,
     SingleRow,
+    /// A single table with encounters as rows and accounts as columns.
+    Matrix,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -260,12 +289,15 @@ pub struct Friend {
     account_name: String,
     show_in_friends: bool,
     expanded_in_friends: bool,
+    /// Names of groups this friend belongs to.
+    #[serde(default = "defaults::friends::friend_groups")]
+    groups: Vec<String>,
 }
 
 #[allow(dead_code)]
 impl Friend {
     pub fn new(account_name: String, show_in_friends: bool) -> Self {
-        Friend { account_name, show_in_friends, expanded_in_friends: true }
+        Friend { account_name, show_in_friends, expanded_in_friends: true, groups: defaults::friends::friend_groups() }
     }
     pub fn account_name(&self) -> &str {
         &self.account_name
@@ -282,11 +314,26 @@ impl Friend {
     pub fn expanded_in_friends_mut(&mut self) -> &mut bool {
         &mut self.expanded_in_friends
     }
+    pub fn groups(&self) -> &Vec<String> {
+        &self.groups
+    }
+    pub fn in_group(&self, group: &str) -> bool {
+        self.groups.iter().any(|x| x == group)
+    }
+    pub fn set_in_group(&mut self, group: &str, in_group: bool) {
+        if in_group && !self.in_group(group) {
+            self.groups.push(group.to_string());
+        } else if !in_group {
+            self.groups.retain(|x| x != group);
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct FriendList {
     friends: Vec<Friend>,
+    #[serde(default = "defaults::friends::groups")]
+    groups: Vec<String>,
 }
 
 impl FriendList {
@@ -305,11 +352,56 @@ impl FriendList {
     pub fn friends_mut(&mut self) -> &mut Vec<Friend> {
         &mut self.friends
     }
+    pub fn groups(&self) -> &Vec<String> {
+        &self.groups
+    }
+    /// Adds a new group, returns `false` if the name is empty or already used.
+    pub fn add_group(&mut self, name: &str) -> bool {
+        let name = name.trim();
+        if name.is_empty() || self.groups.iter().any(|x| x == name) {
+            return false;
+        }
+        self.groups.push(name.to_string());
+        true
+    }
+    /// Removes a group, friends in the group are kept.
+    pub fn remove_group(&mut self, name: &str) {
+        self.groups.retain(|x| x != name);
+        for friend in &mut self.friends {
+            friend.set_in_group(name, false);
+        }
+    }
+    /// Friends in a group, or all friends if there is no group.
+    pub fn friends_in_group_mut<'a>(&'a mut self, group: Option<&'a str>) -> impl Iterator<Item=&'a mut Friend> {
+        self.friends.iter_mut().filter(move |friend| group.map(|group| friend.in_group(group)).unwrap_or(true))
+    }
+}
+
+/// A named set of display settings. API keys and the friend list are shared by all profiles.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SettingsProfile {
+    name: String,
+    short_names: bool,
+    my_clears_style: ClearsStyle,
+    friends_clears_style: ClearsStyle,
+    main_window_show_bg: bool,
+    main_window_show_title: bool,
+    /// Ids of keys hidden in clears, keys added later are shown in all profiles.
+    hidden_keys: Vec<Uuid>,
+    /// Account names of friends hidden in the friends tab.
+    hidden_friends: Vec<String>,
+}
+
+impl SettingsProfile {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
 }
 
 impl Settings {
-    fn default() -> Self {
+    pub(crate) fn default() -> Self {
         Settings {
+            schema_version: migrations::CURRENT_VERSION,
             last_run_version: defaults::last_run_version(),
             api_keys: defaults::api_keys(),
             check_updates: defaults::check_updates(),
@@ -324,6 +416,10 @@ impl Settings {
             feature_adverts: defaults::feature_ads::ads(),
             friends: defaults::friends::settings(),
             clears_check_interval_minutes: defaults::clears_check_interval_minutes(),
+            profiles: defaults::profiles(),
+            active_profile: defaults::active_profile(),
+            encrypted_keys: defaults::encrypted_keys(),
+            key_cipher: None,
             // Are you adding a new style option? Make sure to add to `reset_style()`!
         }
     }
@@ -357,24 +453,125 @@ impl Settings {
         self.short_names
     }
 
+    fn current_profile(&self, name: &str) -> SettingsProfile {
+        SettingsProfile {
+            name: name.to_string(),
+            short_names: self.short_names,
+            my_clears_style: self.my_clears_style.clone(),
+            friends_clears_style: self.friends_clears_style.clone(),
+            main_window_show_bg: self.main_window_show_bg,
+            main_window_show_title: self.main_window_show_title,
+            hidden_keys: self.api_keys.iter().filter(|key| !key.show_key_in_clears).map(|key| key.id).collect(),
+            hidden_friends: self.friends.list.friends.iter()
+                .filter(|friend| !friend.show_in_friends)
+                .map(|friend| friend.account_name.clone())
+                .collect(),
+        }
+    }
+
+    fn apply_profile(&mut self, profile: SettingsProfile) {
+        self.short_names = profile.short_names;
+        self.my_clears_style = profile.my_clears_style;
+        self.friends_clears_style = profile.friends_clears_style;
+        self.main_window_show_bg = profile.main_window_show_bg;
+        self.main_window_show_title = profile.main_window_show_title;
+        for key in &mut self.api_keys {
+            key.show_key_in_clears = !profile.hidden_keys.contains(&key.id);
+        }
+        for friend in &mut self.friends.list.friends {
+            friend.show_in_friends = !profile.hidden_friends.contains(&friend.account_name);
+        }
+        self.active_profile = Some(profile.name);
+    }
+
+    /// Saves current settings as a profile, replacing a profile with the same name.
+    /// Returns `false` if the name is empty.
+    pub fn save_profile(&mut self, name: &str) -> bool {
+        let name = name.trim();
+        if name.is_empty() {
+            return false;
+        }
+        let profile = self.current_profile(name);
+        match self.profiles.iter_mut().find(|x| x.name == name) {
+            Some(existing) => *existing = profile,
+            None => self.profiles.push(profile),
+        }
+        self.active_profile = Some(name.to_string());
+        true
+    }
+
+    /// Applies a profile, current settings are saved into the active profile first.
+    pub fn switch_profile(&mut self, name: &str) -> bool {
+        if !self.profiles.iter().any(|x| x.name == name) {
+            return false;
+        }
+        if let Some(active) = self.active_profile.clone() {
+            let current = self.current_profile(&active);
+            if let Some(existing) = self.profiles.iter_mut().find(|x| x.name == active) {
+                *existing = current;
+            }
+        }
+        let profile = self.profiles.iter().find(|x| x.name == name).cloned().expect("Profile should exist");
+        self.apply_profile(profile);
+        true
+    }
+
+    /// Switches to the profile after the active one, returns its name.
+    pub fn switch_to_next_profile(&mut self) -> Option<String> {
+        if self.profiles.is_empty() {
+            return None;
+        }
+        let next_index = self.active_profile.as_ref()
+            .and_then(|active| self.profiles.iter().position(|x| &x.name == active))
+            .map(|index| (index + 1) % self.profiles.len())
+            .unwrap_or(0);
+        let name = self.profiles[next_index].name.clone();
+        self.switch_profile(&name);
+        Some(name)
+    }
+
+    pub fn remove_profile(&mut self, name: &str) {
+        self.profiles.retain(|x| x.name != name);
+        if self.active_profile.as_deref() == Some(name) {
+            self.active_profile = None;
+        }
+    }
+
     pub fn load_from_file(filename: &str) -> Result<Self, Box<dyn Error>> {
         let mut file = File::open(filename)?;
         let mut settings_json = String::new();
         file.read_to_string(&mut settings_json)?;
 
-        // Try deserialization of settings from older versions first
-        if let Some(settings) = migrations::load_old_settings(&settings_json) {
-            return Ok(settings);
+        let (settings, migrated_from) = Settings::from_json_with_migrations(&settings_json)?;
+        if let Some(version) = migrated_from {
+            // Migrated settings are saved over the original file, which older versions may not read.
+            let backup_filename = format!("{}.v{}.bak", filename, version);
+            match std::fs::copy(filename, &backup_filename) {
+                Ok(_) => info!("Settings from before migration were saved to {}", backup_filename),
+                Err(e) => error!("Failed to back up settings before migration: {}", e),
+            }
         }
+        Ok(settings)
+    }
 
-        let mut settings: Settings = serde_json::from_str(&settings_json)?;
+    /// Deserializes settings of any version, applying migrations if needed.
+    pub fn from_json(settings_json: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(Settings::from_json_with_migrations(settings_json)?.0)
+    }
 
-        // Version-based migrations can be added here,
-        // before we update the last version.
+    /// Also returns the version settings were migrated from, if they were migrated.
+    fn from_json_with_migrations(settings_json: &str) -> Result<(Self, Option<u32>), Box<dyn Error>> {
+        // Try deserialization of settings from versions before 1.0 first
+        if let Some(settings) = migrations::load_old_settings(settings_json) {
+            return Ok((settings, Some(0)));
+        }
 
+        let mut settings_value: serde_json::Value = serde_json::from_str(settings_json)?;
+        let migrated_from = migrations::migrate(&mut settings_value)?;
+        let mut settings: Settings = serde_json::from_value(settings_value)?;
         settings.last_run_version = env!("CARGO_PKG_VERSION").to_string();
 
-        Ok(settings)
+        Ok((settings, migrated_from))
     }
 
     pub fn save_to_file(&self, filename: &str) -> Result<(), Box<dyn Error>> {
@@ -383,7 +580,7 @@ impl Settings {
         let tmp_filename = format!("{}.tmp", filename);
         let tmp_file = File::create(&tmp_filename)?;
         let mut writer = BufWriter::new(tmp_file);
-        serde_json::to_writer(&mut writer, &self)?;
+        serde_json::to_writer(&mut writer, &self.to_file_value()?)?;
         writer.flush()?;
 
         std::fs::rename(tmp_filename, filename)?;
@@ -411,4 +608,62 @@ pub fn load_bg(
             function();
         }
     });
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles_keep_their_own_visibility_and_style() {
+        let mut settings = Settings::default();
+        settings.api_keys.push(ApiKey::new("key"));
+        settings.friends.list.add(Friend::new("Friend.1234".to_string(), true));
+        assert!(settings.save_profile("Raid night"));
+
+        settings.short_names = false;
+        *settings.api_keys[0].show_key_in_clears_mut() = false;
+        *settings.friends.list.friends_mut()[0].show_in_friends_mut() = false;
+        assert!(settings.save_profile(" Alt farming "));
+        assert_eq!(Some("Alt farming"), settings.active_profile.as_deref());
+
+        assert_eq!(Some("Raid night".to_string()), settings.switch_to_next_profile());
+        assert!(settings.short_names);
+        assert!(settings.api_keys[0].show_key_in_clears());
+        assert!(settings.friends.list.friends()[0].show_in_friends());
+
+        // Changes are kept in the profile that was active.
+        settings.my_clears_style.table_style = ClearsTableStyle::Matrix;
+        assert!(settings.switch_profile("Alt farming"));
+        assert!(!settings.short_names);
+        assert!(!settings.api_keys[0].show_key_in_clears());
+        assert!(settings.switch_profile("Raid night"));
+        assert!(settings.my_clears_style.table_style == ClearsTableStyle::Matrix);
+
+        settings.remove_profile("Raid night");
+        assert!(settings.active_profile.is_none());
+        assert!(!settings.switch_profile("Raid night"));
+        assert_eq!(1, settings.profiles.len());
+    }
+
+    #[test]
+    fn migrated_settings_file_is_backed_up() {
+        let filename = std::env::temp_dir().join(format!("settings_clears_{}.json", Uuid::new_v4()));
+        let filename = filename.to_str().unwrap();
+        let old_json = r#"{"last_run_version": "1.0.0", "short_names": false}"#;
+        std::fs::write(filename, old_json).unwrap();
+
+        let settings = Settings::load_from_file(filename).unwrap();
+        assert_eq!(migrations::CURRENT_VERSION, settings.schema_version);
+        assert!(!settings.short_names);
+
+        let backup_filename = format!("{}.v0.bak", filename);
+        assert_eq!(old_json, std::fs::read_to_string(&backup_filename).unwrap());
+
+        // Settings that are up to date are not backed up again.
+        std::fs::remove_file(&backup_filename).unwrap();
+        settings.save_to_file(filename).unwrap();
+        Settings::load_from_file(filename).unwrap();
+        assert!(std::fs::metadata(&backup_filename).is_err());
+        std::fs::remove_file(filename).unwrap();
+    }
+}
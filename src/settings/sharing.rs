@@ -0,0 +1,165 @@
+use std::error::Error;
+
+use serde_json::{Map, Value};
+
+use crate::settings::Settings;
+
+/// Top-level settings included in shared settings, anything else is left out.
+const SHARED_FIELDS: [&str; 11] = [
+    "schema_version",
+    "last_run_version",
+    "short_names",
+    "my_clears_style",
+    "friends_clears_style",
+    "keybinds",
+    "close_window_with_escape",
+    "hide_in_loading_screens",
+    "main_window_show_bg",
+    "main_window_show_title",
+    "clears_check_interval_minutes",
+];
+
+/// Changes that importing shared settings would make.
+pub struct ImportPreview {
+    pub style_changed: bool,
+    pub keybinds_changed: bool,
+    /// Window behavior and the clear check interval.
+    pub behavior_changed: bool,
+    /// Friends that are not in the friend list yet.
+    pub new_friends: Vec<String>,
+    pub new_groups: Vec<String>,
+}
+
+impl ImportPreview {
+    pub fn is_empty(&self) -> bool {
+        !self.style_changed
+            && !self.keybinds_changed
+            && !self.behavior_changed
+            && self.new_friends.is_empty()
+            && self.new_groups.is_empty()
+    }
+}
+
+impl Settings {
+    /// Settings that can be shared with others, API keys and subtokens are never included.
+    ///
+    /// The result can be read with `Settings::from_json`, fields that are left out get their defaults.
+    pub fn export_shared(&self) -> Result<String, serde_json::Error> {
+        let settings = serde_json::to_value(self)?;
+        let mut shared = Map::new();
+        for field in SHARED_FIELDS.iter() {
+            if let Some(value) = settings.get(*field) {
+                shared.insert(field.to_string(), value.clone());
+            }
+        }
+
+        // Only the friend list is shared, the friends server and key status are personal.
+        if let Some(list) = settings.get("friends").and_then(|friends| friends.get("list")) {
+            let mut friends = Map::new();
+            friends.insert("list".to_string(), list.clone());
+            shared.insert("friends".to_string(), Value::Object(friends));
+        }
+
+        serde_json::to_string_pretty(&Value::Object(shared))
+    }
+
+    pub fn export_shared_to_file(&self, filename: &str) -> Result<(), Box<dyn Error>> {
+        std::fs::write(filename, self.export_shared()?)?;
+        Ok(())
+    }
+
+    pub fn import_preview(&self, imported: &Settings) -> ImportPreview {
+        ImportPreview {
+            style_changed: self.short_names != imported.short_names
+                || self.my_clears_style != imported.my_clears_style
+                || self.friends_clears_style != imported.friends_clears_style
+                || self.main_window_show_bg != imported.main_window_show_bg
+                || self.main_window_show_title != imported.main_window_show_title,
+            keybinds_changed: self.keybinds != imported.keybinds,
+            behavior_changed: self.close_window_with_escape != imported.close_window_with_escape
+                || self.hide_in_loading_screens != imported.hide_in_loading_screens
+                || self.clears_check_interval_minutes != imported.clears_check_interval_minutes,
+            new_friends: imported.friends.list.friends().iter()
+                .filter(|friend| self.friends.list.get(friend.account_name()).is_none())
+                .map(|friend| friend.account_name().to_string())
+                .collect(),
+            new_groups: imported.friends.list.groups().iter()
+                .filter(|group| !self.friends.list.groups().contains(group))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Applies shared settings, imported friends and groups are added to the current friend list.
+    pub fn apply_import(&mut self, imported: Settings) {
+        self.short_names = imported.short_names;
+        self.my_clears_style = imported.my_clears_style;
+        self.friends_clears_style = imported.friends_clears_style;
+        self.main_window_show_bg = imported.main_window_show_bg;
+        self.main_window_show_title = imported.main_window_show_title;
+        self.keybinds = imported.keybinds;
+        self.close_window_with_escape = imported.close_window_with_escape;
+        self.hide_in_loading_screens = imported.hide_in_loading_screens;
+        self.clears_check_interval_minutes = imported.clears_check_interval_minutes;
+
+        for group in imported.friends.list.groups() {
+            self.friends.list.add_group(group);
+        }
+        for friend in imported.friends.list.friends {
+            if self.friends.list.get(friend.account_name()).is_none() {
+                self.friends.list.add(friend);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::settings::{ApiKey, ClearsTableStyle, Friend};
+    use super::*;
+
+    #[test]
+    fn export_does_not_contain_keys() {
+        let mut settings = Settings::default();
+        settings.api_keys.push(ApiKey::new("SECRET-KEY"));
+        settings.friends.friends_api_url = "https://example.com/private".to_string();
+        settings.friends.list.add(Friend::new("Friend.1234".to_string(), true));
+        settings.my_clears_style.table_style = ClearsTableStyle::Matrix;
+
+        let json = settings.export_shared().unwrap();
+        assert!(!json.contains("SECRET-KEY"));
+        assert!(!json.contains("api_keys"));
+        assert!(!json.contains("example.com"));
+
+        let imported = Settings::from_json(&json).unwrap();
+        assert!(imported.api_keys().is_empty());
+        assert!(imported.friends.is_url_default());
+        assert!(imported.my_clears_style.table_style == ClearsTableStyle::Matrix);
+        assert!(imported.friends.list.get("Friend.1234").is_some());
+    }
+
+    #[test]
+    fn import_merges_friends() {
+        let mut shared = Settings::default();
+        shared.short_names = false;
+        shared.friends.list.add_group("Static");
+        shared.friends.list.add(Friend::new("Friend.1234".to_string(), true));
+        shared.friends.list.add(Friend::new("New.5678".to_string(), true));
+        let imported = Settings::from_json(&shared.export_shared().unwrap()).unwrap();
+
+        let mut settings = Settings::default();
+        settings.friends.list.add(Friend::new("Friend.1234".to_string(), false));
+        let preview = settings.import_preview(&imported);
+        assert!(preview.style_changed);
+        assert!(!preview.keybinds_changed);
+        assert!(!preview.behavior_changed);
+        assert_eq!(vec!["New.5678".to_string()], preview.new_friends);
+        assert_eq!(vec!["Static".to_string()], preview.new_groups);
+
+        settings.apply_import(imported);
+        assert!(!settings.short_names);
+        assert_eq!(2, settings.friends.list.friends().len());
+        assert!(!settings.friends.list.get("Friend.1234").unwrap().show_in_friends());
+        assert_eq!(&vec!["Static".to_string()], settings.friends.list.groups());
+    }
+}
@@ -0,0 +1,34 @@
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+/// Returned by waits that were cut short because a shutdown was requested.
+#[derive(Debug)]
+pub struct Stopped;
+
+/// Tells background threads to stop, waking them up if they are waiting.
+pub struct ShutdownSignal {
+    stopped: Mutex<bool>,
+    condvar: Condvar,
+}
+
+impl ShutdownSignal {
+    pub fn new() -> Self {
+        ShutdownSignal { stopped: Mutex::new(false), condvar: Condvar::new() }
+    }
+
+    pub fn stop(&self) {
+        *self.stopped.lock().unwrap() = true;
+        self.condvar.notify_all();
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        *self.stopped.lock().unwrap()
+    }
+
+    /// Waits for the specified duration. Returns `true` if a shutdown was requested.
+    pub fn wait(&self, duration: Duration) -> bool {
+        let stopped = self.stopped.lock().unwrap();
+        let (stopped, _) = self.condvar.wait_timeout_while(stopped, duration, |stopped| !*stopped).unwrap();
+        *stopped
+    }
+}
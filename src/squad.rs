@@ -0,0 +1,87 @@
+/// Members of the current squad, tracked from arcdps agent notifications.
+pub struct Squad {
+    members: Vec<SquadMember>,
+}
+
+pub struct SquadMember {
+    account_name: String,
+    character_name: String,
+    is_self: bool,
+    expanded: bool,
+}
+
+impl SquadMember {
+    pub fn account_name(&self) -> &str {
+        &self.account_name
+    }
+    pub fn character_name(&self) -> &str {
+        &self.character_name
+    }
+    pub fn is_self(&self) -> bool {
+        self.is_self
+    }
+    pub fn expanded_mut(&mut self) -> &mut bool {
+        &mut self.expanded
+    }
+}
+
+impl Squad {
+    pub fn new() -> Self {
+        Squad { members: Vec::new() }
+    }
+    pub fn members(&self) -> &Vec<SquadMember> {
+        &self.members
+    }
+    pub fn members_mut(&mut self) -> &mut Vec<SquadMember> {
+        &mut self.members
+    }
+    /// Adds a member, returns `true` if the account was not in the squad yet.
+    pub fn add_member(&mut self, account_name: &str, character_name: &str, is_self: bool) -> bool {
+        let account_name = account_name_from_arcdps(account_name);
+        if let Some(member) = self.members.iter_mut().find(|x| x.account_name == account_name) {
+            // The same account may be added again after swapping characters.
+            member.character_name = character_name.to_string();
+            member.is_self = is_self;
+            return false;
+        }
+
+        self.members.push(SquadMember {
+            account_name: account_name.to_string(),
+            character_name: character_name.to_string(),
+            is_self,
+            expanded: true,
+        });
+        true
+    }
+    pub fn remove_member(&mut self, account_name: &str) {
+        let account_name = account_name_from_arcdps(account_name);
+        self.members.retain(|x| x.account_name != account_name);
+    }
+}
+
+/// Account names from arcdps are prefixed with a colon.
+pub fn account_name_from_arcdps(name: &str) -> &str {
+    name.trim_start_matches(':')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn members_are_tracked_by_account() {
+        let mut squad = Squad::new();
+        assert!(squad.add_member(":Test.1234", "Character", true));
+        assert!(squad.add_member(":Friend.5678", "Friend Character", false));
+        assert!(!squad.add_member(":Friend.5678", "Other Character", false));
+
+        assert_eq!(2, squad.members().len());
+        assert_eq!("Test.1234", squad.members()[0].account_name());
+        assert!(squad.members()[0].is_self());
+        assert_eq!("Other Character", squad.members()[1].character_name());
+
+        squad.remove_member(":Test.1234");
+        assert_eq!(1, squad.members().len());
+        assert_eq!("Friend.5678", squad.members()[0].account_name());
+    }
+}
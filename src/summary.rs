@@ -0,0 +1,90 @@
+//! Compact text summaries of clears meant to be pasted into chat, such as Discord.
+
+use crate::clears::{FinishedEncountersStore, RaidEncounter, RaidWing, RaidWings};
+
+const FINISHED_MARK: &str = "✓";
+const UNFINISHED_MARK: &str = "✗";
+
+/// Names used in summaries, usually short names from the current translation.
+pub struct SummaryNames<'a> {
+    pub wing: &'a dyn Fn(usize, &RaidWing) -> String,
+    pub encounter: &'a dyn Fn(&RaidEncounter) -> String,
+}
+
+/// A line per wing for each account, with marks for finished and unfinished encounters.
+pub fn per_account(raids: &RaidWings, accounts: &[(String, &FinishedEncountersStore)], names: &SummaryNames) -> String {
+    let mut lines = Vec::new();
+    for (account, finished) in accounts {
+        lines.push(format!("**{}**", account));
+        for (index, wing) in raids.wings().iter().enumerate() {
+            let encounters: Vec<String> = wing.encounters().iter()
+                .map(|encounter| {
+                    let mark = if finished.is_finished(encounter) { FINISHED_MARK } else { UNFINISHED_MARK };
+                    format!("{} {}", mark, (names.encounter)(encounter))
+                })
+                .collect();
+            lines.push(format!("{}: {}", (names.wing)(index, wing), encounters.join(" | ")));
+        }
+    }
+    lines.join("\n")
+}
+
+/// A line for each encounter with the accounts that still need it, finished encounters are skipped.
+pub fn missing_by(raids: &RaidWings, accounts: &[(String, &FinishedEncountersStore)], names: &SummaryNames, nobody_missing: &str) -> String {
+    let mut lines = Vec::new();
+    for (index, wing) in raids.wings().iter().enumerate() {
+        for encounter in wing.encounters() {
+            let missing: Vec<&str> = accounts.iter()
+                .filter(|(_, finished)| !finished.is_finished(encounter))
+                .map(|(account, _)| account.as_str())
+                .collect();
+            if !missing.is_empty() {
+                lines.push(format!("{} {} {}: {}", UNFINISHED_MARK, (names.wing)(index, wing), (names.encounter)(encounter), missing.join(", ")));
+            }
+        }
+    }
+    if lines.is_empty() {
+        format!("{} {}", FINISHED_MARK, nobody_missing)
+    } else {
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clears::EncounterType;
+
+    fn raids() -> RaidWings {
+        RaidWings::new(vec![RaidWing::new("spirit_vale".to_string(), vec![
+            RaidEncounter::new("vale_guardian".to_string(), EncounterType::Boss),
+            RaidEncounter::new("gorseval".to_string(), EncounterType::Boss),
+        ])])
+    }
+
+    fn with_names<T>(f: impl Fn(&SummaryNames) -> T) -> T {
+        let wing = |index: usize, _: &RaidWing| format!("W{}", index + 1);
+        let encounter = |encounter: &RaidEncounter| encounter.id().to_string();
+        f(&SummaryNames { wing: &wing, encounter: &encounter })
+    }
+
+    #[test]
+    fn per_account_marks_encounters() {
+        let finished = FinishedEncountersStore::new(vec!["gorseval".to_string()]);
+        let summary = with_names(|names| per_account(&raids(), &[("Test.1234".to_string(), &finished)], names));
+        assert_eq!("**Test.1234**\nW1: ✗ vale_guardian | ✓ gorseval", summary);
+    }
+
+    #[test]
+    fn missing_by_lists_accounts() {
+        let first = FinishedEncountersStore::new(vec!["gorseval".to_string()]);
+        let second = FinishedEncountersStore::new(vec![]);
+        let accounts = [("First.1234".to_string(), &first), ("Second.1234".to_string(), &second)];
+        let summary = with_names(|names| missing_by(&raids(), &accounts, names, "done"));
+        assert_eq!("✗ W1 vale_guardian: First.1234, Second.1234\n✗ W1 gorseval: Second.1234", summary);
+
+        let done = FinishedEncountersStore::new(vec!["vale_guardian".to_string(), "gorseval".to_string()]);
+        let summary = with_names(|names| missing_by(&raids(), &[("First.1234".to_string(), &done)], names, "done"));
+        assert_eq!("✓ done", summary);
+    }
+}
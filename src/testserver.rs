@@ -0,0 +1,561 @@
+//! A local HTTP server standing in for the GW2 API and the friends server in tests.
+//!
+//! Only the endpoints and behavior used by this addon are emulated. The server state
+//! can be set up and inspected by tests while the server is running.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::thread::JoinHandle;
+
+use chrono::{DateTime, Duration, Utc};
+use serde_json::{json, Value};
+
+use crate::friends::key_hash;
+
+const FRIENDS_PREFIX: &str = "/friends/";
+
+pub struct TestServer {
+    url: String,
+    state: Arc<Mutex<ServerState>>,
+    stopped: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+#[derive(Default)]
+pub struct ServerState {
+    accounts: HashMap<String, FakeAccount>,
+    tokens: HashMap<String, FakeToken>,
+    friend_keys: HashMap<String, FriendServerKey>,
+    next_subtoken_id: u32,
+    /// Requests in the `METHOD /path` format, without the query.
+    requests: Vec<String>,
+}
+
+pub struct FakeAccount {
+    pub id: String,
+    pub name: String,
+    pub finished_raids: Vec<String>,
+    pub finished_dungeons: Vec<String>,
+    pub finished_world_bosses: Vec<String>,
+    pub last_modified: DateTime<Utc>,
+    /// The achievements endpoint fails with a server error for this account.
+    pub achievements_unavailable: bool,
+}
+
+struct FakeToken {
+    account: String,
+    subtoken: Option<SubtokenDetails>,
+}
+
+struct SubtokenDetails {
+    issued_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+    urls: Vec<String>,
+}
+
+#[derive(Default)]
+struct FriendServerKey {
+    subtoken: Option<String>,
+    subtoken_added_at: Option<DateTime<Utc>>,
+    shared_to: Vec<(String, DateTime<Utc>)>,
+    public: bool,
+    disabled: bool,
+}
+
+struct HttpRequest {
+    method: String,
+    path: String,
+    query: HashMap<String, String>,
+    headers: HashMap<String, String>,
+    form: HashMap<String, String>,
+}
+
+struct HttpResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl HttpResponse {
+    fn json(value: Value) -> Self {
+        HttpResponse { status: 200, headers: Vec::new(), body: value.to_string() }
+    }
+
+    fn error(status: u16, text: &str) -> Self {
+        HttpResponse { status, headers: Vec::new(), body: json!({ "text": text }).to_string() }
+    }
+
+    fn with_header(mut self, name: &str, value: String) -> Self {
+        self.headers.push((name.to_string(), value));
+        self
+    }
+}
+
+impl FakeAccount {
+    pub fn new(name: &str) -> Self {
+        FakeAccount {
+            id: format!("{}-ID", name),
+            name: name.to_string(),
+            finished_raids: Vec::new(),
+            finished_dungeons: Vec::new(),
+            finished_world_bosses: Vec::new(),
+            last_modified: Utc::now() - Duration::minutes(5),
+            achievements_unavailable: false,
+        }
+    }
+}
+
+impl ServerState {
+    /// Adds an account with an API key with full permissions.
+    pub fn add_account(&mut self, api_key: &str, account: FakeAccount) {
+        self.tokens.insert(api_key.to_string(), FakeToken { account: account.name.clone(), subtoken: None });
+        self.accounts.insert(account.name.clone(), account);
+    }
+
+    pub fn account_mut(&mut self, name: &str) -> Option<&mut FakeAccount> {
+        self.accounts.get_mut(name)
+    }
+
+    pub fn request_count(&self, request: &str) -> usize {
+        self.requests.iter().filter(|x| *x == request).count()
+    }
+
+    /// Whether the friends server has a subtoken for the key.
+    pub fn friends_has_subtoken(&self, api_key: &str) -> bool {
+        self.friend_keys.get(&key_hash(api_key)).map(|x| x.subtoken.is_some()).unwrap_or(false)
+    }
+
+    pub fn friends_key_public(&self, api_key: &str) -> bool {
+        self.friend_keys.get(&key_hash(api_key)).map(|x| x.public).unwrap_or(false)
+    }
+
+    fn account_for_key_hash(&self, hash: &str) -> Option<&str> {
+        let subtoken = self.friend_keys.get(hash)?.subtoken.as_ref()?;
+        self.tokens.get(subtoken).map(|x| x.account.as_str())
+    }
+}
+
+impl TestServer {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind the test server");
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let state = Arc::new(Mutex::new(ServerState::default()));
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let thread_state = state.clone();
+        let thread_stopped = stopped.clone();
+        let thread = thread::spawn(move || {
+            for stream in listener.incoming() {
+                if thread_stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                if let Ok(stream) = stream {
+                    let state = thread_state.clone();
+                    thread::spawn(move || handle_connection(stream, &state));
+                }
+            }
+        });
+
+        TestServer { url, state, stopped, thread: Some(thread) }
+    }
+
+    /// The base URL of the GW2 API, as used by `LiveApi`.
+    pub fn gw2_url(&self) -> String {
+        self.url.clone()
+    }
+
+    /// The base URL of the friends server, as used by `FriendsApiClient`.
+    pub fn friends_url(&self) -> String {
+        format!("{}{}", self.url.trim_end_matches('/'), FRIENDS_PREFIX)
+    }
+
+    pub fn state(&self) -> std::sync::MutexGuard<'_, ServerState> {
+        self.state.lock().unwrap()
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // Wake up the accepting thread so it notices it should stop.
+        let _ = TcpStream::connect(self.url.trim_start_matches("http://").trim_end_matches('/'));
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn handle_connection(stream: TcpStream, state: &Mutex<ServerState>) {
+    let request = match read_request(&stream) {
+        Some(request) => request,
+        None => return,
+    };
+
+    let response = {
+        let mut state = state.lock().unwrap();
+        state.requests.push(format!("{} {}", request.method, request.path));
+        if let Some(path) = request.path.strip_prefix(FRIENDS_PREFIX) {
+            route_friends(path, &request, &mut state)
+        } else {
+            route_gw2(&request, &mut state)
+        }
+    };
+
+    let _ = write_response(stream, response);
+}
+
+fn read_request(stream: &TcpStream) -> Option<HttpRequest> {
+    let mut reader = BufReader::new(stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).ok()?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let target = parts.next()?.to_string();
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+
+    let content_length: usize = headers.get("content-length").and_then(|x| x.parse().ok()).unwrap_or(0);
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).ok()?;
+
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path.to_string(), parse_urlencoded(query)),
+        None => (target, HashMap::new()),
+    };
+
+    Some(HttpRequest {
+        method,
+        path,
+        query,
+        headers,
+        form: parse_urlencoded(&String::from_utf8_lossy(&body)),
+    })
+}
+
+fn write_response(mut stream: TcpStream, response: HttpResponse) -> std::io::Result<()> {
+    let reason = match response.status {
+        200 => "OK",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        _ => "Unknown",
+    };
+    // A 304 response never has a body.
+    let body = if response.status == 304 { "" } else { response.body.as_str() };
+
+    write!(stream, "HTTP/1.1 {} {}\r\n", response.status, reason)?;
+    write!(stream, "Content-Type: application/json\r\n")?;
+    write!(stream, "Content-Length: {}\r\n", body.len())?;
+    write!(stream, "Connection: close\r\n")?;
+    for (name, value) in &response.headers {
+        write!(stream, "{}: {}\r\n", name, value)?;
+    }
+    write!(stream, "\r\n{}", body)?;
+    stream.flush()
+}
+
+fn parse_urlencoded(text: &str) -> HashMap<String, String> {
+    fn decode(text: &str) -> String {
+        let bytes = text.as_bytes();
+        let mut decoded = Vec::with_capacity(bytes.len());
+        let mut i = 0;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'+' => decoded.push(b' '),
+                b'%' if i + 2 < bytes.len() => {
+                    let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+                    match u8::from_str_radix(hex, 16) {
+                        Ok(byte) => {
+                            decoded.push(byte);
+                            i += 2;
+                        }
+                        Err(_) => decoded.push(b'%'),
+                    }
+                }
+                byte => decoded.push(byte),
+            }
+            i += 1;
+        }
+        String::from_utf8_lossy(&decoded).to_string()
+    }
+
+    text.split('&')
+        .filter(|x| !x.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((name, value)) => (decode(name), decode(value)),
+            None => (decode(pair), String::new()),
+        })
+        .collect()
+}
+
+fn http_date(time: DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+fn route_gw2(request: &HttpRequest, state: &mut ServerState) -> HttpResponse {
+    // Public endpoints
+    match request.path.as_str() {
+        "/v2/raids" => return HttpResponse::json(raids_json()),
+        "/v2/dungeons" => return HttpResponse::json(json!([
+            { "id": "ascalonian_catacombs", "paths": [
+                { "id": "ac_story", "type": "Story" },
+                { "id": "hodgins", "type": "Explorable" },
+                { "id": "detha", "type": "Explorable" },
+                { "id": "tzark", "type": "Explorable" }
+            ]}
+        ])),
+        "/v2/worldbosses" => return HttpResponse::json(json!(["claw_of_jormag", "tequatl_the_sunless"])),
+        _ => {}
+    }
+
+    let token = match request.headers.get("authorization").and_then(|x| x.strip_prefix("Bearer ")) {
+        Some(token) => token.to_string(),
+        None => return HttpResponse::error(400, "missing access token"),
+    };
+    let (account_name, subtoken) = match state.tokens.get(&token) {
+        Some(token) => (token.account.clone(), token.subtoken.as_ref()),
+        None => return HttpResponse::error(401, "Invalid access token"),
+    };
+
+    // Token info is available to every token, the URL restrictions do not apply to it.
+    if let Some(subtoken) = subtoken.filter(|_| request.path != "/v2/tokeninfo") {
+        if !subtoken.urls.is_empty() && !subtoken.urls.iter().any(|url| *url == request.path) {
+            return HttpResponse::error(403, "requires scope");
+        }
+    }
+
+    let tokeninfo = match subtoken {
+        Some(details) => json!({
+            "id": "subtoken",
+            "name": "subtoken",
+            "permissions": ["account", "progression"],
+            "type": "Subtoken",
+            "expires_at": details.expires_at.to_rfc3339(),
+            "issued_at": details.issued_at.to_rfc3339(),
+            "urls": details.urls,
+        }),
+        None => json!({
+            "id": token,
+            "name": "test key",
+            "permissions": ["account", "progression"],
+            "type": "APIKey",
+        }),
+    };
+
+    let account = &state.accounts[&account_name];
+    let last_modified = http_date(account.last_modified);
+    let not_modified = request.headers.get("if-modified-since") == Some(&last_modified);
+
+    match request.path.as_str() {
+        "/v2/tokeninfo" => HttpResponse::json(tokeninfo),
+        "/v2/account" => HttpResponse::json(json!({
+            "id": account.id,
+            "name": account.name,
+            "last_modified": account.last_modified.to_rfc3339(),
+        })).with_header("Last-Modified", last_modified),
+        "/v2/account/raids" if not_modified => HttpResponse { status: 304, headers: Vec::new(), body: String::new() },
+        "/v2/account/raids" => HttpResponse::json(json!(account.finished_raids)).with_header("Last-Modified", last_modified),
+        "/v2/account/dungeons" => HttpResponse::json(json!(account.finished_dungeons)),
+        "/v2/account/worldbosses" => HttpResponse::json(json!(account.finished_world_bosses)),
+        "/v2/account/masteries" => HttpResponse::json(json!([])).with_header("Last-Modified", last_modified),
+        "/v2/account/achievements" if account.achievements_unavailable => HttpResponse::error(500, "internal error"),
+        "/v2/account/achievements" => HttpResponse::error(404, "all ids provided are invalid"),
+        "/v2/createsubtoken" => {
+            let expires_at = request.query.get("expire")
+                .and_then(|x| DateTime::parse_from_rfc3339(x).ok())
+                .map(|x| x.with_timezone(&Utc))
+                .unwrap_or_else(|| Utc::now() + Duration::days(1));
+            let urls = request.query.get("urls")
+                .map(|x| x.split(',').filter(|x| !x.is_empty()).map(|x| x.to_string()).collect())
+                .unwrap_or_default();
+
+            state.next_subtoken_id += 1;
+            let subtoken = format!("subtoken-{}", state.next_subtoken_id);
+            state.tokens.insert(subtoken.clone(), FakeToken {
+                account: account_name,
+                subtoken: Some(SubtokenDetails { issued_at: Utc::now(), expires_at, urls }),
+            });
+            HttpResponse::json(json!({ "subtoken": subtoken }))
+        }
+        _ => HttpResponse::error(404, "not found"),
+    }
+}
+
+fn route_friends(path: &str, request: &HttpRequest, state: &mut ServerState) -> HttpResponse {
+    let auth_hashes: Vec<String> = request.headers.get("x-auth-keys")
+        .map(|x| x.split(',').filter(|x| !x.is_empty()).map(|x| x.to_string()).collect())
+        .unwrap_or_default();
+    let public_friends: Vec<String> = request.headers.get("x-public-friends")
+        .map(|x| x.split(',').filter(|x| !x.is_empty()).map(|x| x.to_string()).collect())
+        .unwrap_or_default();
+
+    if request.method == "POST" {
+        let hash = match request.form.get("key_hash") {
+            Some(hash) if auth_hashes.contains(hash) => hash.clone(),
+            _ => return HttpResponse::error(400, "unknown key hash"),
+        };
+
+        match path {
+            "key/add" => {
+                let subtoken = request.form.get("subtoken").cloned().unwrap_or_default();
+                if !state.tokens.contains_key(&subtoken) {
+                    return HttpResponse::error(400, "invalid subtoken");
+                }
+                let key = state.friend_keys.entry(hash).or_default();
+                key.subtoken = Some(subtoken);
+                key.subtoken_added_at = Some(Utc::now());
+            }
+            "key/share" | "key/unshare" => {
+                let account = request.form.get("account").cloned().unwrap_or_default();
+                let key = state.friend_keys.entry(hash).or_default();
+                key.shared_to.retain(|(x, _)| *x != account);
+                if path == "key/share" {
+                    key.shared_to.push((account, Utc::now()));
+                }
+            }
+            "key/public" => {
+                let key = state.friend_keys.entry(hash).or_default();
+                key.public = request.form.get("public").map(|x| x == "true").unwrap_or(false);
+                key.disabled = request.form.get("disabled").map(|x| x == "true").unwrap_or(false);
+            }
+            _ => return HttpResponse::error(404, "not found"),
+        }
+    } else if path != "state" {
+        return HttpResponse::error(404, "not found");
+    }
+
+    HttpResponse::json(friends_state(state, &auth_hashes, &public_friends))
+}
+
+fn friends_state(state: &ServerState, auth_hashes: &[String], public_friends: &[String]) -> Value {
+    let own_accounts: Vec<&str> = auth_hashes.iter()
+        .filter_map(|hash| state.account_for_key_hash(hash))
+        .collect();
+
+    let keys: Vec<Value> = auth_hashes.iter()
+        .map(|hash| {
+            let key = state.friend_keys.get(hash);
+            let expires_at = key.and_then(|key| key.subtoken.as_ref())
+                .and_then(|subtoken| state.tokens.get(subtoken))
+                .and_then(|token| token.subtoken.as_ref())
+                .map(|details| details.expires_at.to_rfc3339());
+            json!({
+                "key_hash": hash,
+                "shared_to": key.map(|key| key.shared_to.iter()
+                    .map(|(account, added_at)| json!({
+                        "account": account,
+                        "added_at": added_at.to_rfc3339(),
+                        "account_available": state.accounts.contains_key(account),
+                    }))
+                    .collect::<Vec<_>>()).unwrap_or_default(),
+                "subtoken_added_at": key.and_then(|key| key.subtoken_added_at).map(|x| x.to_rfc3339()),
+                "subtoken_expires_at": expires_at,
+                "account": state.account_for_key_hash(hash),
+                "public": key.map(|key| key.public).unwrap_or(false),
+                "disabled": key.map(|key| key.disabled).unwrap_or(false),
+            })
+        })
+        .collect();
+
+    let mut friends: Vec<Value> = Vec::new();
+    let mut friend_accounts: Vec<&str> = Vec::new();
+    for (hash, key) in &state.friend_keys {
+        let account = match state.account_for_key_hash(hash) {
+            Some(account) if !own_accounts.contains(&account) => account,
+            _ => continue,
+        };
+        if key.disabled || friend_accounts.contains(&account) {
+            continue;
+        }
+        let shared_with: Vec<&str> = key.shared_to.iter()
+            .map(|(account, _)| account.as_str())
+            .filter(|account| own_accounts.contains(account))
+            .collect();
+        let public = key.public && public_friends.iter().any(|x| x == account);
+        if shared_with.is_empty() && !public {
+            continue;
+        }
+
+        let subtoken = key.subtoken.as_ref().unwrap();
+        let expires_at = state.tokens[subtoken].subtoken.as_ref().map(|x| x.expires_at).unwrap_or_else(Utc::now);
+        friend_accounts.push(account);
+        friends.push(json!({
+            "account": account,
+            "subtoken": { "subtoken": subtoken, "expires_at": expires_at.to_rfc3339() },
+            "shared_with": shared_with,
+            "known": true,
+            "public": public,
+        }));
+    }
+
+    // Public friends we know nothing about are reported as unknown.
+    for account in public_friends {
+        if !friend_accounts.contains(&account.as_str()) {
+            friends.push(json!({
+                "account": account,
+                "subtoken": null,
+                "shared_with": [],
+                "known": false,
+                "public": false,
+            }));
+        }
+    }
+
+    json!({ "keys": keys, "friends": friends })
+}
+
+fn raids_json() -> Value {
+    json!([
+        {
+            "id": "forsaken_thicket",
+            "wings": [
+                {
+                    "id": "spirit_vale",
+                    "events": [
+                        { "id": "vale_guardian", "type": "Boss" },
+                        { "id": "spirit_woods", "type": "Checkpoint" },
+                        { "id": "gorseval", "type": "Boss" },
+                        { "id": "sabetha", "type": "Boss" }
+                    ]
+                },
+                {
+                    "id": "salvation_pass",
+                    "events": [
+                        { "id": "slothasor", "type": "Boss" },
+                        { "id": "bandit_trio", "type": "Boss" },
+                        { "id": "matthias", "type": "Boss" }
+                    ]
+                }
+            ]
+        }
+    ])
+}
+
+/// Waits until the condition is true, panicking if it takes too long.
+pub fn wait_until<F: FnMut() -> bool>(mut condition: F) {
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+    while !condition() {
+        if std::time::Instant::now() > deadline {
+            panic!("Timed out waiting for a condition.");
+        }
+        thread::sleep(std::time::Duration::from_millis(10));
+    }
+}
@@ -3,7 +3,7 @@ use std::fs::File;
 use std::io::BufReader;
 use std::error::Error;
 use serde::{Serialize, Deserialize};
-use crate::clears::RaidEncounter;
+use crate::clears::{RaidEncounter, RaidWing};
 use itertools::Itertools;
 
 #[derive(Serialize, Deserialize)]
@@ -45,6 +45,14 @@ impl Translation {
         }
     }
 
+    pub fn wing_short_name(&self, wing: &RaidWing) -> String {
+        if let Some(translation) = self.encounter_short_names.get(wing.id()) {
+            translation.to_string()
+        } else {
+            english_name(wing.id())
+        }
+    }
+
 }
 
 pub fn get_default_translation_contents() -> &'static str {
@@ -52,6 +60,14 @@ pub fn get_default_translation_contents() -> &'static str {
 }
 
 pub fn encounter_english_name(encounter: &RaidEncounter) -> String {
+    english_name(encounter.id())
+}
+
+pub fn wing_english_name(wing: &RaidWing) -> String {
+    english_name(wing.id())
+}
+
+fn english_name(id: &str) -> String {
     fn capitalize(str: &str) -> String {
         let capitalized = str.chars().enumerate().map(|(i, char)| {
             if i == 0 {
@@ -62,7 +78,7 @@ pub fn encounter_english_name(encounter: &RaidEncounter) -> String {
         }).collect();
         capitalized
     }
-    let parts = encounter.id().split('_');
+    let parts = id.split('_');
     parts.enumerate().map(|(i, x)| {
         // The first word should always get capitalized
         if i > 0 && ["of", "in", "the"].contains(&x) {
@@ -1,12 +1,12 @@
 use arcdps::imgui::{ChildWindow, Condition, PopupModal, Selectable, StyleVar, TabBar, TabItem, TableFlags, Ui, Window};
-use chrono::Utc;
+use chrono::{Local, Utc};
 use log::warn;
 
 use crate::{Data, friends};
 use crate::friends::KeyUsability;
 use crate::settings::{ApiKey, Settings, TokenType};
 use crate::translations::Translation;
-use crate::ui::{get_api_key_name, SelectedApiKey, UiState, utils};
+use crate::ui::{api_failure_description, get_api_key_name, SelectedApiKey, UiState, utils};
 use crate::ui::friends::refresh_button;
 use crate::ui::style::WARNING_RED;
 use crate::workers::{ApiJob, BackgroundWorkers};
@@ -29,6 +29,15 @@ pub fn api_keys_window(
             .collapsible(false)
             .opened(&mut shown)
             .build(ui, || {
+                // Keys cannot be managed until they are decrypted, new keys would be saved unencrypted.
+                if settings.keys_locked() {
+                    ui.text(tr.translate("api-key-locked"));
+                    if ui.button(tr.translate("api-key-unlock-button")) {
+                        ui_state.unlock_window.shown = true;
+                    }
+                    return;
+                }
+
                 // We copy this here as we will be doing borrows later on and settings won't be
                 // available when this value is needed.
                 let friends_enabled = settings.friends.enabled;
@@ -93,6 +102,12 @@ pub fn api_keys_window(
                                         if let Err(_) = sender.send(ApiJob::UpdateClears(*key.id())) {
                                             warn!("Failed to send request to API worker");
                                         }
+                                        if let Err(_) = sender.send(ApiJob::UpdateDungeonPaths(*key.id())) {
+                                            warn!("Failed to send request to API worker");
+                                        }
+                                        if let Err(_) = sender.send(ApiJob::UpdateFinishedWorldBosses(*key.id())) {
+                                            warn!("Failed to send request to API worker");
+                                        }
                                     }
                                     ui.separator();
                                     ui.text_wrapped(&tr.translate("api-key-guide-step1-prefix"));
@@ -240,12 +255,32 @@ pub fn api_keys_window(
                                                 }
                                             }
 
+                                            // Recent request failures
+                                            if let Some(health) = data.clears.key_health(key) {
+                                                if let Some(failure) = health.current_failure() {
+                                                    ui.text_colored(WARNING_RED, api_failure_description(failure, tr));
+                                                    ui.same_line();
+                                                    utils::help_marker(ui, format!("{}{}{}{}\n{}",
+                                                                                   tr.translate("api-key-failure-count-prefix"),
+                                                                                   health.consecutive_failures(),
+                                                                                   tr.translate("api-key-failure-count-suffix"),
+                                                                                   failure.time().with_timezone(&Local).format("%Y-%m-%d %H:%M:%S"),
+                                                                                   failure.message()));
+                                                }
+                                            }
+
                                             ui.separator();
                                             if ui.checkbox(&tr.translate("api-key-show-in-my-clears-checkbox"), key.show_key_in_clears_mut()) {
                                                 if key.show_key_in_clears() {
                                                     if let Err(_) = bg_workers.api_sender().send(ApiJob::UpdateClears(*key.id())) {
                                                         warn!("Failed to send request to API worker");
                                                     }
+                                                    if let Err(_) = bg_workers.api_sender().send(ApiJob::UpdateDungeonPaths(*key.id())) {
+                                                        warn!("Failed to send request to API worker");
+                                                    }
+                                                    if let Err(_) = bg_workers.api_sender().send(ApiJob::UpdateFinishedWorldBosses(*key.id())) {
+                                                        warn!("Failed to send request to API worker");
+                                                    }
                                                 }
                                             }
 
@@ -1,13 +1,20 @@
 use std::time::Instant;
 
+use chrono::{Datelike, Local, Utc, Weekday};
+use log::{error, info};
 use arcdps::imgui::{CollapsingHeader, MenuItem, MouseButton, StyleColor, StyleVar, TableBgTarget, TableColumnFlags, TableColumnSetup, TableFlags, TableRowFlags, Ui};
 
-use crate::clears::{FinishedEncountersStore, RaidWings};
+use crate::achievements;
+use crate::export;
+use crate::export::ExportFormat;
+use crate::summary;
+use crate::summary::SummaryNames;
+use crate::clears::{ClearTime, FinishedEncountersStore, RaidClearState, RaidEncounter, RaidWing, RaidWings};
 use crate::Data;
 
 use crate::settings::{AccountHeaderStyle, ClearsStyle, ClearsTableStyle, Settings};
 use crate::translations::{encounter_english_name, Translation};
-use crate::ui::{get_api_key_name, settings, UiState, utils};
+use crate::ui::{api_failure_description, get_api_key_name, settings, UiState, utils};
 use crate::workers::BackgroundWorkers;
 
 pub fn my_clears(
@@ -45,32 +52,27 @@ pub fn my_clears(
             .map(|key| ClearTableEntry {
                 account_name: get_api_key_name(key, tr),
                 state: data.clears.finished_encounters(key),
+                warning: data.clears.key_health(key)
+                    .and_then(|health| health.current_failure())
+                    .map(|failure| api_failure_description(failure, tr)),
                 expanded: key.expanded_in_clears_mut()
             })
             .collect();
 
-        clears_table(ui, raids, &mut entries, &settings.my_clears_style, settings.short_names, tr, || {
+        clears_table(ui, raids, ClearsTableKind::Raids, &mut entries, &settings.my_clears_style, settings.short_names, tr, || {
             utils::centered_text(ui, &tr.translate("clears-no-clears-data-yet"));
             ui.text("");
             // TODO: Custom prompt for missing perms
 
-            let time = *bg_workers.api_refresher_next_wakeup().lock().unwrap();
-            let until_wakeup = time.saturating_duration_since(Instant::now());
-            utils::centered_text(
-                ui,
-                format!("{}{}{}", tr.translate("next-refresh-secs-prefix"), until_wakeup.as_secs(), tr.translate("next-refresh-secs-suffix")),
-            );
+            next_refresh_text(ui, bg_workers, tr);
         });
+
+        challenge_motes(ui, data, settings, tr);
     } else {
         ui.text(tr.translate("clears-no-public-data-yet"));
         ui.text("");
 
-        let time = *bg_workers.api_refresher_next_wakeup().lock().unwrap();
-        let until_wakeup = time.saturating_duration_since(Instant::now());
-        utils::centered_text(
-            ui,
-            format!("{}{}{}", tr.translate("next-refresh-secs-prefix"), until_wakeup.as_secs(), tr.translate("next-refresh-secs-suffix")),
-        );
+        next_refresh_text(ui, bg_workers, tr);
     }
 
     if ui.is_mouse_released(MouseButton::Right) && ui.is_window_hovered() {
@@ -92,21 +94,259 @@ pub fn my_clears(
                     }
                 }
             });
+            if let Some(raids) = data.clears.raids() {
+                let accounts = own_accounts_summary(data, settings, tr);
+                copy_summary_menu(ui, raids, &accounts, tr);
+            }
+            ui.menu(&tr.translate("clears-contextmenu-export"), || {
+                for format in ExportFormat::all().iter() {
+                    if MenuItem::new(export_format_name(*format, tr)).build(ui) {
+                        export_clears(*format, data, settings, tr);
+                    }
+                    if ui.is_item_hovered() {
+                        ui.tooltip_text(format!("{}{}.{}", tr.translate("clears-export-file-prefix"), crate::EXPORT_FILENAME_BASE, format.extension()));
+                    }
+                }
+            });
+            settings::profiles_menu(ui, settings, tr);
             ui.separator();
             settings::style_section(ui, "my-clears-style-tooltip", &mut settings.my_clears_style, tr);
         }
     })
 }
 
+/// Challenge motes are shown apart from weekly clears, as they are never reset.
+fn challenge_motes(ui: &Ui, data: &Data, settings: &mut Settings, tr: &Translation) {
+    let mut entries: Vec<_> = settings.api_keys.iter_mut()
+        .filter(|key| key.show_key_in_clears())
+        .map(|key| ClearTableEntry {
+            account_name: get_api_key_name(key, tr),
+            state: data.clears.finished_challenge_motes(key),
+            warning: None,
+            expanded: key.expanded_in_clears_mut()
+        })
+        .collect();
+    if entries.iter().all(|entry| entry.state.is_none()) {
+        return;
+    }
+
+    if CollapsingHeader::new(&tr.translate("clears-challenge-motes-header")).build(ui) {
+        clears_table(ui, data.clears.challenge_motes(), ClearsTableKind::ChallengeMotes, &mut entries, &settings.my_clears_style, settings.short_names, tr, || {});
+    }
+}
+
+/// Accounts that are shown in the clears table and have data.
+pub fn own_accounts_summary<'a>(data: &'a Data, settings: &Settings, tr: &Translation) -> Vec<(String, &'a FinishedEncountersStore)> {
+    settings.api_keys.iter()
+        .filter(|key| key.show_key_in_clears())
+        .filter_map(|key| data.clears.finished_encounters(key).map(|finished| (get_api_key_name(key, tr), finished)))
+        .collect()
+}
+
+/// A menu for copying a text summary of clears to the clipboard.
+pub fn copy_summary_menu(ui: &Ui, raids: &RaidWings, accounts: &[(String, &FinishedEncountersStore)], tr: &Translation) {
+    ui.menu(&tr.translate("clears-contextmenu-copy-summary"), || {
+        let wing_name = |index: usize, wing: &RaidWing| ClearsTableKind::Raids.group_name(index, wing, false, tr);
+        let encounter_name = |encounter: &RaidEncounter| tr.encounter_short_name_im_string(encounter);
+        let names = SummaryNames { wing: &wing_name, encounter: &encounter_name };
+
+        if MenuItem::new(tr.translate("clears-copy-summary-per-account")).build(ui) {
+            ui.set_clipboard_text(summary::per_account(raids, accounts, &names));
+        }
+        if MenuItem::new(tr.translate("clears-copy-summary-missing-by")).build(ui) {
+            let nobody_missing = tr.translate("clears-copy-summary-nobody-missing");
+            ui.set_clipboard_text(summary::missing_by(raids, accounts, &names, &nobody_missing));
+        }
+    });
+}
+
+fn export_format_name(format: ExportFormat, tr: &Translation) -> String {
+    match format {
+        ExportFormat::Csv => tr.translate("clears-export-csv"),
+        ExportFormat::Json => tr.translate("clears-export-json"),
+        ExportFormat::Markdown => tr.translate("clears-export-markdown"),
+    }
+}
+
+/// Exports clears of own accounts followed by friends.
+fn export_clears(format: ExportFormat, data: &Data, settings: &Settings, tr: &Translation) {
+    let raids = match data.clears.raids() {
+        Some(raids) => raids,
+        None => return,
+    };
+
+    let mut accounts: Vec<(String, Option<&RaidClearState>)> = settings.api_keys.iter()
+        .map(|key| (get_api_key_name(key, tr), data.clears.state(key)))
+        .collect();
+    let mut friends: Vec<_> = data.friends.clears_by_account().iter()
+        .map(|(account, state)| (account.clone(), Some(state)))
+        .collect();
+    friends.sort_by(|a, b| a.0.cmp(&b.0));
+    accounts.extend(friends);
+
+    let rows = export::export_rows(raids, &accounts, Utc::now());
+    match export::write_export(crate::EXPORT_FILENAME_BASE, format, &rows) {
+        Ok(filename) => info!("Exported clears to {}", filename),
+        Err(e) => error!("Failed to export clears: {}", e),
+    }
+}
+
+pub fn my_dungeons(
+    ui: &Ui,
+    data: &Data,
+    bg_workers: &BackgroundWorkers,
+    settings: &mut Settings,
+    tr: &Translation,
+) {
+    if let Some(dungeons) = data.clears.dungeons() {
+        if settings.api_keys.iter().filter(|x| x.show_key_in_clears()).count() == 0 {
+            if let _wrap = ui.push_text_wrap_pos_with_pos(ui.current_font_size() * 25.0) {
+                ui.text_wrapped(&tr.translate("clears-all-accounts-hidden"));
+            }
+        }
+
+        let mut entries: Vec<_> = settings.api_keys.iter_mut()
+            .filter(|key| key.show_key_in_clears())
+            .map(|key| ClearTableEntry {
+                account_name: get_api_key_name(key, tr),
+                state: data.clears.finished_dungeon_paths(key),
+                warning: data.clears.key_health(key)
+                    .and_then(|health| health.current_failure())
+                    .map(|failure| api_failure_description(failure, tr)),
+                expanded: key.expanded_in_clears_mut()
+            })
+            .collect();
+
+        clears_table(ui, dungeons, ClearsTableKind::Dungeons, &mut entries, &settings.my_clears_style, settings.short_names, tr, || {
+            utils::centered_text(ui, &tr.translate("dungeons-no-data-yet"));
+            ui.text("");
+
+            next_refresh_text(ui, bg_workers, tr);
+        });
+    } else {
+        ui.text(tr.translate("clears-no-public-data-yet"));
+        ui.text("");
+
+        next_refresh_text(ui, bg_workers, tr);
+    }
+}
+
+/// Clears detected by arcdps and not confirmed by the API yet are shown in between the two colors.
+fn clear_color(clears: &FinishedEncountersStore, encounter: &RaidEncounter, style: &ClearsStyle) -> [f32; 4] {
+    if clears.is_unconfirmed(encounter) {
+        let mut color = style.finished_clear_color;
+        for (channel, unfinished) in color.iter_mut().zip(style.unfinished_clear_color.iter()) {
+            *channel = (*channel + unfinished) / 2.0;
+        }
+        color
+    } else if clears.is_finished(encounter) {
+        style.finished_clear_color
+    } else {
+        style.unfinished_clear_color
+    }
+}
+
+/// Shows when a hovered encounter was cleared, if it is known.
+fn clear_tooltip(ui: &Ui, clears: &FinishedEncountersStore, encounter: &RaidEncounter, tr: &Translation) {
+    if !ui.is_item_hovered() {
+        return;
+    }
+    if clears.is_unconfirmed(encounter) {
+        ui.tooltip_text(tr.translate("clears-unconfirmed-tooltip"));
+    } else if let Some(time) = clears.clear_time(encounter) {
+        ui.tooltip_text(clear_time_text(time, tr));
+    }
+}
+
+fn clear_time_text(time: &ClearTime, tr: &Translation) -> String {
+    let earliest = time.earliest().with_timezone(&Local);
+    let latest = time.latest().with_timezone(&Local);
+    if earliest.date() == latest.date() {
+        format!(
+            "{}{}{}{}{}{}",
+            tr.translate("clears-cleared-between-prefix"),
+            earliest.format("%H:%M"),
+            tr.translate("clears-cleared-between-and"),
+            latest.format("%H:%M"),
+            tr.translate("clears-cleared-between-on"),
+            weekday_name(earliest.weekday(), tr),
+        )
+    } else {
+        format!(
+            "{}{} {}{}{} {}",
+            tr.translate("clears-cleared-between-prefix"),
+            weekday_name(earliest.weekday(), tr),
+            earliest.format("%H:%M"),
+            tr.translate("clears-cleared-between-and"),
+            weekday_name(latest.weekday(), tr),
+            latest.format("%H:%M"),
+        )
+    }
+}
+
+fn weekday_name(weekday: Weekday, tr: &Translation) -> String {
+    tr.translate(match weekday {
+        Weekday::Mon => "weekday-monday",
+        Weekday::Tue => "weekday-tuesday",
+        Weekday::Wed => "weekday-wednesday",
+        Weekday::Thu => "weekday-thursday",
+        Weekday::Fri => "weekday-friday",
+        Weekday::Sat => "weekday-saturday",
+        Weekday::Sun => "weekday-sunday",
+    })
+}
+
+pub fn next_refresh_text(ui: &Ui, bg_workers: &BackgroundWorkers, tr: &Translation) {
+    let time = *bg_workers.api_refresher_next_wakeup().lock().unwrap();
+    let until_wakeup = time.saturating_duration_since(Instant::now());
+    utils::centered_text(
+        ui,
+        format!("{}{}{}", tr.translate("next-refresh-secs-prefix"), until_wakeup.as_secs(), tr.translate("next-refresh-secs-suffix")),
+    );
+}
+
+/// The content shown in a clears table, this only changes the labels.
+#[derive(Copy, Clone)]
+pub enum ClearsTableKind {
+    /// Raid wings with their encounters.
+    Raids,
+    /// Dungeons with their paths.
+    Dungeons,
+    /// Challenge motes of raid wings, these are finished once per account.
+    ChallengeMotes,
+}
+
+impl ClearsTableKind {
+    pub fn group_name(self, index: usize, group: &RaidWing, full: bool, tr: &Translation) -> String {
+        match self {
+            ClearsTableKind::Raids if achievements::is_achievement_group(group) => tr.wing_short_name(group),
+            ClearsTableKind::Raids if full => format!("{} {}", tr.translate("clears-wing-prefix-full"), index + 1),
+            ClearsTableKind::Raids => format!("{}{}", tr.translate("clears-wing-prefix"), index + 1),
+            ClearsTableKind::Dungeons | ClearsTableKind::ChallengeMotes => tr.wing_short_name(group),
+        }
+    }
+
+    fn encounter_column_name(self, index: usize, tr: &Translation) -> String {
+        let name = match self {
+            ClearsTableKind::Raids | ClearsTableKind::ChallengeMotes => tr.translate("clears-header-boss"),
+            ClearsTableKind::Dungeons => tr.translate("dungeons-header-path"),
+        };
+        format!("{} {}", name, index + 1)
+    }
+}
+
 pub struct ClearTableEntry<'a> {
     pub account_name: String,
     pub state: Option<&'a FinishedEncountersStore>,
+    /// Shown next to the account name, used when the data is failing to update.
+    pub warning: Option<String>,
     pub expanded: &'a mut bool,
 }
 
 pub fn clears_table<F: Fn()>(
     ui: &Ui,
     raids: &RaidWings,
+    kind: ClearsTableKind,
     data: &mut [ClearTableEntry],
     style: &ClearsStyle,
     short_names: bool,
@@ -129,7 +369,7 @@ pub fn clears_table<F: Fn()>(
                 }
                 first_key = false;
 
-                if account_header(ui, &item.account_name, item.expanded, style.account_header_style) {
+                if account_header(ui, &item.account_name, item.warning.as_deref(), item.expanded, style.account_header_style) {
                     if let Some(clears) = item.state {
                         let column_count = if style.show_clears_table_row_names {
                             max_bosses + 1
@@ -145,7 +385,7 @@ pub fn clears_table<F: Fn()>(
                                 ui.table_setup_column("");
                             }
                             for boss in 0..max_bosses {
-                                ui.table_setup_column(format!("{} {}", tr.translate("clears-header-boss"), boss + 1 ));
+                                ui.table_setup_column(kind.encounter_column_name(boss, tr));
                             }
                             if style.show_clears_table_headers {
                                 ui.table_headers_row();
@@ -154,18 +394,12 @@ pub fn clears_table<F: Fn()>(
                                 ui.table_next_row();
                                 if style.show_clears_table_row_names {
                                     ui.table_next_column();
-                                    ui.text(format!("{}{}", tr.translate("clears-wing-prefix"), wing_index + 1));
+                                    ui.text(kind.group_name(wing_index, wing, false, tr));
                                 }
                                 for column in 0..max_bosses {
                                     ui.table_next_column();
                                     if let Some(encounter) = wing.encounters().get(column) {
-                                        let finished = clears.is_finished(encounter);
-
-                                        let bg_color = if finished {
-                                            style.finished_clear_color
-                                        } else {
-                                            style.unfinished_clear_color
-                                        };
+                                        let bg_color = clear_color(clears, encounter, style);
 
                                         if short_names {
                                             utils::centered_text(
@@ -180,6 +414,7 @@ pub fn clears_table<F: Fn()>(
                                         }
 
                                         ui.table_set_bg_color(TableBgTarget::CELL_BG, bg_color);
+                                        clear_tooltip(ui, clears, encounter, tr);
                                     }
                                     ui.next_column()
                                 }
@@ -200,7 +435,7 @@ pub fn clears_table<F: Fn()>(
                 }
                 first_key = false;
 
-                if account_header(ui, &item.account_name, item.expanded, style.account_header_style) {
+                if account_header(ui, &item.account_name, item.warning.as_deref(), item.expanded, style.account_header_style) {
                     if let Some(clears) = item.state {
                         let column_count = if style.show_clears_table_row_names {
                             raids.wings().len() + 1
@@ -215,8 +450,8 @@ pub fn clears_table<F: Fn()>(
                             if style.show_clears_table_row_names {
                                 ui.table_setup_column("");
                             }
-                            for (wing_index, _wing) in raids.wings().iter().enumerate() {
-                                ui.table_setup_column(format!("{} {}", tr.translate("clears-wing-prefix-full"), wing_index + 1));
+                            for (wing_index, wing) in raids.wings().iter().enumerate() {
+                                ui.table_setup_column(kind.group_name(wing_index, wing, true, tr));
                             }
                             if style.show_clears_table_headers {
                                 ui.table_headers_row();
@@ -225,18 +460,12 @@ pub fn clears_table<F: Fn()>(
                                 ui.table_next_row();
                                 if style.show_clears_table_row_names {
                                     ui.table_next_column();
-                                    ui.text(format!("{} {}", tr.translate("clears-header-boss"), boss + 1));
+                                    ui.text(kind.encounter_column_name(boss, tr));
                                 }
                                 for wing in raids.wings() {
                                     ui.table_next_column();
                                     if let Some(encounter) = wing.encounters().get(boss) {
-                                        let finished = clears.is_finished(encounter);
-
-                                        let bg_color = if finished {
-                                            style.finished_clear_color
-                                        } else {
-                                            style.unfinished_clear_color
-                                        };
+                                        let bg_color = clear_color(clears, encounter, style);
 
                                         if short_names {
                                             utils::centered_text(ui, tr.encounter_short_name_im_string(encounter));
@@ -245,6 +474,7 @@ pub fn clears_table<F: Fn()>(
                                         }
 
                                         ui.table_set_bg_color(TableBgTarget::CELL_BG, bg_color);
+                                        clear_tooltip(ui, clears, encounter, tr);
                                     }
                                     ui.next_column()
                                 }
@@ -256,6 +486,7 @@ pub fn clears_table<F: Fn()>(
                 }
             }
         }
+        ClearsTableStyle::Matrix => matrix_table(ui, raids, kind, data, style, short_names, tr, &no_data_available),
         ClearsTableStyle::SingleRow => {
             /*
             Beware, this is significantly cursed, attempts to simplify this are likely to fail.
@@ -286,8 +517,16 @@ pub fn clears_table<F: Fn()>(
             borders are affected by horizontal ones.
             */
 
+            let warning_marker_width = ui.calc_text_size("(!)")[0] + ui.clone_style().item_spacing[0];
             let max_name_width = data.iter()
-                .map(|item| ui.calc_text_size(&item.account_name)[0])
+                .map(|item| {
+                    let name_width = ui.calc_text_size(&item.account_name)[0];
+                    if item.warning.is_some() {
+                        name_width + warning_marker_width
+                    } else {
+                        name_width
+                    }
+                })
                 .fold(0.0, f32::max);
 
             let cell_padding = ui.clone_style().cell_padding;
@@ -315,7 +554,7 @@ pub fn clears_table<F: Fn()>(
                     let inner_width = (ui.current_font_size() * 1.5).ceil() * wing.encounters().len() as f32
                         + (wing.encounters().len() - 1) as f32 // Inner borders
                         + 2.0; // Outer borders
-                    table_headers_names.push(format!("{} {}", tr.translate("clears-wing-prefix-full"), wing_index + 1));
+                    table_headers_names.push(kind.group_name(wing_index, wing, true, tr));
                     ui.table_setup_column_with(TableColumnSetup {
                         name: table_headers_names.last().unwrap(),
                         flags: TableColumnFlags::WIDTH_FIXED,
@@ -349,6 +588,10 @@ pub fn clears_table<F: Fn()>(
                     for item in data.iter() {
                         ui.table_next_column();
                         ui.text(&item.account_name);
+                        if let Some(warning) = &item.warning {
+                            ui.same_line();
+                            utils::warning_marker(ui, warning);
+                        }
                     }
                 }
 
@@ -374,12 +617,7 @@ pub fn clears_table<F: Fn()>(
                                 ui.table_next_column();
                                 if let Some(clears) = item.state {
                                     let finished = clears.is_finished(encounter);
-
-                                    let bg_color = if finished {
-                                        style.finished_clear_color
-                                    } else {
-                                        style.unfinished_clear_color
-                                    };
+                                    let bg_color = clear_color(clears, encounter, style);
 
                                     ui.table_set_bg_color(TableBgTarget::CELL_BG, bg_color);
 
@@ -426,6 +664,66 @@ pub fn clears_table<F: Fn()>(
     }
 }
 
+/// A single table with a row per encounter and a column per account,
+/// along with the number of accounts that still need each encounter.
+fn matrix_table(
+    ui: &Ui,
+    raids: &RaidWings,
+    kind: ClearsTableKind,
+    data: &[ClearTableEntry],
+    style: &ClearsStyle,
+    short_names: bool,
+    tr: &Translation,
+    no_data_available: &dyn Fn(),
+) {
+    let accounts: Vec<(&ClearTableEntry, &FinishedEncountersStore)> = data.iter()
+        .filter_map(|item| item.state.map(|state| (item, state)))
+        .collect();
+    if accounts.is_empty() {
+        no_data_available();
+        return;
+    }
+
+    let states: Vec<&FinishedEncountersStore> = accounts.iter().map(|(_, state)| *state).collect();
+    let encounters = raids.encounters_needed_by(&states, style.sort_matrix_by_needed);
+
+    if let Some(_t) = ui.begin_table_with_flags(
+        "ClearsTableMatrix",
+        accounts.len() + 2,
+        TableFlags::BORDERS | TableFlags::NO_HOST_EXTEND_X | TableFlags::SIZING_FIXED_FIT,
+    ) {
+        ui.table_setup_column(tr.translate("clears-matrix-encounter"));
+        ui.table_setup_column(tr.translate("clears-matrix-needed-by"));
+        for (item, _) in &accounts {
+            ui.table_setup_column(&item.account_name);
+        }
+        if style.show_clears_table_headers {
+            ui.table_headers_row();
+        }
+
+        for (wing_index, encounter, needed_by) in encounters {
+            ui.table_next_row();
+            ui.table_next_column();
+            let encounter_name = if short_names {
+                tr.encounter_short_name_im_string(encounter)
+            } else {
+                encounter_english_name(encounter)
+            };
+            ui.text(format!("{} {}", kind.group_name(wing_index, &raids.wings()[wing_index], false, tr), encounter_name));
+
+            ui.table_next_column();
+            utils::centered_text(ui, needed_by.to_string());
+
+            for (item, clears) in &accounts {
+                ui.table_next_column();
+                ui.table_set_bg_color(TableBgTarget::CELL_BG, clear_color(clears, encounter, style));
+                ui.dummy([ui.calc_text_size(&item.account_name)[0].max(ui.frame_height()), ui.text_line_height()]);
+                clear_tooltip(ui, clears, encounter, tr);
+            }
+        }
+    }
+}
+
 pub fn account_separator(ui: &Ui, style: AccountHeaderStyle) {
     match style {
         AccountHeaderStyle::None => ui.separator(),
@@ -434,16 +732,45 @@ pub fn account_separator(ui: &Ui, style: AccountHeaderStyle) {
     }
 }
 
-pub fn account_header<T: AsRef<str>>(ui: &Ui, name: T, expanded: &mut bool, style: AccountHeaderStyle) -> bool {
+pub fn account_header<T: AsRef<str>>(ui: &Ui, name: T, warning: Option<&str>, expanded: &mut bool, style: AccountHeaderStyle) -> bool {
     let mut shown = true;
     match style {
-        AccountHeaderStyle::None => {}
-        AccountHeaderStyle::CenteredText => utils::centered_text(ui, name),
+        AccountHeaderStyle::None => {
+            if let Some(warning) = warning {
+                utils::warning_marker(ui, warning);
+            }
+        }
+        AccountHeaderStyle::CenteredText => {
+            if let Some(warning) = warning {
+                // The marker is placed after the name, so we center the text by pretending
+                // the name is only half a marker wider.
+                let marker_width = ui.calc_text_size("(!)")[0] + ui.clone_style().item_spacing[0];
+                let current_x = ui.cursor_pos()[0];
+                let text_width = ui.calc_text_size(&name)[0] + marker_width;
+                let column_width = ui.current_column_width();
+                let new_x = (current_x + column_width / 2. - text_width / 2.).max(current_x);
+                ui.set_cursor_pos([new_x, ui.cursor_pos()[1]]);
+                ui.text(name);
+                ui.same_line();
+                utils::warning_marker(ui, warning);
+            } else {
+                utils::centered_text(ui, name)
+            }
+        }
         AccountHeaderStyle::Collapsible => {
-            *expanded = CollapsingHeader::new(name).default_open(*expanded).build(ui);
+            let label = match warning {
+                Some(_) => format!("{} (!)##{}", name.as_ref(), name.as_ref()),
+                None => name.as_ref().to_string(),
+            };
+            *expanded = CollapsingHeader::new(label).default_open(*expanded).build(ui);
+            if let Some(warning) = warning {
+                if ui.is_item_hovered() {
+                    ui.tooltip_text(warning);
+                }
+            }
             shown = *expanded;
         }
     };
 
     shown
-}
\ No newline at end of file
+}
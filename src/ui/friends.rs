@@ -1,5 +1,4 @@
-use std::thread;
-use std::thread::sleep;
+use std::borrow::Cow;
 use std::time::{Duration, Instant};
 
 use arcdps::imgui::{Direction, MenuItem, MouseButton, StyleVar, TableFlags, Ui, Window};
@@ -7,10 +6,10 @@ use log::warn;
 
 
 use crate::{Data, urls};
-use crate::settings::{Friend, Settings};
+use crate::settings::{Friend, FriendSettings, Settings};
 use crate::translations::Translation;
 use crate::ui::{settings, UiState, utils};
-use crate::ui::clears::{clears_table, ClearTableEntry};
+use crate::ui::clears::{clears_table, copy_summary_menu, own_accounts_summary, ClearsTableKind, ClearTableEntry};
 use crate::ui::style::WARNING_RED;
 use crate::workers::{ApiJob, BackgroundWorkers};
 
@@ -32,23 +31,18 @@ pub fn friends(
         let enable_button_width = ui.text_line_height() * 25.0;
 
         if ui.button_with_size(tr.translate("friends-enable-button"), [enable_button_width, ui.text_line_height() * 2.0]) {
-            if ui_state.friends_window.enable_set_all_keys_public {
-                let sender = bg_workers.api_sender();
-                thread::spawn(move || {
-                    sleep(Duration::from_secs(60));
-                    // We are really hoping that all subtokens are uploaded
-                    // already so everything can be set properly.
-                    // We are also hoping the game is not closed before this finishes.
-
-                    // There currently isn't any good way to enqueue this so that it
-                    // only happens *after* the submission of all subtokens.
-
-                    sender.send(ApiJob::SetAllKeysPublicFriend { public: true });
-                });
+            settings.friends.enabled = true;
+            if let Err(_) = bg_workers.api_sender().send(ApiJob::UpdateFriendState) {
+                warn!("Failed to send request to API worker");
             }
 
-            settings.friends.enabled = true;
-            bg_workers.api_sender().send(ApiJob::UpdateFriendState);
+            if ui_state.friends_window.enable_set_all_keys_public {
+                // Kept in settings until done, the request is sent again on the next start if needed.
+                settings.friends.make_keys_public_pending = true;
+                if let Err(_) = bg_workers.api_sender().send_after(ApiJob::SetAllKeysPublicFriend { public: true }, ApiJob::is_friends_key_setup) {
+                    warn!("Failed to send request to API worker");
+                }
+            }
         }
 
         if let Some(text) = publicize_text {
@@ -76,12 +70,18 @@ pub fn friends(
             ui.text_colored(WARNING_RED, tr.translate("friends-no-connection-to-server"));
             refresh_button(ui, ui_state, bg_workers, tr);
         } else {
-            let mut entries: Vec<_> = settings.friends.list.friends_mut().iter_mut()
+            if !settings.friends.list.groups().is_empty() {
+                group_selector(ui, &mut settings.friends, tr);
+            }
+
+            let selected_group = settings.friends.selected_group.as_deref();
+            let mut entries: Vec<_> = settings.friends.list.friends_in_group_mut(selected_group)
                 .filter(|friend| friend.show_in_friends())
                 .filter(|friend| data.friends.state_available(friend.account_name()))
                 .map(|friend| ClearTableEntry {
                     account_name: friend.account_name().to_string(),
                     state: data.friends.finished_encounters(friend.account_name()),
+                    warning: None,
                     expanded: friend.expanded_in_friends_mut(),
                 })
                 .collect();
@@ -93,7 +93,7 @@ pub fn friends(
                     ui.text("");
                     wrap.pop(ui);
                 } else {
-                    clears_table(ui, raids, &mut entries, &settings.friends_clears_style, settings.short_names, tr, || {
+                    clears_table(ui, raids, ClearsTableKind::Raids, &mut entries, &settings.friends_clears_style, settings.short_names, tr, || {
                         utils::centered_text(ui, &tr.translate("friends-no-data-available"));
                         ui.text("");
 
@@ -152,6 +152,20 @@ pub fn friends(
                         }
                     }
                 });
+                if let Some(raids) = data.clears.raids() {
+                    // Own accounts are included so that the summary covers the whole group.
+                    let mut accounts = own_accounts_summary(data, settings, tr);
+                    let selected_group = settings.friends.selected_group.as_deref();
+                    for friend in settings.friends.list.friends().iter().filter(|friend| friend.show_in_friends()) {
+                        if selected_group.map(|group| friend.in_group(group)).unwrap_or(true) {
+                            if let Some(finished) = data.friends.finished_encounters(friend.account_name()) {
+                                accounts.push((friend.account_name().to_string(), finished));
+                            }
+                        }
+                    }
+                    copy_summary_menu(ui, raids, &accounts, tr);
+                }
+                settings::profiles_menu(ui, settings, tr);
                 ui.separator();
                 settings::style_section(ui, "friends-style-tooltip", &mut settings.friends_clears_style, tr);
             }
@@ -177,10 +191,11 @@ pub fn friends_window(
             .opened(&mut shown)
             .build(ui, || {
                 if settings.friends.list.friends().iter().any(|friend| data.friends.state_available(friend.account_name())) {
-                    if let Some(_t) = ui.begin_table_with_flags("FriendsTable", 5, TableFlags::BORDERS) {
+                    if let Some(_t) = ui.begin_table_with_flags("FriendsTable", 6, TableFlags::BORDERS) {
                         ui.table_setup_column("##updown");
                         ui.table_setup_column(&tr.translate("friends-friendlist-account-name"));
                         ui.table_setup_column(&tr.translate("friends-friendlist-shown"));
+                        ui.table_setup_column(&tr.translate("friends-friendlist-groups"));
                         ui.table_setup_column("##remove");
                         ui.table_setup_column("##status");
                         ui.table_headers_row();
@@ -192,6 +207,8 @@ pub fn friends_window(
                             .map(|friend| data.friends.state_available(friend.account_name()))
                             .collect();
 
+                        let groups = settings.friends.list.groups().clone();
+
                         for (i, friend) in settings.friends.list.friends_mut().iter_mut().enumerate() {
                             // Hide currently unavailable friends, but do not remove them
                             if !shown_indices[i] {
@@ -237,6 +254,9 @@ pub fn friends_window(
                                 ui.set_cursor_pos([new_x, ui.cursor_pos()[1]]);
                                 ui.checkbox(&format!("##friend_show_{}", friend.account_name()), friend.show_in_friends_mut());
 
+                                ui.table_next_column();
+                                friend_groups(ui, friend, &groups, tr);
+
                                 ui.table_next_column();
                                 if ui.button(format!("{}##friend_{}", tr.translate("friends-friendlist-remove"), friend.account_name())) {
                                     removal = Some(i);
@@ -299,12 +319,96 @@ pub fn friends_window(
                 if ui.button(tr.translate("friends-friendlist-button-add")) {
                     ui.open_popup("add-friend-popup")
                 }
+                ui.same_line();
+
+                ui.popup("manage-groups-popup", || {
+                    manage_groups(ui, ui_state, &mut settings.friends, tr);
+                });
+
+                if ui.button(tr.translate("friends-groups-button")) {
+                    ui.open_popup("manage-groups-popup")
+                }
             });
 
         ui_state.friends_window.shown = shown;
     }
 }
 
+fn group_selector(ui: &Ui, friend_settings: &mut FriendSettings, tr: &Translation) {
+    let groups: Vec<Option<&String>> = std::iter::once(None)
+        .chain(friend_settings.list.groups().iter().map(Some))
+        .collect();
+
+    // A group that does not exist anymore shows all friends.
+    let mut index = friend_settings.selected_group.as_ref()
+        .and_then(|selected| groups.iter().position(|group| *group == Some(selected)))
+        .unwrap_or(0);
+
+    let _width = ui.push_item_width(ui.current_font_size() * 12.0);
+    if ui.combo(tr.translate("friends-group-selector"), &mut index, &groups, |group| match group {
+        Some(group) => Cow::from(group.as_str()),
+        None => Cow::from(tr.translate("friends-group-all")),
+    }) {
+        friend_settings.selected_group = groups[index].cloned();
+    }
+}
+
+fn friend_groups(ui: &Ui, friend: &mut Friend, groups: &[String], tr: &Translation) {
+    let popup_id = format!("##friend_groups_popup_{}", friend.account_name());
+    let label = if friend.groups().is_empty() {
+        tr.translate("friends-friendlist-groups-none")
+    } else {
+        friend.groups().join(", ")
+    };
+
+    if ui.button(format!("{}##friend_groups_{}", label, friend.account_name())) {
+        ui.open_popup(&popup_id);
+    }
+    ui.popup(&popup_id, || {
+        if groups.is_empty() {
+            ui.text(tr.translate("friends-groups-none"));
+        }
+        for group in groups {
+            let mut in_group = friend.in_group(group);
+            if ui.checkbox(format!("{}##friend_group_{}", group, friend.account_name()), &mut in_group) {
+                friend.set_in_group(group, in_group);
+            }
+        }
+    });
+}
+
+fn manage_groups(ui: &Ui, ui_state: &mut UiState, friend_settings: &mut FriendSettings, tr: &Translation) {
+    let mut removal = None;
+    for group in friend_settings.list.groups() {
+        ui.text(group);
+        ui.same_line();
+        if ui.small_button(format!("{}##remove_group_{}", tr.translate("friends-groups-remove"), group)) {
+            removal = Some(group.clone());
+        }
+    }
+    if let Some(group) = removal {
+        if friend_settings.selected_group.as_ref() == Some(&group) {
+            friend_settings.selected_group = None;
+        }
+        friend_settings.list.remove_group(&group);
+    }
+
+    let mut add = ui.input_text(tr.translate("friends-groups-add-name"), &mut ui_state.friends_window.new_group_name)
+        .enter_returns_true(true)
+        .build();
+
+    add = add || ui.button(tr.translate("friends-groups-add"));
+
+    if add {
+        friend_settings.list.add_group(&ui_state.friends_window.new_group_name);
+        ui_state.friends_window.new_group_name.clear();
+    }
+    ui.same_line();
+    if ui.button(tr.translate("friends-friendlist-add-close")) {
+        ui.close_current_popup();
+    }
+}
+
 pub fn refresh_button(ui: &Ui, ui_state: &mut UiState, bg_workers: &BackgroundWorkers, tr: &Translation) {
     // We have a cooldown here to avoid spamming the request too much
     // and to make it feel like the button is doing something.
@@ -0,0 +1,99 @@
+use arcdps::imgui::{CollapsingHeader, TableBgTarget, TableFlags, Ui};
+use chrono::Utc;
+
+use crate::achievements;
+use crate::clears::{EncounterType, RaidWings};
+use crate::Data;
+use crate::settings::Settings;
+use crate::translations::Translation;
+use crate::ui::clears::ClearsTableKind;
+use crate::ui::utils;
+
+/// Only the most recent weeks are shown, older ones are kept in the file.
+const SHOWN_WEEKS: usize = 12;
+
+pub fn history(
+    ui: &Ui,
+    data: &Data,
+    settings: &Settings,
+    tr: &Translation,
+) {
+    let raids = match data.clears.raids() {
+        Some(raids) => raids,
+        None => {
+            ui.text(tr.translate("clears-no-public-data-yet"));
+            return;
+        }
+    };
+
+    let accounts = data.history.accounts();
+    if accounts.is_empty() {
+        let wrap = ui.push_text_wrap_pos_with_pos(ui.current_font_size() * 25.0);
+        ui.text_wrapped(&tr.translate("history-empty"));
+        wrap.pop(ui);
+        return;
+    }
+
+    // Streaks only count raid bosses, strike missions and challenge motes are optional.
+    let boss_ids: Vec<&str> = raids.wings().iter()
+        .filter(|wing| !achievements::is_achievement_group(wing))
+        .flat_map(|wing| wing.encounters())
+        .filter(|encounter| *encounter.encounter_type() == EncounterType::Boss)
+        .map(|encounter| encounter.id())
+        .collect();
+    let now = Utc::now();
+
+    for account_name in accounts {
+        let streak = data.history.streak(account_name, &boss_ids, now);
+        let label = format!(
+            "{} - {}{}{}##history_{}",
+            account_name,
+            tr.translate("history-streak-prefix"),
+            streak,
+            tr.translate("history-streak-suffix"),
+            account_name
+        );
+        if CollapsingHeader::new(&label).build(ui) {
+            history_table(ui, account_name, raids, data, settings, tr);
+        }
+    }
+}
+
+fn history_table(ui: &Ui, account_name: &str, raids: &RaidWings, data: &Data, settings: &Settings, tr: &Translation) {
+    let wings = raids.wings();
+    let style = &settings.my_clears_style;
+    if let Some(_t) = ui.begin_table_with_flags(format!("HistoryTable##{}", account_name), wings.len() + 1, TableFlags::BORDERS) {
+        ui.table_setup_column(tr.translate("history-week"));
+        for (index, wing) in wings.iter().enumerate() {
+            ui.table_setup_column(ClearsTableKind::Raids.group_name(index, wing, false, tr));
+        }
+        ui.table_headers_row();
+
+        let weeks = data.history.weeks().iter()
+            .filter(|week| week.finished_encounter_ids(account_name).is_some())
+            .take(SHOWN_WEEKS);
+
+        for week in weeks {
+            ui.table_next_row();
+            ui.table_next_column();
+            ui.text(week.start().format("%Y-%m-%d").to_string());
+
+            for wing in wings {
+                ui.table_next_column();
+                let total = wing.encounters().len();
+                let finished = wing.encounters().iter()
+                    .filter(|encounter| week.is_finished(account_name, encounter.id()))
+                    .count();
+
+                utils::centered_text(ui, format!("{}/{}", finished, total));
+
+                let bg_color = if finished == total {
+                    style.finished_clear_color
+                } else {
+                    style.unfinished_clear_color
+                };
+                ui.table_set_bg_color(TableBgTarget::CELL_BG, bg_color);
+            }
+        }
+    }
+}
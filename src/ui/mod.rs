@@ -1,7 +1,8 @@
 // Do not warn when using the `if let _token = ui.push_style() {}` pattern.
 #![allow(irrefutable_let_patterns)]
 
-use crate::settings::{ApiKey, Settings};
+use crate::clears::{ApiFailure, ApiFailureKind};
+use crate::settings::{ApiKey, ImportPreview, Settings};
 use crate::translations::Translation;
 use crate::updates::Release;
 use crate::workers::BackgroundWorkers;
@@ -18,6 +19,10 @@ mod utils;
 mod friends;
 mod style;
 mod about;
+mod worldbosses;
+mod squad;
+mod history;
+mod unlock;
 
 pub struct UiState {
     pub main_window: MainWindowState,
@@ -25,6 +30,8 @@ pub struct UiState {
     pub api_key_window: ApiKeyWindowState,
     pub friends_window: FriendsWindowState,
     pub about_window: AboutWindowState,
+    pub unlock_window: UnlockWindowState,
+    pub settings: SettingsState,
 }
 
 impl UiState {
@@ -46,11 +53,27 @@ impl UiState {
                 shown: false,
                 last_refresh_use: Instant::now(),
                 new_friend_name: String::new(),
+                new_group_name: String::new(),
                 enable_set_all_keys_public: true,
             },
             about_window: AboutWindowState {
                 shown: false
             },
+            // Shown on load if API keys are encrypted.
+            unlock_window: UnlockWindowState {
+                shown: true,
+                passphrase: String::new(),
+                error: None,
+                confirm_forget: false,
+            },
+            settings: SettingsState {
+                new_profile_name: String::new(),
+                pending_import: None,
+                import_error: None,
+                new_passphrase: String::new(),
+                new_passphrase_repeated: String::new(),
+                encryption_error: None,
+            },
         }
     }
 }
@@ -79,6 +102,7 @@ pub struct FriendsWindowState {
     pub shown: bool,
     pub last_refresh_use: Instant,
     pub new_friend_name: String,
+    pub new_group_name: String,
     pub enable_set_all_keys_public: bool,
 }
 
@@ -86,6 +110,23 @@ pub struct AboutWindowState {
     pub shown: bool,
 }
 
+pub struct UnlockWindowState {
+    pub shown: bool,
+    pub passphrase: String,
+    pub error: Option<String>,
+    pub confirm_forget: bool,
+}
+
+pub struct SettingsState {
+    pub new_profile_name: String,
+    /// Imported settings waiting for confirmation, along with the changes they would make.
+    pub pending_import: Option<(Settings, ImportPreview)>,
+    pub import_error: Option<String>,
+    pub new_passphrase: String,
+    pub new_passphrase_repeated: String,
+    pub encryption_error: Option<String>,
+}
+
 impl ApiKeyWindowState {
     pub fn is_key_selected(&self, key: &ApiKey) -> bool {
         if let SelectedApiKey::Id(uuid) = self.selected_key {
@@ -104,6 +145,17 @@ fn get_api_key_name(api_key: &ApiKey, tr: &Translation) -> String {
     }
 }
 
+fn api_failure_description(failure: &ApiFailure, tr: &Translation) -> String {
+    match failure.kind() {
+        ApiFailureKind::InvalidKey => tr.translate("api-failure-invalid-key"),
+        ApiFailureKind::InsufficientPermissions => tr.translate("api-failure-insufficient-permissions"),
+        ApiFailureKind::RateLimited => tr.translate("api-failure-rate-limited"),
+        ApiFailureKind::ApiUnavailable => tr.translate("api-failure-api-unavailable"),
+        ApiFailureKind::ConnectionFailed => tr.translate("api-failure-connection-failed"),
+        ApiFailureKind::Other => tr.translate("api-failure-other"),
+    }
+}
+
 pub fn draw_ui(
     ui: &Ui,
     ui_state: &mut UiState,
@@ -130,6 +182,16 @@ pub fn draw_ui(
                                 clears::my_clears(ui, ui_state, data, bg_workers, settings, tr);
                             });
 
+                        TabItem::new(&tr.translate("dungeons-tab-title"))
+                            .build(ui, || {
+                                clears::my_dungeons(ui, data, bg_workers, settings, tr);
+                            });
+
+                        TabItem::new(&tr.translate("world-bosses-tab-title"))
+                            .build(ui, || {
+                                worldbosses::world_bosses(ui, data, bg_workers, settings, tr);
+                            });
+
                         if !settings.feature_adverts.friends_shown {
                             if let _color = ui.push_style_color(StyleColor::Tab, [0.0, 0.5, 0.0, 1.0]) {
                                 TabItem::new(&tr.translate("friends-tab-title"))
@@ -143,6 +205,10 @@ pub fn draw_ui(
                                 .build(ui, || friends::friends(ui, ui_state, data, bg_workers, settings, tr));
 
                         }
+                        TabItem::new(&tr.translate("squad-tab-title"))
+                            .build(ui, || squad::squad(ui, data, bg_workers, settings, tr));
+                        TabItem::new(&tr.translate("history-tab-title"))
+                            .build(ui, || history::history(ui, data, settings, tr));
                         TabItem::new(&tr.translate("settings-tab-title"))
                             .build(ui, || settings::settings(ui, ui_state, settings, tr, false));
                 });
@@ -158,4 +224,6 @@ pub fn draw_ui(
     about::about_window(ui, ui_state, tr);
 
     apikeys::api_keys_window(ui, ui_state, data, bg_workers, settings, tr);
+
+    unlock::unlock_window(ui, ui_state, bg_workers, settings, tr);
 }
\ No newline at end of file
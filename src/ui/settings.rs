@@ -1,11 +1,13 @@
 use std::borrow::Cow;
 
-use arcdps::imgui::{CollapsingHeader, ColorEdit, ColorEditFlags, PopupModal, Slider, TableFlags, Ui};
+use arcdps::imgui::{CollapsingHeader, ColorEdit, ColorEditFlags, MenuItem, PopupModal, Slider, TableFlags, Ui};
+use log::{error, info};
 
 use crate::urls;
 use crate::settings::{AccountHeaderStyle, ClearsStyle, ClearsTableStyle, Settings};
 use crate::translations::Translation;
 use crate::ui::{UiState, utils};
+use crate::ui::style::WARNING_RED;
 use crate::ui::utils::url_button;
 
 pub fn settings(
@@ -96,6 +98,20 @@ pub fn settings(
         ui.align_text_to_frame_padding();
         utils::help_marker(ui, tr.translate("setting-keybind-window-apikeys-description"));
 
+        /* Keybind: Next profile */
+        utils::keybind_input(
+            ui,
+            "##NextProfileKeybindInput",
+            &mut settings.keybinds.next_profile,
+            tr,
+        );
+        ui.same_line();
+        ui.align_text_to_frame_padding();
+        ui.text(tr.translate("setting-keybind-next-profile"));
+        ui.same_line();
+        ui.align_text_to_frame_padding();
+        utils::help_marker(ui, tr.translate("setting-keybind-next-profile-description"));
+
         ui.separator();
         /* Close on escape */
         ui.checkbox(
@@ -121,6 +137,21 @@ pub fn settings(
         style_section(ui, "friends-style", &mut settings.friends_clears_style, tr);
     }
 
+    if CollapsingHeader::new(&tr.translate("settings-section-profiles"))
+        .build(ui) {
+        profiles_section(ui, ui_state, settings, tr);
+    }
+
+    if CollapsingHeader::new(&tr.translate("settings-section-key-encryption"))
+        .build(ui) {
+        key_encryption_section(ui, ui_state, settings, tr);
+    }
+
+    if CollapsingHeader::new(&tr.translate("settings-section-sharing"))
+        .build(ui) {
+        sharing_section(ui, ui_state, settings, tr);
+    }
+
     if CollapsingHeader::new(&tr.translate("settings-section-updates"))
         .build(ui) {
         ui.checkbox(
@@ -151,6 +182,192 @@ pub fn settings(
     }
 }
 
+fn profiles_section(ui: &Ui, ui_state: &mut UiState, settings: &mut Settings, tr: &Translation) {
+    let wrap = ui.push_text_wrap_pos_with_pos(ui.current_font_size() * 25.0);
+    ui.text_wrapped(tr.translate("setting-profiles-description"));
+    wrap.pop(ui);
+
+    if !settings.profiles.is_empty() {
+        if let Some(_t) = ui.begin_table_with_flags("ProfilesTable", 3, TableFlags::BORDERS | TableFlags::SIZING_FIXED_FIT) {
+            let names: Vec<String> = settings.profiles.iter().map(|x| x.name().to_string()).collect();
+            for (i, name) in names.iter().enumerate() {
+                ui.table_next_row();
+                ui.table_next_column();
+                if settings.active_profile.as_ref() == Some(name) {
+                    ui.text(format!("{} {}", name, tr.translate("setting-profile-active")));
+                } else {
+                    ui.text(name);
+                }
+                ui.table_next_column();
+                if ui.small_button(format!("{}##ProfileSwitch{}", tr.translate("setting-profile-switch"), i)) {
+                    settings.switch_profile(name);
+                }
+                ui.table_next_column();
+                if ui.small_button(format!("{}##ProfileRemove{}", tr.translate("setting-profile-remove"), i)) {
+                    settings.remove_profile(name);
+                }
+            }
+        }
+    }
+
+    ui.input_text("##NewProfileName", &mut ui_state.settings.new_profile_name)
+        .hint(tr.translate("setting-profile-name-hint"))
+        .build();
+    ui.same_line();
+    if ui.button(tr.translate("setting-profile-save")) && settings.save_profile(&ui_state.settings.new_profile_name) {
+        ui_state.settings.new_profile_name.clear();
+    }
+    ui.same_line();
+    utils::help_marker(ui, tr.translate("setting-profile-save-description"));
+}
+
+fn key_encryption_section(ui: &Ui, ui_state: &mut UiState, settings: &mut Settings, tr: &Translation) {
+    let wrap = ui.push_text_wrap_pos_with_pos(ui.current_font_size() * 25.0);
+    ui.text_wrapped(tr.translate("setting-key-encryption-description"));
+    wrap.pop(ui);
+
+    if settings.keys_locked() {
+        ui.text(tr.translate("setting-key-encryption-locked"));
+        if ui.button(tr.translate("api-key-unlock-button")) {
+            ui_state.unlock_window.shown = true;
+        }
+    } else if settings.key_encryption_enabled() {
+        ui.text(tr.translate("setting-key-encryption-enabled"));
+        if ui.button(tr.translate("setting-key-encryption-disable")) {
+            settings.disable_key_encryption();
+        }
+    } else {
+        let state = &mut ui_state.settings;
+        ui.input_text("##NewPassphrase", &mut state.new_passphrase)
+            .password(true)
+            .hint(tr.translate("setting-key-encryption-passphrase-hint"))
+            .build();
+        ui.input_text("##NewPassphraseRepeated", &mut state.new_passphrase_repeated)
+            .password(true)
+            .hint(tr.translate("setting-key-encryption-passphrase-repeat-hint"))
+            .build();
+        if ui.button(tr.translate("setting-key-encryption-enable")) {
+            if state.new_passphrase != state.new_passphrase_repeated {
+                state.encryption_error = Some(tr.translate("setting-key-encryption-mismatch"));
+            } else if let Err(e) = settings.enable_key_encryption(&state.new_passphrase) {
+                state.encryption_error = Some(e.to_string());
+            } else {
+                state.encryption_error = None;
+                state.new_passphrase.clear();
+                state.new_passphrase_repeated.clear();
+            }
+        }
+        if let Some(error) = &state.encryption_error {
+            ui.text_colored(WARNING_RED, error);
+        }
+    }
+}
+
+fn sharing_section(ui: &Ui, ui_state: &mut UiState, settings: &mut Settings, tr: &Translation) {
+    let wrap = ui.push_text_wrap_pos_with_pos(ui.current_font_size() * 25.0);
+    ui.text_wrapped(tr.translate("setting-share-description"));
+    wrap.pop(ui);
+
+    if ui.button(tr.translate("setting-share-copy")) {
+        match settings.export_shared() {
+            Ok(json) => ui.set_clipboard_text(json),
+            Err(e) => error!("Failed to export settings: {}", e),
+        }
+    }
+    ui.same_line();
+    if ui.button(tr.translate("setting-share-export-file")) {
+        match settings.export_shared_to_file(crate::SHARED_SETTINGS_FILENAME) {
+            Ok(_) => info!("Exported settings to {}", crate::SHARED_SETTINGS_FILENAME),
+            Err(e) => error!("Failed to export settings: {}", e),
+        }
+    }
+    if ui.is_item_hovered() {
+        ui.tooltip_text(format!("{}{}", tr.translate("setting-share-file-prefix"), crate::SHARED_SETTINGS_FILENAME));
+    }
+
+    let mut import_result = None;
+    if ui.button(tr.translate("setting-share-import-clipboard")) {
+        import_result = Some(Settings::from_json(&ui.clipboard_text().unwrap_or_default()));
+    }
+    ui.same_line();
+    if ui.button(tr.translate("setting-share-import-file")) {
+        import_result = Some(Settings::load_from_file(crate::SHARED_SETTINGS_FILENAME));
+    }
+    if ui.is_item_hovered() {
+        ui.tooltip_text(format!("{}{}", tr.translate("setting-share-file-prefix"), crate::SHARED_SETTINGS_FILENAME));
+    }
+
+    match import_result {
+        Some(Ok(imported)) => {
+            let preview = settings.import_preview(&imported);
+            ui_state.settings.pending_import = Some((imported, preview));
+            ui_state.settings.import_error = None;
+        }
+        Some(Err(e)) => {
+            error!("Failed to import settings: {}", e);
+            ui_state.settings.pending_import = None;
+            ui_state.settings.import_error = Some(e.to_string());
+        }
+        None => {}
+    }
+
+    if let Some(error) = &ui_state.settings.import_error {
+        ui.text_colored(WARNING_RED, format!("{}{}", tr.translate("setting-share-import-failed"), error));
+    }
+
+    let mut apply = false;
+    if let Some((_, preview)) = &ui_state.settings.pending_import {
+        ui.separator();
+        if preview.is_empty() {
+            ui.text(tr.translate("setting-share-import-no-changes"));
+        } else {
+            ui.text(tr.translate("setting-share-import-preview"));
+            if preview.style_changed {
+                ui.bullet_text(tr.translate("setting-share-import-style"));
+            }
+            if preview.keybinds_changed {
+                ui.bullet_text(tr.translate("setting-share-import-keybinds"));
+            }
+            if preview.behavior_changed {
+                ui.bullet_text(tr.translate("setting-share-import-behavior"));
+            }
+            if !preview.new_friends.is_empty() {
+                ui.bullet_text(format!("{}{}", tr.translate("setting-share-import-new-friends"), preview.new_friends.join(", ")));
+            }
+            if !preview.new_groups.is_empty() {
+                ui.bullet_text(format!("{}{}", tr.translate("setting-share-import-new-groups"), preview.new_groups.join(", ")));
+            }
+            apply = ui.button(tr.translate("setting-share-import-apply"));
+            ui.same_line();
+        }
+        if ui.button(tr.translate("setting-share-import-cancel")) {
+            ui_state.settings.pending_import = None;
+        }
+    }
+    if apply {
+        if let Some((imported, _)) = ui_state.settings.pending_import.take() {
+            settings.apply_import(imported);
+        }
+    }
+}
+
+/// A context menu for switching between profiles, hidden if there are none.
+pub fn profiles_menu(ui: &Ui, settings: &mut Settings, tr: &Translation) {
+    if settings.profiles.is_empty() {
+        return;
+    }
+    ui.menu(&tr.translate("contextmenu-profiles"), || {
+        let names: Vec<String> = settings.profiles.iter().map(|x| x.name().to_string()).collect();
+        for name in names {
+            if MenuItem::new(format!("{}##ProfilesContextItem", name))
+                .selected(settings.active_profile.as_ref() == Some(&name))
+                .build(ui) {
+                settings.switch_profile(&name);
+            }
+        }
+    });
+}
+
 pub fn common_style_section(ui: &Ui, settings: &mut Settings, tr: &Translation) {
     /* Short encounter names */
     ui.checkbox(
@@ -216,6 +433,7 @@ pub fn style_section(ui: &Ui, imgui_id_label: &str, style: &mut ClearsStyle, tr:
         ClearsTableStyle::WingRows,
         ClearsTableStyle::WingColumns,
         ClearsTableStyle::SingleRow,
+        ClearsTableStyle::Matrix,
     ];
 
     let mut table_style_index = table_styles.iter().position(|x| *x == style.table_style).unwrap_or_default();
@@ -225,6 +443,7 @@ pub fn style_section(ui: &Ui, imgui_id_label: &str, style: &mut ClearsStyle, tr:
             ClearsTableStyle::WingRows => tr.translate("setting-clears-style-option-rows"),
             ClearsTableStyle::WingColumns => tr.translate("setting-clears-style-option-columns"),
             ClearsTableStyle::SingleRow => tr.translate("setting-clears-style-option-single-row"),
+            ClearsTableStyle::Matrix => tr.translate("setting-clears-style-option-matrix"),
         }),
     ) {
         style.table_style = table_styles[table_style_index];
@@ -233,9 +452,19 @@ pub fn style_section(ui: &Ui, imgui_id_label: &str, style: &mut ClearsStyle, tr:
     ui.align_text_to_frame_padding();
     utils::help_marker(ui, tr.translate("setting-clears-style-description"));
 
+    /* Sorting of the matrix */
+    if matches!(style.table_style, ClearsTableStyle::Matrix) {
+        ui.checkbox(
+            format!("{}##{}", tr.translate("setting-clears-sort-matrix-by-needed"), imgui_id_label),
+            &mut style.sort_matrix_by_needed,
+        );
+        ui.same_line();
+        utils::help_marker(ui, tr.translate("setting-clears-sort-matrix-by-needed-description"));
+    }
+
     /* Account header styles */
-    // Hidden for single row layout as it's not affected.
-    if !matches!(style.table_style, ClearsTableStyle::SingleRow) {
+    // Hidden for single table layouts as they are not affected.
+    if !matches!(style.table_style, ClearsTableStyle::SingleRow | ClearsTableStyle::Matrix) {
         let account_header_styles = [
             AccountHeaderStyle::None,
             AccountHeaderStyle::CenteredText,
@@ -271,9 +500,9 @@ pub fn style_section(ui: &Ui, imgui_id_label: &str, style: &mut ClearsStyle, tr:
         tr.translate("setting-clears-show-table-headers-description"),
     );
 
-    /* Show table headers */
-    // Hidden for single row layout as it's not affected.
-    if !matches!(style.table_style, ClearsTableStyle::SingleRow) {
+    /* Show table row names */
+    // Hidden for single table layouts as they are not affected.
+    if !matches!(style.table_style, ClearsTableStyle::SingleRow | ClearsTableStyle::Matrix) {
         ui.checkbox(
             format!("{}##{}", tr.translate("setting-clears-show-table-row-names"), imgui_id_label),
             &mut style.show_clears_table_row_names,
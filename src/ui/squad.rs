@@ -0,0 +1,77 @@
+use arcdps::imgui::Ui;
+
+use crate::Data;
+use crate::settings::Settings;
+use crate::translations::Translation;
+use crate::ui::utils;
+use crate::ui::clears::{clears_table, ClearsTableKind, ClearTableEntry, next_refresh_text};
+use crate::workers::BackgroundWorkers;
+
+pub fn squad(
+    ui: &Ui,
+    data: &mut Data,
+    bg_workers: &BackgroundWorkers,
+    settings: &Settings,
+    tr: &Translation,
+) {
+    if !settings.friends.enabled {
+        let wrap = ui.push_text_wrap_pos_with_pos(ui.current_font_size() * 25.0);
+        ui.text_wrapped(&tr.translate("squad-friends-disabled"));
+        wrap.pop(ui);
+        return;
+    }
+
+    // We are always tracked, even outside of squads.
+    if data.squad.members().iter().all(|member| member.is_self()) {
+        ui.text(tr.translate("squad-empty"));
+        return;
+    }
+
+    let raids = match data.clears.raids() {
+        Some(raids) => raids,
+        None => {
+            ui.text(tr.translate("clears-no-public-data-yet"));
+            ui.text("");
+            next_refresh_text(ui, bg_workers, tr);
+            return;
+        }
+    };
+
+    let mut missing = Vec::new();
+    let mut entries = Vec::new();
+    for member in data.squad.members_mut() {
+        let state = if member.is_self() {
+            // Our own clears come from API keys, the friends server does not return them.
+            settings.api_keys.iter()
+                .find(|key| key.data().account_data().as_ref().map(|x| x.name()) == Some(member.account_name()))
+                .and_then(|key| data.clears.finished_encounters(key))
+        } else {
+            data.friends.finished_encounters(member.account_name())
+        };
+
+        match state {
+            Some(state) => entries.push(ClearTableEntry {
+                account_name: member.account_name().to_string(),
+                state: Some(state),
+                warning: None,
+                expanded: member.expanded_mut(),
+            }),
+            None => missing.push(member.account_name().to_string()),
+        }
+    }
+
+    if !entries.is_empty() {
+        clears_table(ui, raids, ClearsTableKind::Raids, &mut entries, &settings.friends_clears_style, settings.short_names, tr, || {
+            utils::centered_text(ui, &tr.translate("friends-no-data-available"));
+        });
+    }
+
+    if !missing.is_empty() {
+        ui.text(tr.translate("squad-members-without-data"));
+        ui.same_line();
+        utils::help_marker(ui, tr.translate("squad-members-without-data-description"));
+        let wrap = ui.push_text_wrap_pos_with_pos(ui.current_font_size() * 25.0);
+        ui.text_wrapped(missing.join(", "));
+        wrap.pop(ui);
+    }
+}
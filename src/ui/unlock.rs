@@ -0,0 +1,88 @@
+use arcdps::imgui::{Ui, Window};
+use log::warn;
+
+use crate::settings::Settings;
+use crate::translations::Translation;
+use crate::ui::{UiState, utils};
+use crate::ui::style::WARNING_RED;
+use crate::workers::{ApiJob, BackgroundWorkers};
+
+/// Asks for the passphrase of encrypted API keys, shown until keys are unlocked or the window is closed.
+pub fn unlock_window(ui: &Ui, ui_state: &mut UiState, bg_workers: &BackgroundWorkers, settings: &mut Settings, tr: &Translation) {
+    if ui_state.unlock_window.shown && settings.keys_locked() {
+        let mut shown = ui_state.unlock_window.shown;
+        Window::new(&tr.translate("unlock-window-title"))
+            .always_auto_resize(true)
+            .focus_on_appearing(true)
+            .no_nav()
+            .collapsible(false)
+            .opened(&mut shown)
+            .build(ui, || {
+                let wrap = ui.push_text_wrap_pos_with_pos(ui.current_font_size() * 25.0);
+                ui.text_wrapped(tr.translate("unlock-description"));
+                wrap.pop(ui);
+
+                let state = &mut ui_state.unlock_window;
+                let submitted = ui.input_text("##UnlockPassphrase", &mut state.passphrase)
+                    .password(true)
+                    .enter_returns_true(true)
+                    .hint(tr.translate("unlock-passphrase-hint"))
+                    .build();
+                ui.same_line();
+                if ui.button(tr.translate("unlock-button")) || submitted {
+                    match settings.unlock_keys(&state.passphrase) {
+                        Ok(_) => {
+                            state.error = None;
+                            // Keys were not available to the API worker until now.
+                            for key in settings.api_keys() {
+                                let jobs = [
+                                    ApiJob::UpdateAccountData(*key.id()),
+                                    ApiJob::UpdateTokenInfo(*key.id()),
+                                    ApiJob::UpdateClears(*key.id()),
+                                    ApiJob::UpdateDungeonPaths(*key.id()),
+                                    ApiJob::UpdateFinishedWorldBosses(*key.id()),
+                                ];
+                                for job in jobs.iter() {
+                                    if let Err(_) = bg_workers.api_sender().send(job.clone()) {
+                                        warn!("Failed to send request to API worker");
+                                    }
+                                }
+                            }
+                            if settings.friends.enabled && settings.friends.make_keys_public_pending {
+                                if let Err(_) = bg_workers.api_sender().send_after(ApiJob::SetAllKeysPublicFriend { public: true }, ApiJob::is_friends_key_setup) {
+                                    warn!("Failed to send request to API worker");
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            warn!("Failed to unlock API keys: {}", e);
+                            state.error = Some(tr.translate("unlock-failed"));
+                        }
+                    }
+                    state.passphrase.clear();
+                }
+                if let Some(error) = &state.error {
+                    ui.text_colored(WARNING_RED, error);
+                }
+
+                ui.separator();
+                ui.text(tr.translate("unlock-lost-passphrase"));
+                ui.same_line();
+                utils::help_marker(ui, tr.translate("unlock-lost-passphrase-description"));
+                if state.confirm_forget {
+                    ui.text_colored(WARNING_RED, tr.translate("unlock-forget-confirm"));
+                    if ui.button(tr.translate("unlock-forget-confirm-button")) {
+                        settings.forget_encrypted_keys();
+                        state.confirm_forget = false;
+                    }
+                    ui.same_line();
+                    if ui.button(tr.translate("unlock-forget-cancel-button")) {
+                        state.confirm_forget = false;
+                    }
+                } else if ui.button(tr.translate("unlock-forget-button")) {
+                    state.confirm_forget = true;
+                }
+            });
+        ui_state.unlock_window.shown = shown;
+    }
+}
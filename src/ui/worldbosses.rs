@@ -0,0 +1,85 @@
+use arcdps::imgui::{TableBgTarget, TableFlags, Ui};
+
+use crate::clears::FinishedEncountersStore;
+use crate::Data;
+use crate::settings::Settings;
+use crate::translations::{encounter_english_name, Translation};
+use crate::ui::{get_api_key_name, utils};
+use crate::ui::clears::next_refresh_text;
+use crate::workers::BackgroundWorkers;
+
+pub fn world_bosses(
+    ui: &Ui,
+    data: &Data,
+    bg_workers: &BackgroundWorkers,
+    settings: &Settings,
+    tr: &Translation,
+) {
+    if let Some(world_bosses) = data.clears.world_bosses() {
+        // Own accounts come first, followed by friends.
+        let mut accounts: Vec<(String, Option<&FinishedEncountersStore>)> = settings.api_keys.iter()
+            .filter(|key| key.show_key_in_clears())
+            .map(|key| (get_api_key_name(key, tr), data.clears.finished_world_bosses(key)))
+            .collect();
+
+        if settings.friends.enabled {
+            accounts.extend(settings.friends.list.friends().iter()
+                .filter(|friend| friend.show_in_friends())
+                .filter_map(|friend| {
+                    let finished = data.friends.finished_world_bosses(friend.account_name())?;
+                    Some((friend.account_name().to_string(), Some(finished)))
+                }));
+        }
+
+        if accounts.is_empty() {
+            if let _wrap = ui.push_text_wrap_pos_with_pos(ui.current_font_size() * 25.0) {
+                ui.text_wrapped(&tr.translate("clears-all-accounts-hidden"));
+            }
+            return;
+        }
+
+        let style = &settings.my_clears_style;
+        if let Some(_t) = ui.begin_table_with_flags(
+            "WorldBossesTable",
+            accounts.len() + 1,
+            TableFlags::BORDERS | TableFlags::NO_HOST_EXTEND_X | TableFlags::SIZING_FIXED_FIT,
+        ) {
+            ui.table_setup_column("");
+            for (name, _) in &accounts {
+                ui.table_setup_column(name);
+            }
+            if style.show_clears_table_headers {
+                ui.table_headers_row();
+            }
+
+            for boss in world_bosses.bosses() {
+                ui.table_next_row();
+                ui.table_next_column();
+                if settings.short_names {
+                    ui.text(tr.encounter_short_name_im_string(boss));
+                } else {
+                    ui.text(encounter_english_name(boss));
+                }
+
+                for (_, finished) in &accounts {
+                    ui.table_next_column();
+                    if let Some(finished) = finished {
+                        let bg_color = if finished.is_finished(boss) {
+                            style.finished_clear_color
+                        } else {
+                            style.unfinished_clear_color
+                        };
+                        ui.table_set_bg_color(TableBgTarget::CELL_BG, bg_color);
+                    } else {
+                        utils::centered_text(ui, &tr.translate("clears-compressed-layout-short-unknown"));
+                    }
+                }
+            }
+        }
+    } else {
+        ui.text(tr.translate("clears-no-public-data-yet"));
+        ui.text("");
+
+        next_refresh_text(ui, bg_workers, tr);
+    }
+}
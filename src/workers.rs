@@ -1,7 +1,5 @@
 use std::sync::{Arc, Mutex};
-use std::sync::mpsc::{Receiver, Sender};
-use std::sync::mpsc;
-use std::thread::sleep;
+use std::thread::JoinHandle;
 use std::thread;
 use std::time::{Duration, Instant};
 
@@ -10,14 +8,19 @@ use itertools::Itertools;
 use log::{error, warn};
 use uuid::Uuid;
 
-use crate::{Data, friends, Settings};
-use crate::api::{ApiError, Gw2Api, LiveApi};
-use crate::clears::RaidClearState;
-use crate::friends::{FriendRequestMetadata, FriendsApiClient, FriendsApiError};
+use crate::{achievements, Data, friends, ratelimit, Settings};
+use crate::achievements::AchievementProgress;
+use crate::api::Gw2Api;
+use crate::clears::{FinishedEncountersStore, KeyRequest, RaidClearState};
+use crate::friends::{FriendRequestMetadata, FriendsApi, FriendsApiError};
+use crate::jobqueue::{JobPriority, JobQueue, QueueClosed};
+use crate::shutdown::ShutdownSignal;
 
 pub struct BackgroundWorkers {
-    api_sender: Sender<ApiJob>,
+    api_queue: Arc<JobQueue<ApiJob>>,
     api_worker_next_wakeup: Arc<Mutex<Instant>>,
+    shutdown: Arc<ShutdownSignal>,
+    threads: Vec<JoinHandle<()>>,
 }
 
 impl BackgroundWorkers {
@@ -25,48 +28,142 @@ impl BackgroundWorkers {
         &self.api_worker_next_wakeup
     }
 
-    pub fn api_sender(&self) -> Sender<ApiJob> {
-        self.api_sender.clone()
+    /// A sender for jobs requested by the user, these are processed before background refreshes.
+    pub fn api_sender(&self) -> JobSender {
+        JobSender::new(self.api_queue.clone(), JobPriority::User)
+    }
+
+    /// A sender for jobs triggered automatically, these wait for jobs requested by the user.
+    pub fn background_api_sender(&self) -> JobSender {
+        JobSender::new(self.api_queue.clone(), JobPriority::Background)
+    }
+
+    /// Stops all background threads and waits for them to finish.
+    ///
+    /// Pending jobs are dropped, requests in progress stop waiting for the rate limiter
+    /// and no further requests of the current job are sent.
+    pub fn stop(mut self) {
+        self.stop_threads();
+    }
+
+    fn stop_threads(&mut self) {
+        self.shutdown.stop();
+        self.api_queue.close();
+        for thread in self.threads.drain(..) {
+            if let Err(_) = thread.join() {
+                error!("A background worker thread panicked.");
+            }
+        }
+    }
+}
+
+impl Drop for BackgroundWorkers {
+    fn drop(&mut self) {
+        self.stop_threads();
+    }
+}
+
+#[derive(Clone)]
+pub struct JobSender {
+    queue: Arc<JobQueue<ApiJob>>,
+    priority: JobPriority,
+}
+
+impl JobSender {
+    fn new(queue: Arc<JobQueue<ApiJob>>, priority: JobPriority) -> Self {
+        JobSender { queue, priority }
+    }
+
+    /// Enqueues a job, unless the same job is already waiting to be processed.
+    pub fn send(&self, job: ApiJob) -> Result<(), QueueClosed> {
+        self.queue.push(job, self.priority)
+    }
+
+    /// Enqueues a job once all jobs matching the prerequisite are processed,
+    /// including any jobs they request in turn.
+    pub fn send_after(&self, job: ApiJob, prerequisite: fn(&ApiJob) -> bool) -> Result<(), QueueClosed> {
+        self.queue.push_after(job, self.priority, prerequisite)
     }
 }
 
+#[derive(Clone, Eq, PartialEq, Hash)]
 pub enum ApiJob {
     UpdateRaids,
     UpdateClears(Uuid),
+    UpdateDungeons,
+    UpdateDungeonPaths(Uuid),
+    UpdateWorldBosses,
+    UpdateFinishedWorldBosses(Uuid),
     UpdateAccountData(Uuid),
     UpdateTokenInfo(Uuid),
     UploadFriendApiSubtoken { key_hash: String },
     UpdateFriendState,
     UpdateFriendClears { account_name: String, subtoken: String },
+    UpdateFriendWorldBosses { account_name: String, subtoken: String },
     ShareKeyWithFriend { key_uuid: Uuid, friend_account_name: String },
     UnshareKeyWithFriend { key_uuid: Uuid, friend_account_name: String },
     SetKeyPublicFriend { key_uuid: Uuid, public: bool, disabled: bool },
     SetAllKeysPublicFriend { public: bool },
 }
 
-pub fn start_workers(
+impl ApiJob {
+    /// Jobs that lead to subtokens of all keys being uploaded to the friends server.
+    pub fn is_friends_key_setup(&self) -> bool {
+        matches!(self, ApiJob::UpdateTokenInfo(_) | ApiJob::UpdateFriendState | ApiJob::UploadFriendApiSubtoken { .. })
+    }
+}
+
+pub fn start_workers<A: Gw2Api + Send + 'static, F: FriendsApi + Send + 'static>(
     data_mutex: &'static Mutex<Data>,
     settings_mutex: &'static Mutex<Option<Settings>>,
-    api: LiveApi,
-    friends_api: FriendsApiClient,
+    api: A,
+    friends_api: F,
+    shutdown: Arc<ShutdownSignal>,
 ) -> BackgroundWorkers {
     let api_next_wakeup = Arc::new(Mutex::new(Instant::now()));
     let api_next_wakeup_for_worker = api_next_wakeup.clone();
-    let (api_tx, api_rx): (Sender<ApiJob>, Receiver<ApiJob>) = mpsc::channel();
+    let api_queue = Arc::new(JobQueue::new());
 
     // Senders for each background thread (moved in to the thread)
-    let refresher_api_tx = api_tx.clone();
-    let friends_refresher_api_tx = api_tx.clone();
-    let self_api_tx = api_tx.clone();
+    let refresher_api_tx = JobSender::new(api_queue.clone(), JobPriority::Background);
+    let friends_refresher_api_tx = JobSender::new(api_queue.clone(), JobPriority::Background);
+    let self_api_tx = JobSender::new(api_queue.clone(), JobPriority::Background);
+    let api_rx = api_queue.clone();
+    let friends_refresher_shutdown = shutdown.clone();
+    let refresher_shutdown = shutdown.clone();
+    let consumer_shutdown = shutdown.clone();
+    let mut threads = Vec::new();
 
     // Friends refresher
-    thread::spawn(move || {
+    threads.push(thread::spawn(move || {
         let send_job = |job: ApiJob| {
             if let Err(_) = friends_refresher_api_tx.send(job) {
                 warn!("Failed to send request to API worker.");
             }
         };
 
+        // Making keys public may have been requested before the game was closed.
+        // Locked keys are not known yet, the job is requested again once they are unlocked.
+        let make_keys_public = settings_mutex.lock().unwrap().as_ref()
+            .map(|x| x.friends.enabled && x.friends.make_keys_public_pending && !x.keys_locked())
+            .unwrap_or(false);
+        if make_keys_public {
+            // Only keys with known token info are used with the friends server.
+            let keys_without_info: Vec<Uuid> = settings_mutex.lock().unwrap().as_ref()
+                .map(|x| x.api_keys().iter()
+                    .filter(|key| key.data().token_info().is_none())
+                    .map(|key| *key.id())
+                    .collect())
+                .unwrap_or_default();
+            for key_uuid in keys_without_info {
+                send_job(ApiJob::UpdateTokenInfo(key_uuid));
+            }
+            send_job(ApiJob::UpdateFriendState);
+            if let Err(_) = friends_refresher_api_tx.send_after(ApiJob::SetAllKeysPublicFriend { public: true }, ApiJob::is_friends_key_setup) {
+                warn!("Failed to send request to API worker.");
+            }
+        }
+
         loop {
             let enabled = settings_mutex.lock().unwrap().as_ref().unwrap().friends.enabled;
 
@@ -76,16 +173,7 @@ pub fn start_workers(
                 }
 
                 if let Some(state) = data_mutex.lock().unwrap().friends.api_state() {
-                    for key_state in state.keys() {
-                        let valid = key_state.subtoken_expires_at().map(|expiration| {
-                            // We want to submit a new subtoken if there's less than two months remaining.
-                            expiration - Utc::now() > chrono::Duration::days(60)
-                        }).unwrap_or(false);
-
-                        if !valid {
-                            send_job(ApiJob::UploadFriendApiSubtoken { key_hash: key_state.key_hash().to_string() });
-                        }
-                    }
+                    request_subtoken_uploads(state, &send_job);
 
                     let token_about_to_expire = state.friends().iter()
                         .any(|friend| {
@@ -103,293 +191,89 @@ pub fn start_workers(
                 }
             }
 
-            sleep(Duration::from_secs(10));
+            if friends_refresher_shutdown.wait(Duration::from_secs(10)) {
+                break;
+            }
         }
-    });
+    }));
 
     // API job consumer
-    thread::spawn(move || {
+    threads.push(thread::spawn(move || {
         let send_job = |job: ApiJob| {
             if let Err(_) = self_api_tx.send(job) {
                 warn!("Failed to send request to API worker.");
             }
         };
 
+        // Jobs that failed in a way that may be fixed by waiting, and will be retried later.
+        let mut delayed_jobs: Vec<DelayedJob> = Vec::new();
+
         loop {
-            // Note that we often copy strings from settings here to avoid locking settings
-            // for the duration of API requests.
-            match api_rx.recv().unwrap() {
-                ApiJob::UpdateRaids => {
-                    if let Ok(raids) = api.get_raids() {
-                        data_mutex.lock().unwrap().clears.set_raids(Some(raids))
-                    }
-                }
-                ApiJob::UpdateClears(key_uuid) => {
-                    let key: Option<String> = copy_api_key(settings_mutex, key_uuid);
-
-                    if let Some(key) = key {
-                        if let Ok(finished_encounters) = api.get_finished_encounters(&key) {
-                            if let Ok(last_modified) = api.get_account_last_modified(&key) {
-                                // TODO: Handle invalid API key explicitly
-                                let state = RaidClearState::new(finished_encounters, Utc::now(), last_modified);
-                                data_mutex.lock().unwrap().clears.set_state(key_uuid, Some(state));
-                            }
-                        }
-                    }
-                }
-                ApiJob::UpdateAccountData(key_uuid) => {
-                    let key: Option<String> = copy_api_key(settings_mutex, key_uuid);
-
-                    if let Some(key) = key {
-                        if let Ok(data) = api.get_account_data(&key) {
-                            // TODO: Handle invalid API key explicitly
-                            if let Some(key) = settings_mutex.lock().unwrap().as_mut().unwrap().get_key_mut(&key_uuid) {
-                                key.set_account_data(Some(data));
-                            }
-                        }
-                    }
+            if consumer_shutdown.is_stopped() {
+                break;
+            }
+
+            let now = Instant::now();
+            let (job, attempt) = if let Some(index) = delayed_jobs.iter().position(|x| x.retry_at <= now) {
+                let delayed = delayed_jobs.swap_remove(index);
+                (delayed.job, delayed.attempt)
+            } else if let Some(next_retry) = delayed_jobs.iter().map(|x| x.retry_at).min() {
+                match api_rx.pop_timeout(next_retry.saturating_duration_since(now)) {
+                    Ok(Some(job)) => (job, 0),
+                    Ok(None) => continue,
+                    Err(QueueClosed) => break,
                 }
-                ApiJob::UpdateTokenInfo(key_uuid) => {
-                    let key: Option<String> = copy_api_key(settings_mutex, key_uuid);
-
-                    if let Some(key) = key {
-                        if let Ok(info) = api.get_token_info(&key) {
-                            // TODO: Handle invalid API key explicitly
-                            if let Some(key) = settings_mutex.lock().unwrap().as_mut().unwrap().get_key_mut(&key_uuid) {
-                                key.set_token_info(Some(info));
-                                // We do request a friend state update in here and not in
-                                // account data as this is requested after account data
-                                // and we need both.
-                                send_job(ApiJob::UpdateFriendState);
-                            }
-                        }
-                    }
+            } else {
+                match api_rx.pop() {
+                    Some(job) => (job, 0),
+                    None => break,
                 }
-                ApiJob::UpdateFriendState => {
-                    let enabled = settings_mutex.lock().unwrap().as_ref().unwrap().friends.enabled;
-
-                    if enabled {
-                        let metadata = copy_friends_metadata(settings_mutex);
-                        if let Some(metadata) = metadata {
-                            match friends_api.get_state(metadata) {
-                                Ok(state) => {
-                                    // TODO: Deduplicate this fragment:
-                                    for friend in state.friends() {
-                                        if let Some(subtoken) = friend.subtoken() {
-                                            // TODO: are accounts deduplicated?
-                                            send_job(ApiJob::UpdateFriendClears {
-                                                account_name: friend.account().to_string(),
-                                                subtoken: subtoken.subtoken().to_string(),
-                                            });
-                                        }
-                                    }
-
-                                    data_mutex.lock().unwrap().friends.set_api_state(Some(state));
-                                }
-                                Err(FriendsApiError::UnknownError) => {
-                                    // TODO: Better logging
-                                    warn!("Friends - failed to get state - unknown error.")
-                                }
-                                Err(FriendsApiError::JsonDeserializationFailed(_)) => {
-                                    warn!("Friends - failed to get state - json deserialization failed.")
-                                }
-                                Err(FriendsApiError::UreqError(e)) => {
-                                    warn!("Friends - failed to get state: {}", e)
-                                }
-                            }
-                        }
-                    }
+            };
+
+            if attempt == 0 {
+                // A fresh request replaces a pending retry of the same job.
+                if let Some(index) = delayed_jobs.iter().position(|x| x.job == job) {
+                    let replaced = delayed_jobs.swap_remove(index);
+                    api_rx.finish(&replaced.job);
                 }
-                ApiJob::UploadFriendApiSubtoken { key_hash } => {
-                    // We need to find a key with the specified hash and make a copy to avoid
-                    // a lengthy lock on the settings mutex.
-                    let matching_key = settings_mutex.lock().unwrap().as_ref()
-                        .and_then(|x| x.api_keys().iter()
-                            .filter(|x| friends::key_hash(x.key()) == key_hash)
-                            .map(|x| x.key().to_string())
-                            .next()
-                        );
-
-                    if let Some(key) = matching_key {
-                        match api.create_subtoken(&key, &friends::SUBTOKEN_PERMISSIONS, &friends::SUBTOKEN_URLS, Utc::now() + chrono::Duration::days(365)) {
-                            Ok(subtoken) => {
-                                if let Some(metadata) = copy_friends_metadata(settings_mutex) {
-                                    match friends_api.add_subtoken(metadata, &key, subtoken) {
-                                        Ok(state) => {
-                                            data_mutex.lock().unwrap().friends.set_api_state(Some(state));
-                                        }
-                                        Err(FriendsApiError::UnknownError) => {
-                                            warn!("Friends - Failed to send subtoken to friend server.");
-                                        }
-                                        Err(FriendsApiError::JsonDeserializationFailed(e)) => {
-                                            warn!("Friends - Failed to send subtoken to friend server - json deserialization failed: {}.", e);
-                                        }
-                                        Err(FriendsApiError::UreqError(e)) => {
-                                            warn!("Friends - Failed to send subtoken to friend server - {}.", e);
-                                        }
-                                    }
-                                } else {
-                                    // Should not happen, failed to get keys from settings
-                                    error!("Friends - Failed to get keys from settings after we generated a friend subtoken.");
-                                }
-                            }
-                            Err(ApiError::UnknownError) => {
-                                warn!("Friends - Failed to get subtoken for from the GW2 API - unknown error.");
-                                // TODO: Add better logging
-                            }
-                            Err(ApiError::InvalidKey) => {
-                                warn!("Friends - Failed to get subtoken for from the GW2 API - invalid key.");
-                            }
-                            Err(ApiError::JsonDeserializationFailed(e)) => {
-                                warn!("Friends - Failed to get subtoken for from the GW2 API - json deserialization failed: {}", e);
-                            }
-                            Err(ApiError::TooManyRequests) => {
-                                warn!("Friends - Failed to get subtoken for from the GW2 API - too many requests, rate limited.");
-                            }
-                        }
+            }
+
+            match process_job(job.clone(), &api, &friends_api, data_mutex, settings_mutex, &consumer_shutdown, &send_job) {
+                JobResult::Done => api_rx.finish(&job),
+                JobResult::Retry { retry_after } => {
+                    if attempt + 1 >= MAX_JOB_ATTEMPTS {
+                        warn!("Giving up on an API job after {} attempts.", MAX_JOB_ATTEMPTS);
+                        api_rx.finish(&job);
                     } else {
-                        warn!("Friends - failed to find a key that is scheduled for a subtoken upload (was it removed in the meantime?).");
-                    }
-                }
-                ApiJob::UpdateFriendClears { account_name, subtoken } => {
-                    match api.get_finished_encounters(&subtoken) {
-                        Ok(finished_encounters) => {
-                            match api.get_account_last_modified(&subtoken) {
-                                Ok(last_modified) => {
-                                    let state = RaidClearState::new(finished_encounters, Utc::now(), last_modified);
-                                    data_mutex.lock().unwrap().friends.set_clears(account_name, state);
-                                }
-                                Err(err) => {
-                                    warn!("Failed to get last-modified for friend {} - {:?}.", account_name, err);
-                                }
-                            }
-                        }
-                        Err(ApiError::UnknownError) => {
-                            warn!("Failed to get clears for friend {} - unknown error.", account_name);
-                            // TODO: Add better logging
-                        }
-                        Err(ApiError::InvalidKey) => {
-                            warn!("Failed to get clears for friend {} - invalid key.", account_name);
-                        }
-                        Err(ApiError::JsonDeserializationFailed(e)) => {
-                            warn!("Failed to get clears for friend {} - json deserialization failed: {}", account_name, e);
-                        }
-                        Err(ApiError::TooManyRequests) => {
-                            warn!("Failed to get clears for friend {} - too many requests, rate limited.", account_name);
-                        }
-                    }
-                }
-                ApiJob::ShareKeyWithFriend { key_uuid, friend_account_name } => {
-                    let metadata = copy_friends_metadata(settings_mutex);
-                    let key: Option<String> = copy_api_key(settings_mutex, key_uuid);
-
-                    if let Some(metadata) = metadata {
-                        if let Some(key) = key {
-                            match friends_api.share(metadata, &key, friend_account_name) {
-                                Ok(state) => {
-                                    data_mutex.lock().unwrap().friends.set_api_state(Some(state));
-                                }
-                                // TODO: deduplicate this logging
-                                Err(FriendsApiError::UnknownError) => {
-                                    warn!("Friends - failed to share key - unknown error.")
-                                }
-                                Err(FriendsApiError::JsonDeserializationFailed(_)) => {
-                                    warn!("Friends - failed to share key - json deserialization failed.")
-                                }
-                                Err(FriendsApiError::UreqError(e)) => {
-                                    warn!("Friends - failed to share key: {}", e)
-                                }
-                            }
-                        }
-                    }
-                }
-                ApiJob::UnshareKeyWithFriend { key_uuid, friend_account_name } => {
-                    let metadata = copy_friends_metadata(settings_mutex);
-                    let key: Option<String> = copy_api_key(settings_mutex, key_uuid);
-
-                    if let Some(metadata) = metadata {
-                        if let Some(key) = key {
-                            match friends_api.unshare(metadata, &key, friend_account_name) {
-                                Ok(state) => {
-                                    data_mutex.lock().unwrap().friends.set_api_state(Some(state));
-                                }
-                                // TODO: deduplicate this logging
-                                Err(FriendsApiError::UnknownError) => {
-                                    warn!("Friends - failed to share key - unknown error.")
-                                }
-                                Err(FriendsApiError::JsonDeserializationFailed(_)) => {
-                                    warn!("Friends - failed to share key - json deserialization failed.")
-                                }
-                                Err(FriendsApiError::UreqError(e)) => {
-                                    warn!("Friends - failed to share key: {}", e)
-                                }
-                            }
-                        }
-                    }
-                }
-                ApiJob::SetKeyPublicFriend { key_uuid, public, disabled } => {
-                    let metadata = copy_friends_metadata(settings_mutex);
-                    let key: Option<String> = copy_api_key(settings_mutex, key_uuid);
-
-                    if let Some(metadata) = metadata {
-                        if let Some(key) = key {
-                            match friends_api.set_public(metadata, &key, public, disabled) {
-                                Ok(state) => {
-                                    data_mutex.lock().unwrap().friends.set_api_state(Some(state));
-                                }
-                                // TODO: deduplicate this logging
-                                Err(FriendsApiError::UnknownError) => {
-                                    warn!("Friends - failed to set key public status - unknown error.")
-                                }
-                                Err(FriendsApiError::JsonDeserializationFailed(_)) => {
-                                    warn!("Friends - failed to set key public status - json deserialization failed.")
-                                }
-                                Err(FriendsApiError::UreqError(e)) => {
-                                    warn!("Friends - failed to set key public status: {}", e)
-                                }
-                            }
-                        }
-                    }
-                }
-                ApiJob::SetAllKeysPublicFriend { public } => {
-                    let metadata = copy_friends_metadata(settings_mutex);
-
-                    if let Some(metadata) = metadata {
-                        for key in &metadata.api_keys {
-                            match friends_api.set_public(metadata.clone(), key, public, false) {
-                                Ok(state) => {
-                                    data_mutex.lock().unwrap().friends.set_api_state(Some(state));
-                                }
-                                // TODO: deduplicate this logging
-                                Err(FriendsApiError::UnknownError) => {
-                                    warn!("Friends - failed to set key public status - unknown error.")
-                                }
-                                Err(FriendsApiError::JsonDeserializationFailed(_)) => {
-                                    warn!("Friends - failed to set key public status - json deserialization failed.")
-                                }
-                                Err(FriendsApiError::UreqError(e)) => {
-                                    warn!("Friends - failed to set key public status: {}", e)
-                                }
-                            }
-                        }
+                        let delay = ratelimit::backoff_delay(attempt, retry_after);
+                        delayed_jobs.push(DelayedJob { job, attempt: attempt + 1, retry_at: Instant::now() + delay });
                     }
                 }
             }
         }
-    });
+    }));
 
     // Clears refresher
-    thread::spawn(move || {
+    threads.push(thread::spawn(move || {
         let send_job = |job: ApiJob| {
             if let Err(_) = refresher_api_tx.send(job) {
                 warn!("Failed to send request to API worker.");
             }
         };
 
+        // Raids may have been loaded from the cache, they are still refreshed once on start.
+        send_job(ApiJob::UpdateRaids);
+
         loop {
             if data_mutex.lock().unwrap().clears.raids().is_none() {
                 send_job(ApiJob::UpdateRaids);
             }
+            if data_mutex.lock().unwrap().clears.dungeons().is_none() {
+                send_job(ApiJob::UpdateDungeons);
+            }
+            if data_mutex.lock().unwrap().clears.world_bosses().is_none() {
+                send_job(ApiJob::UpdateWorldBosses);
+            }
 
             if let Some(settings) = settings_mutex.lock().unwrap().as_ref() {
                 for key in settings.api_keys() {
@@ -401,33 +285,452 @@ pub fn start_workers(
                     }
                     if key.show_key_in_clears() {
                         send_job(ApiJob::UpdateClears(*key.id()));
+                        send_job(ApiJob::UpdateDungeonPaths(*key.id()));
+                        send_job(ApiJob::UpdateFinishedWorldBosses(*key.id()));
                     }
                 }
             }
 
             if let Some(state) = data_mutex.lock().unwrap().friends.api_state() {
-                for friend in state.friends() {
-                    if let Some(subtoken) = friend.subtoken() {
-                        // TODO: are accounts deduplicated?
-                        send_job(ApiJob::UpdateFriendClears {
-                            account_name: friend.account().to_string(),
-                            subtoken: subtoken.subtoken().to_string(),
-                        });
-                    }
-                }
+                request_friend_data(state, &send_job);
             }
 
             let sleep_minutes = settings_mutex.lock().unwrap().as_ref().expect("Settings should be loaded by now").clears_check_interval_minutes;
 
             let sleep_duration = Duration::from_secs((60 * sleep_minutes) as u64);
             *api_next_wakeup_for_worker.lock().unwrap() = Instant::now() + sleep_duration;
-            sleep(sleep_duration);
+            if refresher_shutdown.wait(sleep_duration) {
+                break;
+            }
         }
-    });
+    }));
 
     BackgroundWorkers {
         api_worker_next_wakeup: api_next_wakeup,
-        api_sender: api_tx,
+        api_queue,
+        shutdown,
+        threads,
+    }
+}
+
+const MAX_JOB_ATTEMPTS: u32 = 6;
+
+struct DelayedJob {
+    job: ApiJob,
+    attempt: u32,
+    retry_at: Instant,
+}
+
+enum JobResult {
+    Done,
+    /// The job failed, but may succeed if it is tried again later.
+    Retry { retry_after: Option<Duration> },
+}
+
+impl JobResult {
+    fn retry_if(retryable: bool, retry_after: Option<Duration>) -> Self {
+        if retryable {
+            JobResult::Retry { retry_after }
+        } else {
+            JobResult::Done
+        }
+    }
+}
+
+fn process_job<A: Gw2Api, F: FriendsApi>(
+    job: ApiJob,
+    api: &A,
+    friends_api: &F,
+    data_mutex: &Mutex<Data>,
+    settings_mutex: &Mutex<Option<Settings>>,
+    shutdown: &ShutdownSignal,
+    send_job: &dyn Fn(ApiJob),
+) -> JobResult {
+    // Note that we often copy strings from settings here to avoid locking settings
+    // for the duration of API requests.
+    match job {
+        ApiJob::UpdateRaids => {
+            match api.get_raids() {
+                Ok(raids) => {
+                    data_mutex.lock().unwrap().clears.set_raids(Some(raids));
+                    JobResult::Done
+                }
+                Err(e) => {
+                    warn!("Failed to get raids - {}.", e);
+                    JobResult::retry_if(e.is_retryable(), e.retry_after())
+                }
+            }
+        }
+        ApiJob::UpdateClears(key_uuid) => {
+            let key: Option<String> = copy_api_key(settings_mutex, key_uuid);
+
+            if let Some(key) = key {
+                let result = api.get_finished_encounters(&key).and_then(|finished_encounters| {
+                    let progress = get_achievement_progress(api, &key);
+                    let finished_encounters = add_strike_missions(finished_encounters, progress.as_deref());
+                    let last_modified = api.get_account_last_modified(&key)?;
+                    Ok((RaidClearState::new(finished_encounters, Utc::now(), last_modified), progress))
+                });
+
+                match result {
+                    Ok((state, progress)) => {
+                        let account_name = settings_mutex.lock().unwrap().as_ref()
+                            .and_then(|x| x.api_keys().iter().find(|key| *key.id() == key_uuid))
+                            .and_then(|key| key.data().account_data().as_ref().map(|x| x.name().to_string()));
+
+                        let mut data = data_mutex.lock().unwrap();
+                        if let Some(account_name) = account_name {
+                            data.history.record(&account_name, state.last_api_update_time(), state.finished_encounters().finished_encounter_ids());
+                        }
+                        data.clears.set_state(key_uuid, Some(state));
+                        // Previously known challenge motes are kept if achievements are not available.
+                        if let Some(progress) = progress {
+                            data.clears.set_finished_challenge_motes(key_uuid, achievements::finished_challenge_mote_ids(&progress));
+                        }
+                        data.clears.record_key_success(key_uuid, KeyRequest::Clears);
+                    }
+                    Err(e) => {
+                        warn!("Failed to get clears for key {} - {}.", key_uuid, e);
+                        data_mutex.lock().unwrap().clears.record_key_failure(key_uuid, KeyRequest::Clears, &e);
+                        return JobResult::retry_if(e.is_retryable(), e.retry_after());
+                    }
+                }
+            }
+            JobResult::Done
+        }
+        ApiJob::UpdateDungeons => {
+            match api.get_dungeons() {
+                Ok(dungeons) => {
+                    data_mutex.lock().unwrap().clears.set_dungeons(Some(dungeons));
+                    JobResult::Done
+                }
+                Err(e) => {
+                    warn!("Failed to get dungeons - {}.", e);
+                    JobResult::retry_if(e.is_retryable(), e.retry_after())
+                }
+            }
+        }
+        ApiJob::UpdateDungeonPaths(key_uuid) => {
+            let key: Option<String> = copy_api_key(settings_mutex, key_uuid);
+
+            if let Some(key) = key {
+                let result = api.get_dungeon_paths(&key).and_then(|finished_paths| {
+                    let last_modified = api.get_account_last_modified(&key)?;
+                    Ok(RaidClearState::new(finished_paths, Utc::now(), last_modified))
+                });
+
+                match result {
+                    Ok(state) => {
+                        let mut data = data_mutex.lock().unwrap();
+                        data.clears.set_dungeon_state(key_uuid, Some(state));
+                        data.clears.record_key_success(key_uuid, KeyRequest::DungeonPaths);
+                    }
+                    Err(e) => {
+                        warn!("Failed to get dungeon paths for key {} - {}.", key_uuid, e);
+                        data_mutex.lock().unwrap().clears.record_key_failure(key_uuid, KeyRequest::DungeonPaths, &e);
+                        return JobResult::retry_if(e.is_retryable(), e.retry_after());
+                    }
+                }
+            }
+            JobResult::Done
+        }
+        ApiJob::UpdateWorldBosses => {
+            match api.get_world_bosses() {
+                Ok(world_bosses) => {
+                    data_mutex.lock().unwrap().clears.set_world_bosses(Some(world_bosses));
+                    JobResult::Done
+                }
+                Err(e) => {
+                    warn!("Failed to get world bosses - {}.", e);
+                    JobResult::retry_if(e.is_retryable(), e.retry_after())
+                }
+            }
+        }
+        ApiJob::UpdateFinishedWorldBosses(key_uuid) => {
+            let key: Option<String> = copy_api_key(settings_mutex, key_uuid);
+
+            if let Some(key) = key {
+                let result = api.get_finished_world_bosses(&key).and_then(|finished_bosses| {
+                    let last_modified = api.get_account_last_modified(&key)?;
+                    Ok(RaidClearState::new(finished_bosses, Utc::now(), last_modified))
+                });
+
+                match result {
+                    Ok(state) => {
+                        let mut data = data_mutex.lock().unwrap();
+                        data.clears.set_world_boss_state(key_uuid, Some(state));
+                        data.clears.record_key_success(key_uuid, KeyRequest::WorldBosses);
+                    }
+                    Err(e) => {
+                        warn!("Failed to get world bosses for key {} - {}.", key_uuid, e);
+                        data_mutex.lock().unwrap().clears.record_key_failure(key_uuid, KeyRequest::WorldBosses, &e);
+                        return JobResult::retry_if(e.is_retryable(), e.retry_after());
+                    }
+                }
+            }
+            JobResult::Done
+        }
+        ApiJob::UpdateAccountData(key_uuid) => {
+            let key: Option<String> = copy_api_key(settings_mutex, key_uuid);
+
+            if let Some(key) = key {
+                match api.get_account_data(&key) {
+                    Ok(data) => {
+                        if let Some(key) = settings_mutex.lock().unwrap().as_mut().unwrap().get_key_mut(&key_uuid) {
+                            key.set_account_data(Some(data));
+                        }
+                        data_mutex.lock().unwrap().clears.record_key_success(key_uuid, KeyRequest::AccountData);
+                    }
+                    Err(e) => {
+                        warn!("Failed to get account data for key {} - {}.", key_uuid, e);
+                        data_mutex.lock().unwrap().clears.record_key_failure(key_uuid, KeyRequest::AccountData, &e);
+                        return JobResult::retry_if(e.is_retryable(), e.retry_after());
+                    }
+                }
+            }
+            JobResult::Done
+        }
+        ApiJob::UpdateTokenInfo(key_uuid) => {
+            let key: Option<String> = copy_api_key(settings_mutex, key_uuid);
+
+            if let Some(key) = key {
+                match api.get_token_info(&key) {
+                    Ok(info) => {
+                        if let Some(key) = settings_mutex.lock().unwrap().as_mut().unwrap().get_key_mut(&key_uuid) {
+                            key.set_token_info(Some(info));
+                            // We do request a friend state update in here and not in
+                            // account data as this is requested after account data
+                            // and we need both.
+                            send_job(ApiJob::UpdateFriendState);
+                        }
+                        data_mutex.lock().unwrap().clears.record_key_success(key_uuid, KeyRequest::TokenInfo);
+                    }
+                    Err(e) => {
+                        warn!("Failed to get token info for key {} - {}.", key_uuid, e);
+                        data_mutex.lock().unwrap().clears.record_key_failure(key_uuid, KeyRequest::TokenInfo, &e);
+                        return JobResult::retry_if(e.is_retryable(), e.retry_after());
+                    }
+                }
+            }
+            JobResult::Done
+        }
+        ApiJob::UpdateFriendState => {
+            let enabled = settings_mutex.lock().unwrap().as_ref().unwrap().friends.enabled;
+
+            if enabled {
+                let metadata = copy_friends_metadata(settings_mutex, data_mutex);
+                if let Some(metadata) = metadata {
+                    match friends_api.get_state(metadata) {
+                        Ok(state) => {
+                            request_friend_data(&state, send_job);
+                            request_subtoken_uploads(&state, send_job);
+
+                            data_mutex.lock().unwrap().friends.set_api_state(Some(state));
+                        }
+                        Err(e) => {
+                            warn!("Friends - failed to get state - {}.", e);
+                            return JobResult::retry_if(e.is_retryable(), e.retry_after());
+                        }
+                    }
+                }
+            }
+            JobResult::Done
+        }
+        ApiJob::UploadFriendApiSubtoken { key_hash } => {
+            // We need to find a key with the specified hash and make a copy to avoid
+            // a lengthy lock on the settings mutex.
+            let matching_key = settings_mutex.lock().unwrap().as_ref()
+                .and_then(|x| x.api_keys().iter()
+                    .filter(|x| friends::key_hash(x.key()) == key_hash)
+                    .map(|x| x.key().to_string())
+                    .next()
+                );
+
+            if let Some(key) = matching_key {
+                match api.create_subtoken(&key, &friends::SUBTOKEN_PERMISSIONS, &friends::SUBTOKEN_URLS, Utc::now() + chrono::Duration::days(365)) {
+                    Ok(subtoken) => {
+                        if let Some(metadata) = copy_friends_metadata(settings_mutex, data_mutex) {
+                            let result = friends_api.add_subtoken(metadata, &key, subtoken);
+                            handle_friends_state_result(result, data_mutex, "send subtoken to friend server")
+                        } else {
+                            // Should not happen, failed to get keys from settings
+                            error!("Friends - Failed to get keys from settings after we generated a friend subtoken.");
+                            JobResult::Done
+                        }
+                    }
+                    Err(e) => {
+                        warn!("Friends - Failed to get subtoken from the GW2 API - {}.", e);
+                        JobResult::retry_if(e.is_retryable(), e.retry_after())
+                    }
+                }
+            } else {
+                warn!("Friends - failed to find a key that is scheduled for a subtoken upload (was it removed in the meantime?).");
+                JobResult::Done
+            }
+        }
+        ApiJob::UpdateFriendClears { account_name, subtoken } => {
+            let result = api.get_finished_encounters(&subtoken).and_then(|finished_encounters| {
+                let progress = get_achievement_progress(api, &subtoken);
+                let finished_encounters = add_strike_missions(finished_encounters, progress.as_deref());
+                let last_modified = api.get_account_last_modified(&subtoken)?;
+                Ok(RaidClearState::new(finished_encounters, Utc::now(), last_modified))
+            });
+
+            match result {
+                Ok(state) => {
+                    let mut data = data_mutex.lock().unwrap();
+                    data.history.record(&account_name, state.last_api_update_time(), state.finished_encounters().finished_encounter_ids());
+                    data.friends.set_clears(account_name, state);
+                    JobResult::Done
+                }
+                Err(e) => {
+                    warn!("Failed to get clears for friend {} - {}.", account_name, e);
+                    JobResult::retry_if(e.is_retryable(), e.retry_after())
+                }
+            }
+        }
+        ApiJob::UpdateFriendWorldBosses { account_name, subtoken } => {
+            let result = api.get_finished_world_bosses(&subtoken).and_then(|finished_bosses| {
+                let last_modified = api.get_account_last_modified(&subtoken)?;
+                Ok(RaidClearState::new(finished_bosses, Utc::now(), last_modified))
+            });
+
+            match result {
+                Ok(state) => {
+                    data_mutex.lock().unwrap().friends.set_world_bosses(account_name, state);
+                    JobResult::Done
+                }
+                Err(e) => {
+                    warn!("Failed to get world bosses for friend {} - {}.", account_name, e);
+                    JobResult::retry_if(e.is_retryable(), e.retry_after())
+                }
+            }
+        }
+        ApiJob::ShareKeyWithFriend { key_uuid, friend_account_name } => {
+            let metadata = copy_friends_metadata(settings_mutex, data_mutex);
+            let key: Option<String> = copy_api_key(settings_mutex, key_uuid);
+
+            if let (Some(metadata), Some(key)) = (metadata, key) {
+                let result = friends_api.share(metadata, &key, friend_account_name);
+                handle_friends_state_result(result, data_mutex, "share key")
+            } else {
+                JobResult::Done
+            }
+        }
+        ApiJob::UnshareKeyWithFriend { key_uuid, friend_account_name } => {
+            let metadata = copy_friends_metadata(settings_mutex, data_mutex);
+            let key: Option<String> = copy_api_key(settings_mutex, key_uuid);
+
+            if let (Some(metadata), Some(key)) = (metadata, key) {
+                let result = friends_api.unshare(metadata, &key, friend_account_name);
+                handle_friends_state_result(result, data_mutex, "unshare key")
+            } else {
+                JobResult::Done
+            }
+        }
+        ApiJob::SetKeyPublicFriend { key_uuid, public, disabled } => {
+            let metadata = copy_friends_metadata(settings_mutex, data_mutex);
+            let key: Option<String> = copy_api_key(settings_mutex, key_uuid);
+
+            if let (Some(metadata), Some(key)) = (metadata, key) {
+                let result = friends_api.set_public(metadata, &key, public, disabled);
+                handle_friends_state_result(result, data_mutex, "set key public status")
+            } else {
+                JobResult::Done
+            }
+        }
+        ApiJob::SetAllKeysPublicFriend { public } => {
+            if settings_mutex.lock().unwrap().as_ref().map(|x| x.keys_locked()).unwrap_or(false) {
+                // Only some keys are available, this is requested again after unlocking.
+                return JobResult::Done;
+            }
+            let metadata = copy_friends_metadata(settings_mutex, data_mutex);
+
+            // Without metadata, nothing was set and the pending status is kept for the next start.
+            let mut all_succeeded = metadata.is_some();
+            if let Some(metadata) = metadata {
+                for key in &metadata.api_keys {
+                    if shutdown.is_stopped() {
+                        return JobResult::Done;
+                    }
+                    let result = friends_api.set_public(metadata.clone(), key, public, false);
+                    all_succeeded &= result.is_ok();
+                    // Setting the status is idempotent, so we can safely retry the whole job.
+                    if let JobResult::Retry { retry_after } = handle_friends_state_result(result, data_mutex, "set key public status") {
+                        return JobResult::Retry { retry_after };
+                    }
+                }
+            }
+            if all_succeeded {
+                if let Some(settings) = settings_mutex.lock().unwrap().as_mut() {
+                    settings.friends.make_keys_public_pending = false;
+                }
+            }
+            JobResult::Done
+        }
+    }
+}
+
+/// Strike missions and challenge motes are not in the raid endpoint, we find them through achievements.
+/// If achievements cannot be retrieved, only the raid clears are available.
+fn get_achievement_progress<A: Gw2Api>(api: &A, api_key: &str) -> Option<Vec<AchievementProgress>> {
+    match api.get_achievement_progress(api_key, &achievements::tracked_achievement_ids()) {
+        Ok(progress) => Some(progress),
+        Err(e) => {
+            warn!("Failed to get achievement progress, only raid clears are available - {}.", e);
+            None
+        }
+    }
+}
+
+/// Only weekly strike missions are added, challenge motes are never reset and are kept separately.
+fn add_strike_missions(finished_encounters: FinishedEncountersStore, progress: Option<&[AchievementProgress]>) -> FinishedEncountersStore {
+    match progress {
+        Some(progress) => finished_encounters.with_additional_ids(achievements::finished_strike_mission_ids(progress)),
+        None => finished_encounters,
+    }
+}
+
+/// Requests updates of all data of friends that shared it with us.
+fn request_friend_data(state: &friends::State, send_job: &dyn Fn(ApiJob)) {
+    for friend in state.friends() {
+        if let Some(subtoken) = friend.subtoken() {
+            send_job(ApiJob::UpdateFriendClears {
+                account_name: friend.account().to_string(),
+                subtoken: subtoken.subtoken().to_string(),
+            });
+            send_job(ApiJob::UpdateFriendWorldBosses {
+                account_name: friend.account().to_string(),
+                subtoken: subtoken.subtoken().to_string(),
+            });
+        }
+    }
+}
+
+/// Requests uploads of new subtokens for keys that do not have a valid one on the friends server.
+fn request_subtoken_uploads(state: &friends::State, send_job: &dyn Fn(ApiJob)) {
+    for key_state in state.keys() {
+        let valid = key_state.subtoken_expires_at().map(|expiration| {
+            // We want to submit a new subtoken if there's less than two months remaining.
+            expiration - Utc::now() > chrono::Duration::days(60)
+        }).unwrap_or(false);
+
+        if !valid {
+            send_job(ApiJob::UploadFriendApiSubtoken { key_hash: key_state.key_hash().to_string() });
+        }
+    }
+}
+
+fn handle_friends_state_result(result: Result<friends::State, FriendsApiError>, data_mutex: &Mutex<Data>, action: &str) -> JobResult {
+    match result {
+        Ok(state) => {
+            data_mutex.lock().unwrap().friends.set_api_state(Some(state));
+            JobResult::Done
+        }
+        Err(e) => {
+            warn!("Friends - failed to {} - {}.", action, e);
+            JobResult::retry_if(e.is_retryable(), e.retry_after())
+        }
     }
 }
 
@@ -438,7 +741,7 @@ fn copy_api_key(settings_mutex: &Mutex<Option<Settings>>, key_uuid: Uuid) -> Opt
         .map(|x| x.key().to_string())
 }
 
-fn copy_friends_metadata(settings_mutex: &Mutex<Option<Settings>>) -> Option<FriendRequestMetadata> {
+fn copy_friends_metadata(settings_mutex: &Mutex<Option<Settings>>, data_mutex: &Mutex<Data>) -> Option<FriendRequestMetadata> {
     // Get api keys that are usable with friends, ignore others.
     // Also does deduplication.
     let api_keys = settings_mutex.lock().unwrap().as_ref()
@@ -449,12 +752,25 @@ fn copy_friends_metadata(settings_mutex: &Mutex<Option<Settings>>) -> Option<Fri
             .collect()
         );
 
-    let public_friends = settings_mutex.lock().unwrap().as_ref()
+    let mut public_friends: Option<Vec<String>> = settings_mutex.lock().unwrap().as_ref()
         .map(|x| x.friends.list.friends().iter()
             .map(|x| x.account_name().to_string())
             .collect()
         );
 
+    // Squad members are also requested to show their clears if they are public.
+    if let Some(public_friends) = public_friends.as_mut() {
+        let squad_members: Vec<String> = data_mutex.lock().unwrap().squad.members().iter()
+            .filter(|member| !member.is_self())
+            .map(|member| member.account_name().to_string())
+            .collect();
+        for account_name in squad_members {
+            if !public_friends.contains(&account_name) {
+                public_friends.push(account_name);
+            }
+        }
+    }
+
     if api_keys.is_none() || public_friends.is_none() {
         None
     } else {
@@ -463,4 +779,119 @@ fn copy_friends_metadata(settings_mutex: &Mutex<Option<Settings>>) -> Option<Fri
             public_friends: public_friends.unwrap(),
         })
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::LiveApi;
+    use crate::friends::FriendsApiClient;
+    use crate::ratelimit::RateLimiter;
+    use crate::settings::ApiKey;
+    use crate::testserver::{FakeAccount, TestServer, wait_until};
+
+    #[test]
+    fn workers_load_clears_from_server() {
+        let server = TestServer::start();
+        let mut account = FakeAccount::new("Test.1234");
+        account.finished_raids = vec!["gorseval".to_string()];
+        server.state().add_account("key", account);
+
+        let mut settings = Settings::default();
+        settings.api_keys.push(ApiKey::new("key"));
+        let key_id = *settings.api_keys[0].id();
+
+        let data_mutex: &'static Mutex<Data> = Box::leak(Box::new(Mutex::new(Data::new())));
+        let settings_mutex: &'static Mutex<Option<Settings>> = Box::leak(Box::new(Mutex::new(Some(settings))));
+        let shutdown = Arc::new(ShutdownSignal::new());
+        let rate_limiter = Arc::new(RateLimiter::new(1000, 1000.0).with_shutdown(shutdown.clone()));
+        let workers = start_workers(
+            data_mutex,
+            settings_mutex,
+            LiveApi::new(server.gw2_url(), rate_limiter.clone()),
+            FriendsApiClient::new(server.friends_url(), rate_limiter),
+            shutdown,
+        );
+
+        wait_until(|| {
+            let settings = settings_mutex.lock().unwrap();
+            let key = settings.as_ref().unwrap().get_key(&key_id).unwrap();
+            let data = data_mutex.lock().unwrap();
+            data.clears.raids().is_some() && data.clears.finished_encounters(key).is_some()
+        });
+        workers.stop();
+
+        let settings = settings_mutex.lock().unwrap();
+        let key = settings.as_ref().unwrap().get_key(&key_id).unwrap();
+        let gorseval = crate::clears::RaidEncounter::new("gorseval".to_string(), crate::clears::EncounterType::Boss);
+        assert!(data_mutex.lock().unwrap().clears.finished_encounters(key).unwrap().is_finished(&gorseval));
+    }
+
+    #[test]
+    fn raid_clears_are_kept_if_achievements_fail() {
+        let server = TestServer::start();
+        let mut account = FakeAccount::new("Test.1234");
+        account.finished_raids = vec!["gorseval".to_string()];
+        account.achievements_unavailable = true;
+        server.state().add_account("key", account);
+
+        let mut settings = Settings::default();
+        settings.api_keys.push(ApiKey::new("key"));
+        let key_id = *settings.api_keys[0].id();
+
+        let data_mutex: &'static Mutex<Data> = Box::leak(Box::new(Mutex::new(Data::new())));
+        let settings_mutex: &'static Mutex<Option<Settings>> = Box::leak(Box::new(Mutex::new(Some(settings))));
+        let shutdown = Arc::new(ShutdownSignal::new());
+        let rate_limiter = Arc::new(RateLimiter::new(1000, 1000.0).with_shutdown(shutdown.clone()));
+        let workers = start_workers(
+            data_mutex,
+            settings_mutex,
+            LiveApi::new(server.gw2_url(), rate_limiter.clone()),
+            FriendsApiClient::new(server.friends_url(), rate_limiter),
+            shutdown,
+        );
+
+        wait_until(|| {
+            let settings = settings_mutex.lock().unwrap();
+            let key = settings.as_ref().unwrap().get_key(&key_id).unwrap();
+            data_mutex.lock().unwrap().clears.finished_encounters(key).is_some()
+        });
+        workers.stop();
+
+        let settings = settings_mutex.lock().unwrap();
+        let key = settings.as_ref().unwrap().get_key(&key_id).unwrap();
+        let data = data_mutex.lock().unwrap();
+        let gorseval = crate::clears::RaidEncounter::new("gorseval".to_string(), crate::clears::EncounterType::Boss);
+        assert!(data.clears.finished_encounters(key).unwrap().is_finished(&gorseval));
+        assert!(data.clears.key_health(key).and_then(|health| health.current_failure()).is_none());
+    }
+
+    #[test]
+    fn pending_public_keys_are_set_after_subtoken_upload() {
+        let server = TestServer::start();
+        server.state().add_account("key", FakeAccount::new("Test.1234"));
+
+        let mut settings = Settings::default();
+        settings.api_keys.push(ApiKey::new("key"));
+        settings.friends.enabled = true;
+        settings.friends.make_keys_public_pending = true;
+
+        let data_mutex: &'static Mutex<Data> = Box::leak(Box::new(Mutex::new(Data::new())));
+        let settings_mutex: &'static Mutex<Option<Settings>> = Box::leak(Box::new(Mutex::new(Some(settings))));
+        let shutdown = Arc::new(ShutdownSignal::new());
+        let rate_limiter = Arc::new(RateLimiter::new(1000, 1000.0).with_shutdown(shutdown.clone()));
+        let workers = start_workers(
+            data_mutex,
+            settings_mutex,
+            LiveApi::new(server.gw2_url(), rate_limiter.clone()),
+            FriendsApiClient::new(server.friends_url(), rate_limiter),
+            shutdown,
+        );
+
+        wait_until(|| !settings_mutex.lock().unwrap().as_ref().unwrap().friends.make_keys_public_pending);
+        workers.stop();
+
+        assert!(server.state().friends_has_subtoken("key"));
+        assert!(server.state().friends_key_public("key"));
+    }
+}